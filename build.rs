@@ -0,0 +1,41 @@
+// Build script to assemble the internal registry from the TOML files in `registry/`.
+//
+// Each file holds the entries for one kind of extension (e.g., `registry/pecl.toml`
+// holds the `pecl:` entries). They are checked for syntax errors here, so that a broken
+// registry fails the build rather than the first `f1-ext-install` run, and combined into
+// a single document in `$OUT_DIR/registry.toml` that is embedded into the binary.
+
+use std::{env, fs, path::Path};
+use toml::value::{Table, Value};
+
+/// The registry files to combine, and the table each is stored under.
+const REGISTRY_FILES: &[(&str, &str)] = &[
+    ("builtin", "registry/builtin.toml"),
+    ("pecl", "registry/pecl.toml"),
+];
+
+fn main() {
+    let mut registry = Table::new();
+
+    for &(kind, path) in REGISTRY_FILES {
+        println!("cargo:rerun-if-changed={}", path);
+
+        let contents = fs::read_to_string(path)
+            .unwrap_or_else(|error| panic!("Failed to read {}: {}", path, error));
+
+        let entries: Table = toml::from_str(&contents)
+            .unwrap_or_else(|error| panic!("Failed to parse {}: {}", path, error));
+
+        for (name, entry) in &entries {
+            if !entry.is_table() {
+                panic!("{}: entry {} must be a table", path, name);
+            }
+        }
+
+        registry.insert(String::from(kind), Value::Table(entries));
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let output = toml::to_string(&Value::Table(registry)).unwrap();
+    fs::write(Path::new(&out_dir).join("registry.toml"), output).unwrap();
+}
@@ -0,0 +1,141 @@
+//! Colored diagnostics, following the `NO_COLOR` and `CLICOLOR` conventions.
+
+use snafu::Snafu;
+use std::{
+    env, fmt,
+    io::{self, IsTerminal as _},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Errors encountered while choosing when to use color.
+#[derive(Debug, Snafu)]
+pub enum ColorError {
+    /// The requested color setting is not supported.
+    #[snafu(display(
+        r#"Unknown color setting "{}" (expected "auto", "always", or "never")"#,
+        choice
+    ))]
+    UnknownChoice {
+        /// The setting that was given
+        choice: String,
+    },
+}
+
+/// When diagnostics are colored (`--color`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Colored when stderr is a terminal, unless `NO_COLOR` or `CLICOLOR=0` is set (or
+    /// whenever `CLICOLOR_FORCE` is set).
+    Auto,
+    /// Always colored.
+    Always,
+    /// Never colored.
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = ColorError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(ColorError::UnknownChoice {
+                choice: String::from(input),
+            }),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Decides whether to color output, given a way to read environment variables and
+    /// whether the output is a terminal.
+    fn decide(self, var: impl Fn(&str) -> Option<String>, terminal: bool) -> bool {
+        let set = |name| var(name).is_some_and(|value| !value.is_empty() && value != "0");
+
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto if var("NO_COLOR").is_some_and(|value| !value.is_empty()) => false,
+            Self::Auto if set("CLICOLOR_FORCE") => true,
+            Self::Auto if var("CLICOLOR").as_deref() == Some("0") => false,
+            Self::Auto => terminal,
+        }
+    }
+}
+
+/// Whether diagnostics are colored.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decides whether diagnostics on stderr are colored.
+pub fn init(choice: ColorChoice) {
+    let enabled = choice.decide(|name| env::var(name).ok(), io::stderr().is_terminal());
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// The styles diagnostics use.
+#[derive(Clone, Copy, Debug)]
+pub enum Style {
+    /// Bold red, for errors.
+    Error,
+    /// Bold yellow, for warnings.
+    Warning,
+}
+
+impl Style {
+    /// Returns the ANSI escape sequence that starts this style.
+    fn escape(self) -> &'static str {
+        match self {
+            Self::Error => "\x1b[1;31m",
+            Self::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+/// Text shown in a style, if diagnostics are colored.
+#[derive(Debug)]
+pub struct Painted<T>(Style, T);
+
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if ENABLED.load(Ordering::Relaxed) {
+            write!(f, "{}{}\x1b[0m", self.0.escape(), self.1)
+        } else {
+            write!(f, "{}", self.1)
+        }
+    }
+}
+
+/// Shows `text` in `style`, if diagnostics are colored.
+pub fn paint<T: fmt::Display>(style: Style, text: T) -> Painted<T> {
+    Painted(style, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decide() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| String::from(*value))
+            }
+        };
+
+        assert!(ColorChoice::Auto.decide(env(&[]), true));
+        assert!(!ColorChoice::Auto.decide(env(&[]), false));
+        assert!(!ColorChoice::Auto.decide(env(&[("NO_COLOR", "1")]), true));
+        assert!(ColorChoice::Auto.decide(env(&[("NO_COLOR", "")]), true));
+        assert!(!ColorChoice::Auto.decide(env(&[("CLICOLOR", "0")]), true));
+        assert!(ColorChoice::Auto.decide(env(&[("CLICOLOR_FORCE", "1")]), false));
+        assert!(ColorChoice::Always.decide(env(&[("NO_COLOR", "1")]), false));
+        assert!(!ColorChoice::Never.decide(env(&[]), true));
+
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! Exit codes that tell wrappers (like CI retry logic) which stage of an installation
+//! failed.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The stages of an installation, each with its own exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Anything not covered by another stage (exit code 1).
+    Other = 1,
+    /// Parsing the command line and extension specs (exit code 2).
+    Parse = 2,
+    /// Looking up registry data and ordering extensions (exit code 3).
+    Resolve = 3,
+    /// Installing or removing packages, which needs the network (exit code 4).
+    Packages = 4,
+    /// Configuring and compiling extensions (exit code 5).
+    Compile = 5,
+    /// Loading and configuring extensions: ini files and enabling modules (exit code 6).
+    Enable = 6,
+    /// Downloading PECL extensions' source, which needs the network (exit code 7).
+    Fetch = 7,
+}
+
+/// The current [`Stage`], as its exit code.
+static STAGE: AtomicU8 = AtomicU8::new(Stage::Other as u8);
+
+/// Records that the installation has entered `stage`, so that a failure exits with its
+/// code.
+pub fn set_stage(stage: Stage) {
+    STAGE.store(stage as u8, Ordering::Relaxed);
+}
+
+/// Returns the current stage.
+pub fn stage() -> Stage {
+    match STAGE.load(Ordering::Relaxed) {
+        2 => Stage::Parse,
+        3 => Stage::Resolve,
+        4 => Stage::Packages,
+        5 => Stage::Compile,
+        6 => Stage::Enable,
+        7 => Stage::Fetch,
+        _ => Stage::Other,
+    }
+}
+
+impl Stage {
+    /// Returns the process exit code for a failure in this stage.
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
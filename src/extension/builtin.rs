@@ -1,23 +1,172 @@
 //! Type and helpers for PHP builtin extensions.
 
-use lazy_static::lazy_static;
-use maplit::btreemap;
 use regex::Regex;
-use serde::Deserialize;
-use std::{collections::BTreeMap, str::FromStr};
-
-use super::ParseError;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr, sync::LazyLock};
+
+use super::{
+    feature::{self, Feature},
+    modifier::{self, sapi_ini_dir},
+    preset::{self, Preset},
+    registry::{self, DataSource, Registry},
+    variant::{self, Condition},
+    ParseError, BUILTIN_TAG,
+};
+use crate::system::Platform;
+
+/// Overrides applied to a builtin's data when the platform matches a condition.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BuiltinVariant {
+    /// The platform requirements for this variant to apply.
+    #[serde(flatten)]
+    when: Condition,
+    /// Replacement list of external packages, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    packages: Option<Vec<String>>,
+    /// Replacement list of runtime packages, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    runtime_packages: Option<Vec<String>>,
+    /// Replacement list of extra package repositories, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repositories: Option<Vec<String>>,
+    /// Replacement list of required extensions, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requires: Option<Vec<String>>,
+    /// Replacement ini settings, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ini: Option<BTreeMap<String, String>>,
+    /// Replacement source patches, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patches: Option<Vec<String>>,
+    /// Replacement `docker-php-ext-configure` arguments, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    configure_cmd: Option<Vec<String>>,
+    /// The extension installed instead of this builtin, if this variant changes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replaced_by: Option<String>,
+    /// Rejects keys that aren't fields of this table (see [`variant::deny_unknown`]).
+    #[serde(flatten, deserialize_with = "variant::deny_unknown", skip_serializing)]
+    _unknown: (),
+}
 
 /// Represents the data for a PHP builtin extension.
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct BuiltinData {
     /// The list of external packages (if any) this extension needs.
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     packages: Option<Vec<String>>,
+    /// The packages this extension needs at run time, if known. When every extension
+    /// being installed declares these, the `scanelf` pass is skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    runtime_packages: Option<Vec<String>>,
+    /// Extra package repositories (URLs) to search when installing this extension's
+    /// packages, for packages the base image's release no longer carries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repositories: Option<Vec<String>>,
     /// Represents the arguments to pass to `docker-php-ext-configure`, if that utility
     /// needs to be called.
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     configure_cmd: Option<Vec<String>>,
+    /// Extensions (in `builtin:<name>` or `pecl:<name>` syntax) that must be installed
+    /// before this one. Missing requirements are added to the install automatically.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requires: Option<Vec<String>>,
+    /// Default ini settings for this extension, written to its own file in `conf.d` when
+    /// the extension is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ini: Option<BTreeMap<String, String>>,
+    /// Patches applied to the extension's source before it is configured. Each entry is
+    /// either a path to a patch file or the text of a diff, applied with `patch -p1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patches: Option<Vec<String>>,
+    /// An extension (in `pecl:<name>[@version]` syntax) to install instead of this builtin,
+    /// for builtins that were moved out of PHP's source tree. Usually set by a variant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replaced_by: Option<String>,
+    /// Features enabled unless the spec disables them (e.g., `builtin:ldap?no-sasl`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    default_features: Vec<String>,
+    /// Platform-specific overrides, applied in order. Later matching variants take
+    /// precedence over earlier ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    variants: Vec<BuiltinVariant>,
+    /// Optional features (requested as `builtin:<name>?with=<feature>`), each a list of
+    /// additions applied on matching platforms.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    features: BTreeMap<String, Vec<Feature>>,
+
+    /// Named sets of ini settings (requested as `builtin:<name>?preset=<preset>`), each a
+    /// list of settings applied on matching platforms.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    presets: BTreeMap<String, Vec<Preset>>,
+}
+
+impl BuiltinData {
+    /// Returns a copy of this data with every variant matching `platform` applied.
+    fn resolve(&self, platform: &Platform) -> Self {
+        let mut resolved = self.clone();
+
+        for variant in self.variants.iter().filter(|v| v.when.matches(platform)) {
+            if let Some(packages) = &variant.packages {
+                resolved.packages = Some(packages.clone());
+            }
+
+            if let Some(runtime_packages) = &variant.runtime_packages {
+                resolved.runtime_packages = Some(runtime_packages.clone());
+            }
+
+            if let Some(repositories) = &variant.repositories {
+                resolved.repositories = Some(repositories.clone());
+            }
+
+            if let Some(requires) = &variant.requires {
+                resolved.requires = Some(requires.clone());
+            }
+
+            if let Some(ini) = &variant.ini {
+                resolved.ini = Some(ini.clone());
+            }
+
+            if let Some(patches) = &variant.patches {
+                resolved.patches = Some(patches.clone());
+            }
+
+            if let Some(replaced_by) = &variant.replaced_by {
+                resolved.replaced_by = Some(replaced_by.clone());
+            }
+
+            if let Some(configure_cmd) = &variant.configure_cmd {
+                resolved.configure_cmd = Some(configure_cmd.clone());
+            }
+        }
+
+        if let Some(repositories) = &mut resolved.repositories {
+            for repository in repositories.iter_mut() {
+                *repository = platform.expand(repository);
+            }
+        }
+
+        resolved.variants.clear();
+        resolved
+    }
+
+    /// Appends the additions from each requested feature that apply to `platform`.
+    fn apply_features(&mut self, features: &[String], platform: &Platform) {
+        let available = std::mem::take(&mut self.features);
+
+        let additions = features
+            .iter()
+            .filter_map(|name| available.get(name))
+            .flatten()
+            .filter(|addition| addition.matches(platform));
+
+        for addition in additions {
+            feature::append(&mut self.packages, &addition.packages);
+            feature::append_runtime(&mut self.runtime_packages, &addition.runtime_packages);
+            feature::append(&mut self.requires, &addition.requires);
+            feature::append(&mut self.configure_cmd, &addition.configure_cmd);
+        }
+    }
 }
 
 /// Represents the information needed for a PHP builtin extension.
@@ -26,8 +175,34 @@ pub struct Builtin {
     /// The name of this extension, as used by the `docker-php-ext-install` utility.
     name: String,
 
+    /// The optional features requested for this builtin.
+    features: Vec<String>,
+
+    /// The name of the ini file that loads this builtin, if the spec chose one.
+    ini_name: Option<String>,
+
+    /// The SAPIs to load this builtin for, or empty to load it for all of them.
+    sapis: Vec<String>,
+
+    /// The ini directives requested for this builtin by the user, which take
+    /// precedence over the registry's defaults.
+    settings: BTreeMap<String, String>,
+
+    /// The presets requested for this installation.
+    presets: Vec<String>,
+
+    /// The environments (see `--env`) this extension is limited to, if any.
+    envs: Vec<String>,
+
+    /// The modifiers as given in the spec (the text after `?`), which are passed on to
+    /// the extension that replaces this builtin, if any.
+    modifiers: Option<String>,
+
     /// The data for this builtin.
     data: BuiltinData,
+
+    /// Where the data was found.
+    source: DataSource,
 }
 
 impl Builtin {
@@ -36,208 +211,190 @@ impl Builtin {
         &self.name
     }
 
+    /// Returns the key identifying this builtin (e.g., `builtin:gd`).
+    pub fn key(&self) -> String {
+        format!("{}{}", BUILTIN_TAG, self.name)
+    }
+
+    /// Returns the optional features requested for this builtin.
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// Returns where this builtin's registry data was found.
+    pub fn source(&self) -> DataSource {
+        self.source
+    }
+
+    /// Returns the name of the ini file that should load this builtin, if it differs from
+    /// the `docker-php-ext-<name>.ini` written by `docker-php-ext-install`.
+    pub fn ini_name(&self) -> Option<&str> {
+        self.ini_name.as_deref()
+    }
+
+    /// Returns the directories (relative to `$PHP_INI_DIR`) that should load this builtin,
+    /// or an empty list if it is loaded from the shared `conf.d`.
+    pub fn ini_dirs(&self) -> Vec<String> {
+        self.sapis.iter().map(|sapi| sapi_ini_dir(sapi)).collect()
+    }
+
     /// Returns the list of external packages (if any) needed by this builtin.
     pub fn packages(&self) -> Option<&Vec<String>> {
         self.data.packages.as_ref()
     }
 
+    /// Returns the list of runtime packages, if the registry declares them.
+    pub fn runtime_packages(&self) -> Option<&Vec<String>> {
+        self.data.runtime_packages.as_ref()
+    }
+
+    /// Returns the extra package repositories needed by this extension, if any.
+    pub fn repositories(&self) -> Option<&Vec<String>> {
+        self.data.repositories.as_ref()
+    }
+
+    /// Returns the extensions that must be installed before this one.
+    pub fn requires(&self) -> Option<&Vec<String>> {
+        self.data.requires.as_ref()
+    }
+
+    /// Returns the default ini settings for this extension, if any.
+    pub fn ini(&self) -> Option<&BTreeMap<String, String>> {
+        self.data.ini.as_ref()
+    }
+
+    /// Returns the ini directives requested by the user for this builtin.
+    pub fn settings(&self) -> &BTreeMap<String, String> {
+        &self.settings
+    }
+
+    /// Retrieves the environments this extension is limited to, or an empty list if it is
+    /// installed in all of them.
+    pub fn envs(&self) -> &[String] {
+        &self.envs
+    }
+
+    /// Adds an ini directive requested by the user (e.g., with `--ini`).
+    pub fn add_setting(&mut self, key: String, value: String) {
+        self.settings.insert(key, value);
+    }
+
+    /// Returns the patches to apply to this extension's source, if any.
+    pub fn patches(&self) -> Option<&Vec<String>> {
+        self.data.patches.as_ref()
+    }
+
+    // Allow overriding the requirements for unit testing
+    #[cfg(test)]
+    pub fn set_requires(&mut self, requires: Vec<String>) {
+        self.data.requires = Some(requires);
+    }
+
     /// Returns the configure command (if any) needed by this builtin.
     pub fn configure_cmd(&self) -> Option<&Vec<String>> {
         self.data.configure_cmd.as_ref()
     }
-}
 
-lazy_static! {
-    // NB. A few extensions are indicated in comments but not explicitly listed:
-    // - A "no need" comment just means that there are no external dependencies
-    //   for the extension, so we let BuiltinData::default() handle it.
-    // - An "already loaded" comment means that for php:7.4-cli-alpine, the test
-    //   extension_loaded("<name>") returns true, and we assume we don't need to add it.
-    // - A "TODO" comment indicates that we can add the extension, but there may not be a
-    //   need, so we've avoided adding it to the built-in registry for now.
-    static ref REGISTRY: BTreeMap<&'static str, BuiltinData> = btreemap! {
-        // bcmath: no need
-
-        "bz2" => BuiltinData {
-            packages: Some(vec![
-                String::from("bzip2-dev"),
-            ]),
-            configure_cmd: Some(vec![
-                String::from("--with-bz2")
-            ]),
-        },
-
-        // calendar: no need
-
-        // ctype: already loaded
-        // curl: already loaded
-        // dom: already loaded
-
-        "enchant" => BuiltinData {
-            packages: Some(vec![
-                String::from("enchant-dev")
-            ]),
-            configure_cmd: Some(vec![
-                String::from("--with-enchant"),
-            ]),
-        },
-
-        // exif: no need
-
-        // fileinfo: already loaded
-        // filter: already loaded
-        // ftp: already loaded
-
-        "gd" => BuiltinData {
-            packages: Some(vec![
-                String::from("coreutils"),
-                String::from("freetype-dev"),
-                String::from("libjpeg-turbo-dev"),
-            ]),
-            configure_cmd: Some(vec![
-                // Skip option checking while we still support pre-7.4 PHPs - this is a
-                // pretty bad idea in general, but since we're applying it only to the GD
-                // extension in particular, we should be relatively safe.
-                String::from("--disable-option-checking"),
-
-                // Configuration for PHP >= 7.4: these options tell configure to use
-                // pkg-config to find the needed compiler flags
-                String::from("--with-freetype"),
-                String::from("--with-jpeg"),
-                String::from("--with-png"),
-
-                // Configuration for PHP < 7.3 needs the --with-foo-dir options instead,
-                // which looks looks for files starting with this prefix (e.g., passing
-                // --with-foo-dir=/usr looks for foo.h inside of /usr/include, and so on.)
-                String::from("--with-freetype-dir=/usr"),
-                String::from("--with-jpeg-dir=/usr"),
-                String::from("--with-png-dir=/usr"),
-            ]),
-        },
-
-        "gettext" => BuiltinData {
-            packages: Some(vec![
-                String::from("gettext"),
-                String::from("gettext-dev"),
-            ]),
-            configure_cmd: Some(vec![
-                String::from("--with-gettext")
-            ]),
-        },
-
-        "gmp" => BuiltinData {
-            packages: Some(vec![
-                String::from("gmp-dev"),
-            ]),
-            configure_cmd: Some(vec![
-                String::from("--with-gmp")
-            ]),
-        },
-
-        // iconv: already loaded
-
-        "imap" => BuiltinData {
-            packages: Some(vec![
-                String::from("imap-dev"),
-                String::from("openssl-dev"),
-            ]),
-            configure_cmd: Some(vec![
-                String::from("--with-imap"),
-                String::from("--with-imap-ssl"),
-            ]),
-        },
-
-        "intl" => BuiltinData {
-            packages: Some(vec![
-                String::from("icu-dev"),
-            ]),
-            ..BuiltinData::default()
-        },
-
-        // json: already loaded
-
-        "ldap" => BuiltinData {
-            packages: Some(vec![
-                String::from("openldap-dev")
-            ]),
-            configure_cmd: Some(vec![
-                String::from("--with-ldap"),
-                String::from("--with-ldap-sasl"),
-            ]),
-        },
-
-        // mbstring: already loaded
-        // mysqli: no need
-        // mysqlnd: no need
-        // opcache: no need
-        // pcntl: no need
-        // phar: no need
-        // pdo: already loaded
-        // pdo_mysql: no need
-        // pdo_pgsql: TODO
-        // posix: already loaded
-        // pspell: TODO
-        // session: already loaded
-        // simplexml: already loaded
-
-        "soap" => BuiltinData {
-            packages: Some(vec![String::from("libxml2-dev")]),
-            ..BuiltinData::default()
-        },
-
-        // sodium: already loaded
-        // sqlite3: already loaded
-        // tokenizer: already loaded
-        // xml: already loaded
-        // xmlreader: already loaded
-        // xmlrpc: TODO
-        // xmlwriter: already loaded
-        // xsl: TODO
-
-        "zip" => BuiltinData {
-            packages: Some(vec![String::from("libzip-dev")]),
-            ..BuiltinData::default()
-        },
-    };
+    /// Returns the extension to install instead of this builtin, if any.
+    pub fn replaced_by(&self) -> Option<&str> {
+        self.data.replaced_by.as_deref()
+    }
+
+    /// Returns the modifiers given in this builtin's spec (the text after `?`), if any.
+    pub fn modifiers(&self) -> Option<&str> {
+        self.modifiers.as_deref()
+    }
+
+    /// Selects the variants of this builtin's data that apply to `platform`, then adds
+    /// the requested features.
+    pub fn resolve(&mut self, platform: &Platform) {
+        self.data = self.data.resolve(platform);
+        self.data.apply_features(&self.features, platform);
+
+        let presets = std::mem::take(&mut self.data.presets);
+        preset::apply(&presets, &self.presets, platform, &mut self.settings);
+    }
 }
 
-/// Finds a builtin extensoin's data from either the internal registry or the environment.
-/// If neither attempt succeeds, returns empty builtin data.
-fn find_builtin_data(name: &str) -> BuiltinData {
-    if let Some(found) = REGISTRY.get(name) {
-        return found.clone();
+/// Finds a builtin extension's data from either the supplemental registry, the internal
+/// registry, or the environment, along with where it was found. If no attempt succeeds,
+/// returns empty builtin data.
+pub(super) fn find_builtin_data(name: &str) -> (BuiltinData, DataSource) {
+    if let Some(found) = Registry::supplemental().and_then(|registry| registry.builtin.get(name)) {
+        return (found.clone(), DataSource::Supplemental);
+    }
+
+    if let Some(found) = Registry::internal().builtin.get(name) {
+        return (found.clone(), DataSource::Internal);
     }
 
     let prefix = format!("F1_BUILTIN_{}_", name.to_ascii_uppercase());
+    let source = if registry::has_env_prefix(&prefix) {
+        DataSource::Environment
+    } else {
+        DataSource::Unlisted
+    };
 
     if let Ok(data) = envy::prefixed(prefix).from_env() {
-        return data;
-    };
+        return (data, source);
+    }
 
-    BuiltinData::default()
+    (BuiltinData::default(), source)
 }
 
 impl FromStr for Builtin {
     type Err = ParseError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        lazy_static! {
-            static ref BUILTIN: Regex = Regex::new(r"^[_a-zA-Z0-9]+$").unwrap();
-        }
-
-        if !BUILTIN.is_match(input) {
-            return Err(ParseError::InvalidSyntax);
-        }
+        static BUILTIN: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+                r#"(?x)
+                ^
+                (?P<name>[_a-zA-Z0-9]+)
+                (?:\?(?P<modifiers>[^\s?]+))?
+                $
+                "#,
+            )
+            .unwrap()
+        });
+
+        let caps = match BUILTIN.captures(input) {
+            Some(caps) => caps,
+            None => return Err(ParseError::InvalidSyntax),
+        };
+
+        let name = &caps["name"];
+        let (data, source) = find_builtin_data(name);
+
+        let given = caps.name("modifiers").map(|cap| cap.as_str());
+        let modifiers = modifier::parse_modifiers(
+            name,
+            &data.features,
+            &data.default_features,
+            &data.presets,
+            given,
+        )?;
 
         Ok(Builtin {
-            name: String::from(input),
-            data: find_builtin_data(input),
+            name: String::from(name),
+            features: modifiers.features,
+            ini_name: modifiers.ini_name,
+            sapis: modifiers.sapis,
+            settings: modifiers.settings,
+            presets: modifiers.presets,
+            envs: modifiers.envs,
+            modifiers: given.map(String::from),
+            source,
+            data,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use cool_asserts::assert_matches;
+    use semver::Version;
+
     use super::*;
 
     #[test]
@@ -257,4 +414,157 @@ mod tests {
         let pdo_mysql: Builtin = "pdo_mysql".parse().unwrap();
         assert_eq!(pdo_mysql.name, "pdo_mysql");
     }
+
+    #[test]
+    fn test_intl_full_icu() {
+        let alpine = Platform::default().with_alpine_version(Some(Version::new(3, 18, 0)));
+
+        let mut intl: Builtin = "intl?with=full-icu".parse().unwrap();
+        assert_eq!(intl.name(), "intl");
+        intl.resolve(&alpine);
+
+        assert!(intl
+            .packages()
+            .unwrap()
+            .contains(&String::from("icu-data-full")));
+        assert!(intl
+            .runtime_packages()
+            .unwrap()
+            .contains(&String::from("icu-data-full")));
+    }
+
+    #[test]
+    fn test_gd_features() {
+        let php74 = Platform::new(Some(Version::new(7, 4, 33)));
+        let php81 = Platform::new(Some(Version::new(8, 1, 0)));
+
+        let mut gd: Builtin = "gd?with=webp,avif".parse().unwrap();
+        gd.resolve(&php74);
+        let configure_cmd = gd.configure_cmd().unwrap();
+        assert!(configure_cmd.contains(&String::from("--with-webp")));
+        assert!(
+            !configure_cmd.contains(&String::from("--with-avif")),
+            "PHP 7.4 doesn't support AVIF"
+        );
+
+        let mut gd: Builtin = "gd?with=webp,avif".parse().unwrap();
+        gd.resolve(&php81);
+        let configure_cmd = gd.configure_cmd().unwrap();
+        assert!(configure_cmd.contains(&String::from("--with-avif")));
+        assert!(gd
+            .packages()
+            .unwrap()
+            .contains(&String::from("libavif-dev")));
+    }
+
+    #[test]
+    fn test_unknown_feature() {
+        assert_matches!(
+            "intl?with=bogus".parse::<Builtin>(),
+            Err(ParseError::UnknownFeature { feature, .. }) => {
+                assert_eq!(feature, "bogus");
+            }
+        );
+    }
+
+    #[test]
+    fn test_opcache_production_preset() {
+        let php74 = Platform::new(Some(Version::new(7, 4, 33)));
+        let php81 = Platform::new(Some(Version::new(8, 1, 0)));
+
+        let mut opcache: Builtin = "opcache?preset=production".parse().unwrap();
+        opcache.resolve(&php74);
+        assert_eq!(opcache.settings()["opcache.validate_timestamps"], "0");
+        assert!(!opcache.settings().contains_key("opcache.jit"));
+
+        let mut opcache: Builtin = "opcache?preset=production&ini=opcache.jit=off"
+            .parse()
+            .unwrap();
+        opcache.resolve(&php81);
+        assert_eq!(opcache.settings()["opcache.jit_buffer_size"], "64M");
+        assert_eq!(
+            opcache.settings()["opcache.jit"],
+            "off",
+            "explicit settings override the preset"
+        );
+
+        assert_matches!(
+            "opcache?preset=bogus".parse::<Builtin>(),
+            Err(ParseError::UnknownPreset { preset, .. }) => {
+                assert_eq!(preset, "bogus");
+            }
+        );
+    }
+
+    #[test]
+    fn test_enchant_variants() {
+        let php74 = Platform::new(Some(Version::new(7, 4, 33)))
+            .with_alpine_version(Some(Version::new(3, 16, 0)));
+        let php80 = Platform::new(Some(Version::new(8, 0, 30)))
+            .with_alpine_version(Some(Version::new(3, 16, 0)));
+
+        let mut enchant: Builtin = "enchant".parse().unwrap();
+        enchant.resolve(&php74);
+        assert_eq!(
+            enchant.packages().unwrap(),
+            &vec![String::from("enchant-dev@v312")]
+        );
+        assert!(
+            enchant.repositories().is_some(),
+            "PHP 7 on newer Alpine needs enchant 1.x from an older release"
+        );
+
+        let mut enchant: Builtin = "enchant".parse().unwrap();
+        enchant.resolve(&php80);
+        assert_eq!(
+            enchant.packages().unwrap(),
+            &vec![String::from("enchant2-dev")]
+        );
+        assert!(enchant.repositories().is_none());
+    }
+
+    #[test]
+    fn test_ldap_no_sasl() {
+        let mut ldap: Builtin = "ldap".parse().unwrap();
+        ldap.resolve(&Platform::default());
+        assert!(ldap
+            .configure_cmd()
+            .unwrap()
+            .contains(&String::from("--with-ldap-sasl")));
+
+        let mut ldap: Builtin = "ldap?no-sasl".parse().unwrap();
+        ldap.resolve(&Platform::default());
+        assert_eq!(
+            ldap.configure_cmd().unwrap(),
+            &vec![String::from("--with-ldap")]
+        );
+        assert!(!ldap
+            .packages()
+            .unwrap()
+            .contains(&String::from("cyrus-sasl-dev")));
+    }
+
+    #[test]
+    fn test_gd_variants() {
+        let php73 = Platform::new(Some(Version::new(7, 3, 33)));
+        let php74 = Platform::new(Some(Version::new(7, 4, 33)));
+
+        let mut gd: Builtin = "gd".parse().unwrap();
+        gd.resolve(&php73);
+        assert!(
+            gd.configure_cmd()
+                .unwrap()
+                .contains(&String::from("--with-jpeg-dir=/usr")),
+            "gd on PHP 7.3 should use --with-jpeg-dir"
+        );
+
+        let mut gd: Builtin = "gd".parse().unwrap();
+        gd.resolve(&php74);
+        assert!(
+            gd.configure_cmd()
+                .unwrap()
+                .contains(&String::from("--with-jpeg")),
+            "gd on PHP 7.4 should use --with-jpeg"
+        );
+    }
 }
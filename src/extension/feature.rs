@@ -0,0 +1,157 @@
+//! Optional features that can be requested for an extension (e.g., `builtin:gd?with=webp`).
+//!
+//! Features are selected by modifiers after a `?` in the extension's spec (see
+//! [`super::modifier`]):
+//!
+//! * `with=<a>,<b>` enables the named features
+//! * `without=<a>,<b>` disables the named features, including those enabled by default
+//! * `no-<a>` is shorthand for `without=<a>`
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::{
+    variant::{self, Condition},
+    ParseError,
+};
+use crate::system::Platform;
+
+/// Additions made to an extension's data when a feature is requested and the platform
+/// matches a condition.
+///
+/// Unlike a variant, which replaces fields, a feature appends to them, so that several
+/// features can be combined.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Feature {
+    /// The platform requirements for these additions to apply.
+    #[serde(flatten)]
+    when: Condition,
+    /// Additional external packages needed to build the feature.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) packages: Vec<String>,
+    /// Additional packages needed by the feature at run time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) runtime_packages: Vec<String>,
+    /// Additional extensions to install first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) requires: Vec<String>,
+    /// Additional `docker-php-ext-configure` arguments (builtins only).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) configure_cmd: Vec<String>,
+    /// Additional answers to `pecl install` prompts (PECL extensions only).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) configure_options: Vec<String>,
+    /// Rejects keys that aren't fields of this table (see [`variant::deny_unknown`]).
+    #[serde(flatten, deserialize_with = "variant::deny_unknown", skip_serializing)]
+    _unknown: (),
+}
+
+impl Feature {
+    /// Determines if these additions apply to `platform`.
+    pub fn matches(&self, platform: &Platform) -> bool {
+        self.when.matches(platform)
+    }
+}
+
+/// Appends `additions` to `target`, creating the list if needed.
+pub fn append(target: &mut Option<Vec<String>>, additions: &[String]) {
+    if !additions.is_empty() {
+        target
+            .get_or_insert_with(Vec::new)
+            .extend(additions.iter().cloned());
+    }
+}
+
+/// Appends runtime packages to `target`, but only if it already declares some, since an
+/// undeclared list means they're discovered by scanning binaries instead.
+pub fn append_runtime(target: &mut Option<Vec<String>>, additions: &[String]) {
+    if let Some(target) = target {
+        target.extend(additions.iter().cloned());
+    }
+}
+
+/// Determines the features to enable for `extension`, starting from its `defaults` and
+/// applying the spec's feature `modifiers` in order.
+///
+/// Every feature named in a modifier must be one of the `available` features.
+pub fn select_features(
+    extension: &str,
+    available: &BTreeMap<String, Vec<Feature>>,
+    defaults: &[String],
+    modifiers: &[&str],
+) -> Result<Vec<String>, ParseError> {
+    let mut selected = defaults.to_vec();
+
+    for modifier in modifiers {
+        let (enable, names) = if let Some(names) = modifier.strip_prefix("with=") {
+            (true, names)
+        } else if let Some(names) = modifier.strip_prefix("without=") {
+            (false, names)
+        } else if let Some(name) = modifier.strip_prefix("no-") {
+            (false, name)
+        } else {
+            return Err(ParseError::InvalidSyntax);
+        };
+
+        for name in names.split(',') {
+            if !available.contains_key(name) {
+                return Err(ParseError::UnknownFeature {
+                    extension: String::from(extension),
+                    feature: String::from(name),
+                });
+            }
+
+            if !enable {
+                selected.retain(|feature| feature != name);
+            } else if !selected.iter().any(|feature| feature == name) {
+                selected.push(String::from(name));
+            }
+        }
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+
+    /// Builds a feature table with empty features of the given names.
+    fn available(names: &[&str]) -> BTreeMap<String, Vec<Feature>> {
+        names
+            .iter()
+            .map(|name| (String::from(*name), Vec::new()))
+            .collect()
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let available = available(&["sasl", "webp", "avif"]);
+        let defaults = vec![String::from("sasl")];
+
+        let selected = select_features("x", &available, &defaults, &[]).unwrap();
+        assert_eq!(selected, vec!["sasl"]);
+
+        let selected = select_features("x", &available, &defaults, &["no-sasl"]).unwrap();
+        assert!(selected.is_empty());
+
+        let selected = select_features(
+            "x",
+            &available,
+            &defaults,
+            &["with=webp,avif", "without=sasl"],
+        )
+        .unwrap();
+        assert_eq!(selected, vec!["webp", "avif"]);
+    }
+
+    #[test]
+    fn test_unknown_modifier() {
+        assert_matches!(
+            select_features("x", &available(&[]), &[], &["bogus"]),
+            Err(ParseError::InvalidSyntax)
+        );
+    }
+}
@@ -1,17 +1,29 @@
 //! Helper types to handle PHP dependencies.
 //!
 //! A dependency is broken down into two categories: builtins and PECL. The structs in
-//! this module exist to capture the information needed to configure and install them.
+//! this module exist to capture the information needed to configure and install them. A
+//! `config:` spec names an extension of either kind that is already present, for which
+//! only the ini settings are written.
 
 use snafu::Snafu;
-use std::str::FromStr;
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::system::Platform;
 
 mod builtin;
+mod feature;
+mod modifier;
+mod order;
 mod pecl;
+mod preset;
+mod registry;
+mod variant;
 mod version;
 
 pub use builtin::Builtin;
-pub use pecl::Pecl;
+pub use order::{sort_by_requirements, OrderError};
+pub use pecl::{PackageKey, Pecl};
+pub use registry::{DataSource, Format, Registry, RegistryError, REGISTRY_ENV, REGISTRY_PATH};
 pub use version::Version;
 
 /// Prefix indicating a builtin extension
@@ -26,16 +38,23 @@ const PECL_TAG: &str = "pecl:";
 /// Length of the "pecl:" prefix
 const PECL_LEN: usize = PECL_TAG.len();
 
+/// Prefix indicating an extension that is only configured, not installed
+const CONFIG_TAG: &str = "config:";
+
+/// Length of the "config:" prefix
+const CONFIG_LEN: usize = CONFIG_TAG.len();
+
 /// Errors returned during parsing
 #[derive(Debug, Snafu)]
 pub enum ParseError {
     /// A prefix mismatch was encountered.
     ///
-    /// We expect either `"builtin:"` or `"pecl:"` in order to identify which installation method is to be used.
+    /// We expect `"builtin:"`, `"pecl:"`, or `"config:"` in order to identify which installation method is to be used.
     #[snafu(display(
-        r#"An extension name needs to begin with a prefix of either "{}" or "{}""#,
+        r#"An extension name needs to begin with a prefix of "{}", "{}", or "{}""#,
         BUILTIN_TAG,
-        PECL_TAG
+        PECL_TAG,
+        CONFIG_TAG
     ))]
     ExpectedPrefix,
 
@@ -46,6 +65,84 @@ pub enum ParseError {
         "An extension name needs to be a valid name (e.g., memcached, pdo_mysql, gd)"
     ))]
     InvalidSyntax,
+
+    /// A feature was requested that the extension's registry entry doesn't define.
+    #[snafu(display("{} has no optional feature named {:?}", extension, feature))]
+    UnknownFeature {
+        /// The extension the feature was requested for
+        extension: String,
+        /// The requested feature
+        feature: String,
+    },
+
+    /// A preset was requested that the extension's registry entry doesn't define.
+    #[snafu(display("{} has no preset named {:?}", extension, preset))]
+    UnknownPreset {
+        /// The extension the preset was requested for
+        extension: String,
+        /// The requested preset
+        preset: String,
+    },
+
+    /// A `sapi=` modifier names a SAPI that extensions can't be limited to.
+    #[snafu(display(r#"Unknown SAPI {:?} (expected "cli", "fpm", or "apache")"#, sapi))]
+    UnknownSapi {
+        /// The requested SAPI
+        sapi: String,
+    },
+
+    /// A modifier was given that only applies to other extensions.
+    #[snafu(display("The {:?} modifier can't be used with {}", modifier, extension))]
+    UnsupportedModifier {
+        /// The extension the modifier was given for
+        extension: String,
+        /// The modifier
+        modifier: String,
+    },
+
+    /// A `mode=` modifier names a mode that Xdebug doesn't have.
+    #[snafu(display("Xdebug has no mode named {:?}", mode))]
+    UnknownXdebugMode {
+        /// The requested mode
+        mode: String,
+    },
+
+    /// A `client-host=` modifier is given without `mode=debug`, the only mode it affects.
+    #[snafu(display("client-host= only applies to Xdebug's debug mode (add mode=debug)"))]
+    ClientHostWithoutDebug,
+
+    /// A registry entry names a replacement that isn't a valid extension.
+    #[snafu(display(
+        "{} is replaced by {:?}, which is not valid: {}",
+        extension,
+        replacement,
+        source
+    ))]
+    InvalidReplacement {
+        /// The extension being replaced
+        extension: String,
+        /// The replacement as written in the registry
+        replacement: String,
+        /// The underlying parse error
+        source: Box<ParseError>,
+    },
+
+    /// The modifiers given for an extension can't be applied to the extension that
+    /// replaces it on this platform.
+    #[snafu(display(
+        "{} is replaced by {} on this platform, which can't take the same modifiers: {}",
+        extension,
+        replacement,
+        source
+    ))]
+    ReplacementModifiers {
+        /// The extension being replaced
+        extension: String,
+        /// The replacement as written in the registry
+        replacement: String,
+        /// The underlying parse error
+        source: Box<ParseError>,
+    },
 }
 
 /// Encapsulates an extension needed by the Docker image currently being built.
@@ -56,15 +153,215 @@ pub enum Extension {
 
     /// This extension is a PECL extension (e.g., `memcached`, XDebug).
     Pecl(Pecl),
+
+    /// This extension is already present, and only its ini settings are written (e.g.,
+    /// `config:opcache?preset=production`).
+    Config(Box<Extension>),
 }
 
 impl Extension {
+    /// Returns the name of this extension.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Builtin(builtin) => builtin.name(),
+            Self::Pecl(pecl) => pecl.name(),
+            Self::Config(extension) => extension.name(),
+        }
+    }
+
+    /// Returns the name of the shared module that this extension builds.
+    pub fn module(&self) -> &str {
+        match self {
+            Self::Builtin(builtin) => builtin.name(),
+            Self::Pecl(pecl) => pecl.module(),
+            Self::Config(extension) => extension.module(),
+        }
+    }
+
+    /// Returns the name of the ini file that loads this extension, if the spec or the
+    /// registry chose one.
+    pub fn ini_name(&self) -> Option<&str> {
+        match self {
+            Self::Builtin(builtin) => builtin.ini_name(),
+            Self::Pecl(pecl) => pecl.ini_name(),
+            Self::Config(extension) => extension.ini_name(),
+        }
+    }
+
+    /// Returns a key identifying this extension (e.g., `builtin:gd` or `pecl:xdebug`),
+    /// without any version information.
+    pub fn key(&self) -> String {
+        match self {
+            Self::Builtin(builtin) => builtin.key(),
+            Self::Pecl(pecl) => format!("{}{}", PECL_TAG, pecl.name()),
+            Self::Config(extension) => format!("{}{}", CONFIG_TAG, extension.key()),
+        }
+    }
+
+    /// Returns where this extension's registry data was found.
+    pub fn source(&self) -> DataSource {
+        match self {
+            Self::Builtin(builtin) => builtin.source(),
+            Self::Pecl(pecl) => pecl.source(),
+            Self::Config(extension) => extension.source(),
+        }
+    }
+
+    /// Returns the optional features requested for this extension.
+    pub fn features(&self) -> &[String] {
+        match self {
+            Self::Builtin(builtin) => builtin.features(),
+            Self::Pecl(pecl) => pecl.features(),
+            Self::Config(extension) => extension.features(),
+        }
+    }
+
+    /// Retrieves the extensions that must be installed before this one.
+    pub fn requires(&self) -> Option<&Vec<String>> {
+        match self {
+            Self::Builtin(builtin) => builtin.requires(),
+            Self::Pecl(pecl) => pecl.requires(),
+            Self::Config(_) => None,
+        }
+    }
+
+    /// Determines if this extension will be enabled once installed. Builtins are always
+    /// enabled by `docker-php-ext-install`, and configured extensions are assumed to be
+    /// enabled already.
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Self::Builtin(_) | Self::Config(_) => true,
+            Self::Pecl(pecl) => pecl.is_enabled(),
+        }
+    }
+
+    /// Determines if this extension should be installed in the environment selected with
+    /// `--env`. Extensions tagged with `env=` are only installed when their environment
+    /// is selected; the rest are always installed.
+    pub fn is_for_env(&self, env: Option<&str>) -> bool {
+        let envs = match self {
+            Self::Builtin(builtin) => builtin.envs(),
+            Self::Pecl(pecl) => pecl.envs(),
+            Self::Config(extension) => return extension.is_for_env(env),
+        };
+
+        envs.is_empty() || env.is_some_and(|env| envs.iter().any(|e| e == env))
+    }
+
+    /// Determines if this extension is only configured (a `config:` spec), so that
+    /// nothing is built or installed for it.
+    pub fn is_config_only(&self) -> bool {
+        matches!(self, Self::Config(_))
+    }
+
+    /// Retrieves the default ini settings for this extension, if any.
+    pub fn ini(&self) -> Option<&BTreeMap<String, String>> {
+        match self {
+            Self::Builtin(builtin) => builtin.ini(),
+            Self::Pecl(pecl) => pecl.ini(),
+            Self::Config(extension) => extension.ini(),
+        }
+    }
+
+    /// Returns the ini directives to write for this extension: the user's settings, on top
+    /// of the registry's defaults if `defaults` is true.
+    pub fn ini_settings(&self, defaults: bool) -> BTreeMap<String, String> {
+        let (registry, user) = match self {
+            Self::Builtin(builtin) => (builtin.ini(), builtin.settings()),
+            Self::Pecl(pecl) => (pecl.ini(), pecl.settings()),
+            Self::Config(extension) => return extension.ini_settings(defaults),
+        };
+
+        let mut settings = match registry {
+            Some(registry) if defaults => registry.clone(),
+            _ => BTreeMap::new(),
+        };
+        settings.extend(user.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        settings
+    }
+
+    /// Adds an ini directive requested by the user (e.g., with `--ini`).
+    pub fn add_setting(&mut self, key: String, value: String) {
+        match self {
+            Self::Builtin(builtin) => builtin.add_setting(key, value),
+            Self::Pecl(pecl) => pecl.add_setting(key, value),
+            Self::Config(extension) => extension.add_setting(key, value),
+        }
+    }
+
+    /// Retrieves the directories (relative to `$PHP_INI_DIR`) that load this extension,
+    /// or an empty list if it is loaded from the shared `conf.d`.
+    pub fn ini_dirs(&self) -> Vec<String> {
+        match self {
+            Self::Builtin(builtin) => builtin.ini_dirs(),
+            Self::Pecl(pecl) => pecl.ini_dirs(),
+            Self::Config(extension) => extension.ini_dirs(),
+        }
+    }
+
+    /// Retrieves the patches to apply to this extension's source, if any.
+    pub fn patches(&self) -> Option<&Vec<String>> {
+        match self {
+            Self::Builtin(builtin) => builtin.patches(),
+            Self::Pecl(pecl) => pecl.patches(),
+            Self::Config(_) => None,
+        }
+    }
+
+    // Allow overriding the requirements for unit testing
+    #[cfg(test)]
+    pub fn set_requires(&mut self, requires: Vec<String>) {
+        match self {
+            Self::Builtin(builtin) => builtin.set_requires(requires),
+            Self::Pecl(pecl) => pecl.set_requires(requires),
+            Self::Config(extension) => extension.set_requires(requires),
+        }
+    }
+
     /// Retrieves the list of packages (if any) needed by this extension. A package is
     /// represented by its name as intepreted by the `apk` package manager.
     pub fn packages(&self) -> Option<&Vec<String>> {
         match self {
             Self::Builtin(builtin) => builtin.packages(),
             Self::Pecl(pecl) => pecl.packages(),
+            Self::Config(_) => None,
+        }
+    }
+
+    /// Retrieves the list of packages this extension needs at run time, if the registry
+    /// declares them.
+    pub fn runtime_packages(&self) -> Option<&Vec<String>> {
+        match self {
+            Self::Builtin(builtin) => builtin.runtime_packages(),
+            Self::Pecl(pecl) => pecl.runtime_packages(),
+            Self::Config(_) => None,
+        }
+    }
+
+    /// Retrieves the extra package repositories needed by this extension, if any.
+    pub fn repositories(&self) -> Option<&Vec<String>> {
+        match self {
+            Self::Builtin(builtin) => builtin.repositories(),
+            Self::Pecl(pecl) => pecl.repositories(),
+            Self::Config(_) => None,
+        }
+    }
+
+    /// Retrieves the URLs of vendor package files needed by this extension, if any.
+    pub fn package_urls(&self) -> Option<&Vec<String>> {
+        match self {
+            Self::Builtin(_) | Self::Config(_) => None,
+            Self::Pecl(pecl) => pecl.package_urls(),
+        }
+    }
+
+    /// Retrieves the key that signs this extension's vendor package files, if the registry
+    /// names one.
+    pub fn package_key(&self) -> Option<&PackageKey> {
+        match self {
+            Self::Builtin(_) | Self::Config(_) => None,
+            Self::Pecl(pecl) => pecl.package_key(),
         }
     }
 
@@ -75,6 +372,78 @@ impl Extension {
             Some(packages) => !packages.is_empty(),
         }
     }
+
+    /// Applies the registry variants for this extension that match `platform`.
+    ///
+    /// If the resolved entry names a replacement (e.g., a builtin that moved to PECL in
+    /// newer PHP releases), this extension becomes that replacement, with the same
+    /// modifiers and ini directives. Replacements are not followed further, so an entry
+    /// can't send the install around in circles.
+    pub fn resolve(&mut self, platform: &Platform) -> Result<(), ParseError> {
+        if let Self::Config(extension) = self {
+            return extension.resolve(platform);
+        }
+
+        // The user's ini directives (from `ini=` or `--ini`), before any presets are added,
+        // apply to the extension either way.
+        let settings = self.ini_settings(false);
+        self.resolve_data(platform);
+
+        let replacement = match &*self {
+            Self::Builtin(builtin) => builtin.replaced_by(),
+            Self::Pecl(_) | Self::Config(_) => None,
+        };
+
+        if let Some(replacement) = replacement {
+            let mut replaced = self.replacement(replacement)?;
+            replaced.resolve_data(platform);
+            for (key, value) in settings {
+                replaced.add_setting(key, value);
+            }
+            *self = replaced;
+        }
+
+        Ok(())
+    }
+
+    /// Parses `replacement` as the extension to install instead of this one, with the same
+    /// modifiers (e.g., `sapi=` or `priority=`), failing if it can't take them.
+    fn replacement(&self, replacement: &str) -> Result<Extension, ParseError> {
+        let replaced = replacement
+            .parse()
+            .map_err(|source| ParseError::InvalidReplacement {
+                extension: self.key(),
+                replacement: String::from(replacement),
+                source: Box::new(source),
+            })?;
+
+        let modifiers = match self {
+            Self::Builtin(builtin) => builtin.modifiers(),
+            Self::Pecl(_) | Self::Config(_) => None,
+        };
+        let modifiers = match modifiers {
+            Some(modifiers) => modifiers,
+            None => return Ok(replaced),
+        };
+
+        let separator = if replacement.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", replacement, separator, modifiers)
+            .parse()
+            .map_err(|source| ParseError::ReplacementModifiers {
+                extension: self.key(),
+                replacement: String::from(replacement),
+                source: Box::new(source),
+            })
+    }
+
+    /// Applies the registry variants for this extension, ignoring any replacement.
+    fn resolve_data(&mut self, platform: &Platform) {
+        match self {
+            Self::Builtin(builtin) => builtin.resolve(platform),
+            Self::Pecl(pecl) => pecl.resolve(platform),
+            Self::Config(extension) => extension.resolve_data(platform),
+        }
+    }
 }
 
 impl FromStr for Extension {
@@ -89,12 +458,77 @@ impl FromStr for Extension {
             let input = &input[PECL_LEN..];
             let pecl = input.parse()?;
             Ok(Self::Pecl(pecl))
+        } else if input.starts_with(CONFIG_TAG) {
+            let input = &input[CONFIG_LEN..];
+            let extension = if input.starts_with(BUILTIN_TAG) || input.starts_with(PECL_TAG) {
+                input.parse()?
+            } else if Registry::is_pecl_only(input.split(['?', '@']).next().unwrap_or_default()) {
+                // A bare name is a builtin unless the registry only knows it from PECL.
+                Self::Pecl(input.parse()?)
+            } else {
+                Self::Builtin(input.parse()?)
+            };
+            Ok(Self::Config(Box::new(extension)))
         } else {
             Err(ParseError::ExpectedPrefix)
         }
     }
 }
 
+/// A spec from the command line that couldn't be parsed.
+#[derive(Debug)]
+pub struct InvalidSpec {
+    /// The spec's position among the extensions given, starting from 1.
+    pub position: usize,
+    /// The spec as given.
+    pub spec: String,
+    /// Why the spec is invalid.
+    pub error: ParseError,
+}
+
+impl fmt::Display for InvalidSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {:?}: {}", self.position, self.spec, self.error)?;
+
+        // A bare name is most likely missing its prefix.
+        if let ParseError::ExpectedPrefix = self.error {
+            if !self.spec.contains(':') {
+                write!(
+                    f,
+                    r#" (did you mean "{}{}" or "{}{}"?)"#,
+                    BUILTIN_TAG, self.spec, PECL_TAG, self.spec
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses every spec in `specs`, reporting all of the invalid ones rather than only the
+/// first, so that they can be fixed together.
+pub fn parse_specs<S: AsRef<str>>(specs: &[S]) -> Result<Vec<Extension>, Vec<InvalidSpec>> {
+    let mut extensions = Vec::new();
+    let mut invalid = Vec::new();
+
+    for (index, spec) in specs.iter().enumerate() {
+        match spec.as_ref().parse() {
+            Ok(extension) => extensions.push(extension),
+            Err(error) => invalid.push(InvalidSpec {
+                position: index + 1,
+                spec: String::from(spec.as_ref()),
+                error,
+            }),
+        }
+    }
+
+    if invalid.is_empty() {
+        Ok(extensions)
+    } else {
+        Err(invalid)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use cool_asserts::assert_matches;
@@ -166,6 +600,121 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_xmlrpc_replaced_on_php8() {
+        let php74 = Platform::new(Some(semver::Version::new(7, 4, 33)));
+        let php80 = Platform::new(Some(semver::Version::new(8, 0, 30)));
+
+        let mut xmlrpc: Extension = "builtin:xmlrpc".parse().unwrap();
+        xmlrpc.resolve(&php74).unwrap();
+        assert_eq!(xmlrpc.key(), "builtin:xmlrpc");
+
+        let mut xmlrpc: Extension = "builtin:xmlrpc".parse().unwrap();
+        xmlrpc.resolve(&php80).unwrap();
+        assert_matches!(
+            &xmlrpc,
+            Extension::Pecl(pecl) => {
+                assert_eq!(pecl.specifier(), "xmlrpc-1.0.0RC3");
+                assert_eq!(pecl.packages().unwrap(), &vec![String::from("libxml2-dev")]);
+            }
+        );
+    }
+
+    #[test]
+    fn test_imap_replaced_on_php84() {
+        let php84 = Platform::new(Some(semver::Version::new(8, 4, 1)));
+
+        let mut imap: Extension = "builtin:imap".parse().unwrap();
+        imap.resolve(&php84).unwrap();
+        assert_matches!(
+            &imap,
+            Extension::Pecl(pecl) => {
+                assert_eq!(pecl.configure_options_arg().unwrap(), r#"with-imap-ssl="yes""#);
+            }
+        );
+    }
+
+    #[test]
+    fn test_replacement_keeps_modifiers() {
+        let php84 = Platform::new(Some(semver::Version::new(8, 4, 1)));
+
+        let mut imap: Extension = "builtin:imap?sapi=fpm&priority=30".parse().unwrap();
+        imap.add_setting(String::from("imap.enable_insecure_rsh"), String::from("0"));
+        imap.resolve(&php84).unwrap();
+
+        assert_eq!(imap.key(), "pecl:imap");
+        assert_eq!(imap.ini_dirs(), vec!["conf.d-fpm"]);
+        assert_eq!(imap.ini_name(), Some("30-imap.ini"));
+        assert_eq!(imap.ini_settings(false)["imap.enable_insecure_rsh"], "0");
+    }
+
+    #[test]
+    fn test_replacement_rejects_modifiers() {
+        let xdebug: Extension = "builtin:xdebug?mode=debug".parse().unwrap();
+
+        assert_matches!(
+            xdebug.replacement("pecl:pcov"),
+            Err(ParseError::ReplacementModifiers { source, .. }) => {
+                assert_matches!(*source, ParseError::UnsupportedModifier { .. });
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let mut opcache: Extension = "config:opcache?preset=production".parse().unwrap();
+        opcache.resolve(&Platform::default()).unwrap();
+        assert_eq!(opcache.key(), "config:builtin:opcache");
+        assert!(opcache.is_config_only());
+        assert!(opcache.packages().is_none());
+        assert_eq!(
+            opcache.ini_settings(true)["opcache.validate_timestamps"],
+            "0"
+        );
+
+        let xdebug: Extension = "config:xdebug?mode=debug".parse().unwrap();
+        assert_eq!(
+            xdebug.key(),
+            "config:pecl:xdebug",
+            "names only listed on PECL are PECL extensions"
+        );
+
+        let imap: Extension = "config:pecl:imap".parse().unwrap();
+        assert_eq!(imap.key(), "config:pecl:imap");
+        assert!(imap.requires().is_none());
+    }
+
+    #[test]
+    fn test_is_for_env() {
+        let xdebug: Extension = "pecl:xdebug?env=dev".parse().unwrap();
+        assert!(xdebug.is_for_env(Some("dev")));
+        assert!(!xdebug.is_for_env(Some("prod")));
+        assert!(!xdebug.is_for_env(None));
+
+        let opcache: Extension = "config:opcache?env=prod".parse().unwrap();
+        assert!(opcache.is_for_env(Some("prod")));
+
+        let redis: Extension = "pecl:redis".parse().unwrap();
+        assert!(redis.is_for_env(Some("dev")));
+        assert!(redis.is_for_env(None));
+    }
+
+    #[test]
+    fn test_parse_specs() {
+        let invalid =
+            parse_specs(&["pecl:redis", "gd", "builtin:gd", "pecl:xdebug@nope"]).unwrap_err();
+
+        assert_eq!(invalid.len(), 2);
+        assert_eq!(invalid[0].position, 2);
+        assert_eq!(
+            invalid[0].to_string(),
+            r#"#2 "gd": An extension name needs to begin with a prefix of "builtin:", "pecl:", or "config:" (did you mean "builtin:gd" or "pecl:gd"?)"#
+        );
+        assert_eq!(invalid[1].position, 4);
+
+        assert_eq!(parse_specs(&["pecl:redis"]).unwrap().len(), 1);
+    }
+
     #[test]
     #[should_panic]
     fn test_parse_pecl_garbage_version() {
@@ -0,0 +1,467 @@
+//! Modifiers that follow a `?` in an extension's spec (e.g., `pecl:xdebug?priority=zz`).
+//!
+//! Modifiers are separated by `&`. Besides the feature modifiers described in
+//! [`super::feature`], these are accepted:
+//!
+//! * `priority=<prefix>` names the extension's ini file `<prefix>-<name>.ini` (e.g.,
+//!   `builtin:opcache?priority=10` writes `10-opcache.ini`), since PHP loads the files
+//!   in `conf.d` in alphabetical order
+//! * `ini-name=<file>` names the ini file outright (`.ini` is added if missing)
+//! * `sapi=<a>,<b>` loads the extension only for the named SAPIs (`cli`, `fpm`, or
+//!   `apache`), from a `conf.d-<sapi>` directory instead of the shared `conf.d`
+//! * `mode=<a>,<b>` (Xdebug only) enables Xdebug with the given `xdebug.mode`, and for
+//!   step debugging, points it at the Docker host; `client-host=<host>` overrides the
+//!   host, and needs `mode=debug` (`client-host=gateway` has Xdebug connect back to the
+//!   address each HTTP request came from, which is the gateway for requests forwarded by
+//!   Docker). Xdebug 2 has no modes, so they are translated to its older settings
+//!
+//!   The Docker host is `host.docker.internal`, or if that doesn't resolve when the
+//!   extension is installed (Docker Engine without a `host-gateway` mapping), the default
+//!   gateway, which is also where CLI runs connect with `client-host=gateway`. That
+//!   gateway is the build's: containers run on another network (e.g., Compose's) have a
+//!   different one, and need `XDEBUG_CONFIG="client_host=<host>"` at run time
+//! * `preset=<a>,<b>` writes the ini settings of the named registry presets (e.g.,
+//!   `builtin:opcache?preset=production`)
+//! * `env=<a>,<b>` installs the extension only when `--env` names one of the given
+//!   environments (e.g., `pecl:xdebug?env=dev`)
+//! * `ini=<key>=<value>` writes an ini directive into the extension's settings file (e.g.,
+//!   `builtin:opcache?ini=opcache.memory_consumption=256`); repeat it for more
+
+use std::collections::BTreeMap;
+
+use super::{
+    feature::{self, Feature},
+    preset::Preset,
+    ParseError,
+};
+use crate::system::ini;
+
+/// The SAPIs accepted by the `sapi=` modifier.
+const SAPIS: &[&str] = &["cli", "fpm", "apache"];
+
+/// The modes accepted by Xdebug 3's `xdebug.mode` setting.
+const XDEBUG_MODES: &[&str] = &[
+    "off", "develop", "coverage", "debug", "gcstats", "profile", "trace",
+];
+
+/// The settings requested by a spec's modifiers.
+#[derive(Debug, Default)]
+pub struct Modifiers {
+    /// The optional features to enable.
+    pub features: Vec<String>,
+    /// The name of the ini file that loads the extension, if the spec chose one.
+    pub ini_name: Option<String>,
+    /// The SAPIs to load the extension for, or empty to load it for all of them.
+    pub sapis: Vec<String>,
+    /// The ini directives requested for the extension.
+    pub settings: BTreeMap<String, String>,
+    /// The presets requested for the extension.
+    pub presets: Vec<String>,
+    /// The environments the extension is limited to, or empty to install it in all of
+    /// them.
+    pub envs: Vec<String>,
+    /// Whether the modifiers imply enabling an extension that is disabled by default.
+    pub enable: bool,
+    /// The Xdebug modes requested, whose settings depend on the version installed.
+    pub xdebug: Option<XdebugModes>,
+}
+
+/// The Xdebug modes requested with `mode=`, and the host given with `client-host=`.
+#[derive(Clone, Debug, Default)]
+pub struct XdebugModes {
+    /// The modes, as named by Xdebug 3's `xdebug.mode`.
+    modes: Vec<String>,
+    /// The host to connect to for step debugging, if not the Docker host.
+    client_host: Option<String>,
+}
+
+impl XdebugModes {
+    /// Determines if `mode` was requested.
+    fn has(&self, mode: &str) -> bool {
+        self.modes.iter().any(|m| m == mode)
+    }
+
+    /// Adds the settings for these modes to `settings`, without replacing any given
+    /// explicitly. `legacy` selects Xdebug 2's settings, which predate `xdebug.mode`, and
+    /// `docker_host` is where step debugging connects unless `client-host=` named a host
+    /// (see [`crate::system::Platform::docker_host`]).
+    pub fn add_settings(
+        &self,
+        legacy: bool,
+        docker_host: &str,
+        settings: &mut BTreeMap<String, String>,
+    ) {
+        let mut add = |key: &str, value: &str| {
+            settings
+                .entry(String::from(key))
+                .or_insert_with(|| String::from(value));
+        };
+
+        let discover = self.client_host.as_deref() == Some("gateway");
+        let host = match self.client_host.as_deref() {
+            Some(host) if !discover => host,
+            _ => docker_host,
+        };
+
+        if legacy {
+            // Xdebug 2 always collects develop and coverage data.
+            if self.has("debug") {
+                add("xdebug.remote_enable", "1");
+                add("xdebug.remote_autostart", "1");
+                add("xdebug.remote_host", host);
+                if discover {
+                    add("xdebug.remote_connect_back", "1");
+                }
+            }
+            if self.has("profile") {
+                add("xdebug.profiler_enable_trigger", "1");
+            }
+            if self.has("trace") {
+                add("xdebug.trace_enable_trigger", "1");
+            }
+            if self.has("gcstats") {
+                add("xdebug.gc_stats_enable", "1");
+            }
+            return;
+        }
+
+        add("xdebug.mode", &self.modes.join(","));
+
+        let start_with_request = if self.has("debug") {
+            "yes"
+        } else if self.has("profile") || self.has("trace") {
+            "trigger"
+        } else {
+            return;
+        };
+        add("xdebug.start_with_request", start_with_request);
+
+        if self.has("debug") {
+            add("xdebug.client_host", host);
+            if discover {
+                add("xdebug.discover_client_host", "1");
+            }
+        }
+    }
+}
+
+/// Returns the directory (relative to `$PHP_INI_DIR`) holding the ini files for `sapi`.
+pub fn sapi_ini_dir(sapi: &str) -> String {
+    format!("conf.d-{}", sapi)
+}
+
+/// Builds the ini file name requested by a `priority=` modifier.
+fn priority_ini_name(extension: &str, priority: &str) -> String {
+    format!("{}-{}.ini", priority, extension)
+}
+
+/// Normalizes the ini file name given by an `ini-name=` modifier.
+fn explicit_ini_name(name: &str) -> Result<String, ParseError> {
+    if name.is_empty() || name.starts_with('.') {
+        return Err(ParseError::InvalidSyntax);
+    }
+
+    if name.ends_with(".ini") {
+        Ok(String::from(name))
+    } else {
+        Ok(format!("{}.ini", name))
+    }
+}
+
+/// Parses the `modifiers` of a spec for `extension` (the text after the `?`, if any).
+///
+/// Features start from the extension's `defaults`, and every feature named must be one
+/// of the `available` features. Likewise, every preset named must be one of the
+/// `available_presets`.
+pub fn parse_modifiers(
+    extension: &str,
+    available: &BTreeMap<String, Vec<Feature>>,
+    defaults: &[String],
+    available_presets: &BTreeMap<String, Vec<Preset>>,
+    modifiers: Option<&str>,
+) -> Result<Modifiers, ParseError> {
+    let mut feature_modifiers = Vec::new();
+    let mut ini_name = None;
+    let mut sapis = Vec::new();
+    let mut settings = BTreeMap::new();
+    let mut presets = Vec::new();
+    let mut envs = Vec::new();
+    let mut xdebug_modes: Option<Vec<String>> = None;
+    let mut client_host = None;
+
+    for modifier in modifiers.into_iter().flat_map(|m| m.split('&')) {
+        if let Some(priority) = modifier.strip_prefix("priority=") {
+            if priority.is_empty() || priority.contains('.') {
+                return Err(ParseError::InvalidSyntax);
+            }
+
+            ini_name = Some(priority_ini_name(extension, priority));
+        } else if let Some(name) = modifier.strip_prefix("ini-name=") {
+            ini_name = Some(explicit_ini_name(name)?);
+        } else if let Some(setting) = modifier.strip_prefix("ini=") {
+            let (key, value) = ini::parse_setting(setting).ok_or(ParseError::InvalidSyntax)?;
+            settings.insert(key, value);
+        } else if let Some(names) = modifier.strip_prefix("preset=") {
+            for name in names.split(',') {
+                if !available_presets.contains_key(name) {
+                    return Err(ParseError::UnknownPreset {
+                        extension: String::from(extension),
+                        preset: String::from(name),
+                    });
+                }
+
+                presets.push(String::from(name));
+            }
+        } else if let Some(names) = modifier.strip_prefix("env=") {
+            for name in names.split(',') {
+                if name.is_empty() {
+                    return Err(ParseError::InvalidSyntax);
+                }
+
+                envs.push(String::from(name));
+            }
+        } else if modifier.starts_with("mode=") || modifier.starts_with("client-host=") {
+            if extension != "xdebug" {
+                return Err(ParseError::UnsupportedModifier {
+                    extension: String::from(extension),
+                    modifier: String::from(modifier),
+                });
+            }
+
+            if let Some(modes) = modifier.strip_prefix("mode=") {
+                let modes: Vec<_> = modes.split(',').collect();
+                if let Some(mode) = modes.iter().find(|mode| !XDEBUG_MODES.contains(mode)) {
+                    return Err(ParseError::UnknownXdebugMode {
+                        mode: String::from(*mode),
+                    });
+                }
+                xdebug_modes = Some(modes.into_iter().map(String::from).collect());
+            } else {
+                client_host = modifier.strip_prefix("client-host=").map(String::from);
+            }
+        } else if let Some(names) = modifier.strip_prefix("sapi=") {
+            for name in names.split(',') {
+                if !SAPIS.contains(&name) {
+                    return Err(ParseError::UnknownSapi {
+                        sapi: String::from(name),
+                    });
+                }
+
+                if !sapis.iter().any(|sapi| sapi == name) {
+                    sapis.push(String::from(name));
+                }
+            }
+        } else {
+            feature_modifiers.push(modifier);
+        }
+    }
+
+    let features = feature::select_features(extension, available, defaults, &feature_modifiers)?;
+
+    let debugging = xdebug_modes
+        .as_ref()
+        .is_some_and(|modes| modes.iter().any(|mode| mode == "debug"));
+    if client_host.is_some() && !debugging {
+        return Err(ParseError::ClientHostWithoutDebug);
+    }
+
+    let xdebug = xdebug_modes.map(|modes| XdebugModes { modes, client_host });
+
+    Ok(Modifiers {
+        features,
+        ini_name,
+        sapis,
+        settings,
+        presets,
+        envs,
+        enable: xdebug.is_some(),
+        xdebug,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+    use crate::system::DOCKER_HOST;
+
+    #[test]
+    fn test_ini_name() {
+        let available = BTreeMap::new();
+
+        let modifiers = parse_modifiers(
+            "opcache",
+            &available,
+            &[],
+            &BTreeMap::new(),
+            Some("priority=10"),
+        )
+        .unwrap();
+        assert_eq!(modifiers.ini_name.as_deref(), Some("10-opcache.ini"));
+
+        let modifiers = parse_modifiers(
+            "xdebug",
+            &available,
+            &[],
+            &BTreeMap::new(),
+            Some("ini-name=zz-xdebug"),
+        )
+        .unwrap();
+        assert_eq!(modifiers.ini_name.as_deref(), Some("zz-xdebug.ini"));
+
+        let modifiers = parse_modifiers("xdebug", &available, &[], &BTreeMap::new(), None).unwrap();
+        assert_eq!(modifiers.ini_name, None);
+    }
+
+    #[test]
+    fn test_sapis() {
+        let available = BTreeMap::new();
+
+        let modifiers =
+            parse_modifiers("pcov", &available, &[], &BTreeMap::new(), Some("sapi=cli")).unwrap();
+        assert_eq!(modifiers.sapis, vec!["cli"]);
+
+        assert_matches!(
+            parse_modifiers("pcov", &available, &[], &BTreeMap::new(), Some("sapi=cgi")),
+            Err(ParseError::UnknownSapi { sapi }) => {
+                assert_eq!(sapi, "cgi");
+            }
+        );
+    }
+
+    #[test]
+    fn test_settings() {
+        let modifiers = parse_modifiers(
+            "opcache",
+            &BTreeMap::new(),
+            &[],
+            &BTreeMap::new(),
+            Some("ini=opcache.memory_consumption=256&ini=opcache.jit=tracing"),
+        )
+        .unwrap();
+
+        assert_eq!(modifiers.settings.len(), 2);
+        assert_eq!(modifiers.settings["opcache.jit"], "tracing");
+    }
+
+    #[test]
+    fn test_xdebug_mode() {
+        let modifiers = parse_modifiers(
+            "xdebug",
+            &BTreeMap::new(),
+            &[],
+            &BTreeMap::new(),
+            Some("mode=debug,develop&ini=xdebug.start_with_request=trigger"),
+        )
+        .unwrap();
+
+        assert!(modifiers.enable);
+        let xdebug = modifiers.xdebug.unwrap();
+        let mut settings = modifiers.settings;
+        xdebug.add_settings(false, DOCKER_HOST, &mut settings);
+        assert_eq!(settings["xdebug.mode"], "debug,develop");
+        assert_eq!(settings["xdebug.client_host"], DOCKER_HOST);
+        assert_eq!(settings["xdebug.start_with_request"], "trigger");
+
+        let mut settings = BTreeMap::new();
+        xdebug.add_settings(true, DOCKER_HOST, &mut settings);
+        assert!(!settings.contains_key("xdebug.mode"));
+        assert_eq!(settings["xdebug.remote_enable"], "1");
+        assert_eq!(settings["xdebug.remote_host"], DOCKER_HOST);
+
+        let modifiers = parse_modifiers(
+            "xdebug",
+            &BTreeMap::new(),
+            &[],
+            &BTreeMap::new(),
+            Some("mode=debug&client-host=gateway"),
+        )
+        .unwrap();
+        let mut settings = BTreeMap::new();
+        modifiers
+            .xdebug
+            .unwrap()
+            .add_settings(false, "172.17.0.1", &mut settings);
+        assert_eq!(settings["xdebug.discover_client_host"], "1");
+        assert_eq!(settings["xdebug.client_host"], "172.17.0.1");
+
+        for modifiers in ["client-host=10.0.0.2", "mode=profile&client-host=gateway"] {
+            assert_matches!(
+                parse_modifiers(
+                    "xdebug",
+                    &BTreeMap::new(),
+                    &[],
+                    &BTreeMap::new(),
+                    Some(modifiers)
+                ),
+                Err(ParseError::ClientHostWithoutDebug)
+            );
+        }
+
+        assert_matches!(
+            parse_modifiers("xdebug", &BTreeMap::new(), &[], &BTreeMap::new(), Some("mode=step")),
+            Err(ParseError::UnknownXdebugMode { mode }) => {
+                assert_eq!(mode, "step");
+            }
+        );
+        assert_matches!(
+            parse_modifiers(
+                "redis",
+                &BTreeMap::new(),
+                &[],
+                &BTreeMap::new(),
+                Some("mode=debug")
+            ),
+            Err(ParseError::UnsupportedModifier { .. })
+        );
+    }
+
+    #[test]
+    fn test_envs() {
+        let modifiers = parse_modifiers(
+            "xdebug",
+            &BTreeMap::new(),
+            &[],
+            &BTreeMap::new(),
+            Some("env=dev,test&mode=debug"),
+        )
+        .unwrap();
+        assert_eq!(modifiers.envs, vec!["dev", "test"]);
+
+        assert_matches!(
+            parse_modifiers(
+                "xdebug",
+                &BTreeMap::new(),
+                &[],
+                &BTreeMap::new(),
+                Some("env=")
+            ),
+            Err(ParseError::InvalidSyntax)
+        );
+    }
+
+    #[test]
+    fn test_invalid_ini_name() {
+        assert_matches!(
+            parse_modifiers(
+                "x",
+                &BTreeMap::new(),
+                &[],
+                &BTreeMap::new(),
+                Some("ini-name=")
+            ),
+            Err(ParseError::InvalidSyntax)
+        );
+        assert_matches!(
+            parse_modifiers(
+                "x",
+                &BTreeMap::new(),
+                &[],
+                &BTreeMap::new(),
+                Some("priority=1.0")
+            ),
+            Err(ParseError::InvalidSyntax)
+        );
+    }
+}
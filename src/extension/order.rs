@@ -0,0 +1,237 @@
+//! Ordering of extensions according to their registry requirements.
+
+use snafu::Snafu;
+use std::collections::HashSet;
+
+use super::{Extension, ParseError};
+use crate::system::Platform;
+
+/// Errors returned while ordering extensions.
+#[derive(Debug, Snafu)]
+pub enum OrderError {
+    /// A registry entry names a requirement that isn't a valid extension.
+    #[snafu(display(
+        "{} requires {:?}, which is not a valid extension: {}",
+        extension,
+        requirement,
+        source
+    ))]
+    InvalidRequirement {
+        /// The extension declaring the requirement
+        extension: String,
+        /// The requirement as written in the registry
+        requirement: String,
+        /// The underlying parse error
+        source: ParseError,
+    },
+
+    /// A builtin requires a PECL extension. Builtins are all installed before any PECL
+    /// extension is compiled, so the requirement can't be met.
+    #[snafu(display(
+        "{} requires {}, but builtins are installed before PECL extensions",
+        extension,
+        requirement
+    ))]
+    BuiltinRequiresPecl {
+        /// The builtin declaring the requirement
+        extension: String,
+        /// The key of the required PECL extension
+        requirement: String,
+    },
+
+    /// Extensions require each other in a cycle, so no install order exists.
+    #[snafu(display("Circular extension requirements: {}", chain.join(" -> ")))]
+    Cycle {
+        /// The extensions that form the cycle, starting and ending with the same one
+        chain: Vec<String>,
+    },
+}
+
+/// Depth-first traversal state for [`sort_by_requirements`].
+struct Sorter<'a> {
+    /// The platform used to resolve requirements that weren't requested explicitly.
+    platform: &'a Platform,
+    /// The extensions requested on the command line, which take precedence over
+    /// requirements parsed from the registry.
+    requested: Vec<Extension>,
+    /// Keys of extensions already placed in `sorted`.
+    visited: HashSet<String>,
+    /// Keys of the extensions currently being visited, in order.
+    stack: Vec<String>,
+    /// The extensions in install order.
+    sorted: Vec<Extension>,
+}
+
+impl<'a> Sorter<'a> {
+    /// Places `extension` in the output after all of its requirements.
+    fn visit(&mut self, extension: Extension) -> Result<(), OrderError> {
+        let key = extension.key();
+        if self.visited.contains(&key) {
+            return Ok(());
+        }
+
+        if let Some(start) = self.stack.iter().position(|k| *k == key) {
+            let mut chain = self.stack[start..].to_vec();
+            chain.push(key);
+            return Err(OrderError::Cycle { chain });
+        }
+
+        self.stack.push(key.clone());
+
+        let requirements = extension.requires().cloned().unwrap_or_default();
+        for requirement in requirements {
+            let required = self.find_requirement(&extension, &requirement)?;
+            if let (Extension::Builtin(_), Extension::Pecl(_)) = (&extension, &required) {
+                return Err(OrderError::BuiltinRequiresPecl {
+                    extension: key,
+                    requirement: required.key(),
+                });
+            }
+
+            self.visit(required)?;
+        }
+
+        self.stack.pop();
+        self.visited.insert(key);
+        self.sorted.push(extension);
+
+        Ok(())
+    }
+
+    /// Finds the extension named by `requirement`, preferring the user's own request
+    /// (which may carry a specific version) over a fresh parse of the requirement.
+    fn find_requirement(
+        &self,
+        extension: &Extension,
+        requirement: &str,
+    ) -> Result<Extension, OrderError> {
+        let mut parsed: Extension =
+            requirement
+                .parse()
+                .map_err(|source| OrderError::InvalidRequirement {
+                    extension: extension.key(),
+                    requirement: String::from(requirement),
+                    source,
+                })?;
+
+        let key = parsed.key();
+        if let Some(requested) = self.requested.iter().find(|e| e.key() == key) {
+            return Ok(requested.clone());
+        }
+
+        parsed
+            .resolve(self.platform)
+            .map_err(|source| OrderError::InvalidRequirement {
+                extension: extension.key(),
+                requirement: String::from(requirement),
+                source,
+            })?;
+
+        Ok(parsed)
+    }
+}
+
+/// Sorts `extensions` so that every extension comes after the extensions it requires,
+/// adding any requirements that weren't requested. Otherwise, the requested order is
+/// preserved. Builtins that require a PECL extension are rejected, since all builtins
+/// are installed first.
+pub fn sort_by_requirements(
+    extensions: Vec<Extension>,
+    platform: &Platform,
+) -> Result<Vec<Extension>, OrderError> {
+    let mut sorter = Sorter {
+        platform,
+        requested: extensions.clone(),
+        visited: HashSet::new(),
+        stack: Vec::new(),
+        sorted: Vec::new(),
+    };
+
+    for extension in extensions {
+        sorter.visit(extension)?;
+    }
+
+    Ok(sorter.sorted)
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+
+    /// Parses an extension and overrides its requirements for testing.
+    fn extension(spec: &str, requires: &[&str]) -> Extension {
+        let mut extension: Extension = spec.parse().unwrap();
+        extension.set_requires(requires.iter().map(|s| String::from(*s)).collect());
+        extension
+    }
+
+    /// Returns the keys of the given extensions, in order.
+    fn keys(extensions: &[Extension]) -> Vec<String> {
+        extensions.iter().map(Extension::key).collect()
+    }
+
+    #[test]
+    fn test_requirement_moves_first() {
+        let extensions = vec![
+            extension("pecl:foo", &["pecl:bar"]),
+            extension("pecl:bar", &[]),
+        ];
+
+        let sorted = sort_by_requirements(extensions, &Platform::default()).unwrap();
+        assert_eq!(keys(&sorted), vec!["pecl:bar", "pecl:foo"]);
+    }
+
+    #[test]
+    fn test_missing_requirement_added() {
+        let extensions = vec![extension("pecl:foo", &["builtin:sockets"])];
+
+        let sorted = sort_by_requirements(extensions, &Platform::default()).unwrap();
+        assert_eq!(keys(&sorted), vec!["builtin:sockets", "pecl:foo"]);
+    }
+
+    #[test]
+    fn test_requested_version_kept() {
+        let extensions = vec![
+            extension("pecl:foo", &["pecl:bar"]),
+            extension("pecl:bar@1.2.3", &[]),
+        ];
+
+        let sorted = sort_by_requirements(extensions, &Platform::default()).unwrap();
+        assert_matches!(
+            &sorted[0],
+            Extension::Pecl(bar) => {
+                assert_eq!(bar.specifier(), "bar-1.2.3");
+            }
+        );
+    }
+
+    #[test]
+    fn test_builtin_requires_pecl() {
+        let extensions = vec![extension("builtin:sockets", &["pecl:foo"])];
+
+        assert_matches!(
+            sort_by_requirements(extensions, &Platform::default()),
+            Err(OrderError::BuiltinRequiresPecl { extension, requirement }) => {
+                assert_eq!(extension, "builtin:sockets");
+                assert_eq!(requirement, "pecl:foo");
+            }
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let extensions = vec![
+            extension("pecl:foo", &["pecl:bar"]),
+            extension("pecl:bar", &["pecl:foo"]),
+        ];
+
+        assert_matches!(
+            sort_by_requirements(extensions, &Platform::default()),
+            Err(OrderError::Cycle { chain }) => {
+                assert_eq!(chain, vec!["pecl:foo", "pecl:bar", "pecl:foo"]);
+            }
+        );
+    }
+}
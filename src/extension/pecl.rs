@@ -1,26 +1,271 @@
 //! Type and helpers for PECL extensions.
 
-use lazy_static::lazy_static;
-use maplit::btreemap;
 use regex::Regex;
-use serde::Deserialize;
-use std::{collections::BTreeMap, str::FromStr};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr, sync::LazyLock};
 
-use super::{ParseError, Version};
+use super::{
+    feature::{self, Feature},
+    modifier::{self, sapi_ini_dir, XdebugModes},
+    preset::{self, Preset},
+    registry::{self, DataSource, Registry},
+    variant::{self, Condition},
+    ParseError, Version,
+};
+use crate::system::Platform;
+
+/// Overrides applied to a PECL extension's data when the platform matches a condition.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PeclVariant {
+    /// The platform requirements for this variant to apply.
+    #[serde(flatten)]
+    when: Condition,
+    /// Replacement list of external packages, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    packages: Option<Vec<String>>,
+    /// Replacement list of runtime packages, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    runtime_packages: Option<Vec<String>>,
+    /// Replacement list of extra package repositories, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repositories: Option<Vec<String>>,
+    /// Replacement list of required extensions, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requires: Option<Vec<String>>,
+    /// Replacement ini settings, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ini: Option<BTreeMap<String, String>>,
+    /// Replacement source patches, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patches: Option<Vec<String>>,
+    /// Replacement answers to configure prompts, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    configure_options: Option<Vec<String>>,
+    /// Replacement package file URLs, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    package_urls: Option<Vec<String>>,
+    /// Replacement default version or channel, if this variant changes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    /// Replacement compiler flags, if this variant changes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cflags: Option<String>,
+    /// Rejects keys that aren't fields of this table (see [`variant::deny_unknown`]).
+    #[serde(flatten, deserialize_with = "variant::deny_unknown", skip_serializing)]
+    _unknown: (),
+}
+
+/// The OpenPGP key that must have signed an extension's vendor package files. Each file's
+/// detached signature is expected at its URL with `.apk` replaced by `.sig`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PackageKey {
+    /// Where the public key is downloaded from.
+    pub url: String,
+
+    /// The key's fingerprint, so that only this key is trusted.
+    pub fingerprint: String,
+}
 
 /// Represents the data for a PECL extension.
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct PeclData {
     /// The external package (if any) needed by this extension.
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     packages: Option<Vec<String>>,
 
+    /// The version or channel (e.g., `beta`) to install when the spec doesn't name one,
+    /// for extensions whose stable releases don't support current PHP versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+
+    /// URLs of `.apk` files to install alongside this extension, for vendor packages that
+    /// aren't published in an Alpine repository (e.g., Microsoft's ODBC driver). These are
+    /// kept at run time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    package_urls: Option<Vec<String>>,
+
+    /// The packages this extension needs at run time, if known. When every extension
+    /// being installed declares these, the `scanelf` pass is skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    runtime_packages: Option<Vec<String>>,
+
+    /// Extra package repositories (URLs) to search when installing this extension's
+    /// packages, for packages the base image's release no longer carries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repositories: Option<Vec<String>>,
+
     /// Should this extension be disabled by default in the Docker image being built?
     ///
     /// This field exists primarily to support XDebug, which is not enabled by default
     /// due to the performance penalty it imposes.
     #[serde(default)]
     disabled: bool,
+
+    /// The name of the shared module that PECL builds, if it differs from the package
+    /// name (e.g., `pecl_http` builds `http.so`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    module: Option<String>,
+
+    /// A directory under `$PHP_INI_DIR` to load this extension from instead of `conf.d`
+    /// (e.g., `conf.d-profiling`).
+    ///
+    /// PHP only reads the directory when it is listed in `PHP_INI_SCAN_DIR`, so the
+    /// extension is installed in the image but only loaded in containers that opt in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ini_dir: Option<String>,
+
+    /// The name of the ini file that enables this extension, if it differs from
+    /// `docker-php-ext-<name>.ini`.
+    ///
+    /// PHP loads the files in `conf.d` in alphabetical order, so an extension that links
+    /// against another shared extension (e.g., `event` and `sockets`) needs a name that
+    /// sorts after its requirement's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ini_name: Option<String>,
+
+    /// Answers to the prompts `pecl install` asks during configuration, in `name=value`
+    /// form (e.g., `enable-redis-igbinary=yes`).
+    ///
+    /// These are passed non-interactively via `--configureoptions`, so that builds don't
+    /// hang on a prompt or silently pick the wrong default. Extensions built from a
+    /// `source_url` are given them as `./configure --<name>=<value>` options instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    configure_options: Option<Vec<String>>,
+
+    /// URL of a `.tar.gz` of the source of an extension that isn't published on PECL
+    /// (e.g., `tideways_xhprof`), with `{version}` standing for the version installed.
+    ///
+    /// The archive is checked against `checksums` like a PECL download, then built with
+    /// `phpize`, `./configure`, and `make install` instead of `pecl install`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_url: Option<String>,
+
+    /// Extra flags passed to the C and C++ compilers (via `CFLAGS` and `CXXFLAGS`) while
+    /// building this extension, for code that needs help compiling against musl.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cflags: Option<String>,
+
+    /// Extensions (in `builtin:<name>` or `pecl:<name>` syntax) that must be installed
+    /// before this one. Missing requirements are added to the install automatically.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requires: Option<Vec<String>>,
+
+    /// Default ini settings for this extension, written to its own file in `conf.d` when
+    /// the extension is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ini: Option<BTreeMap<String, String>>,
+
+    /// Patches applied to the extension's source before it is configured. Each entry is
+    /// either a path to a patch file or the text of a diff, applied with `patch -p1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patches: Option<Vec<String>>,
+
+    /// SHA-256 digests (in hex) of this extension's release archives, by version (e.g.,
+    /// `"6.0.2" = "01531a..."`).
+    ///
+    /// PECL doesn't publish digests for its releases, so these are pinned here, usually in
+    /// an imported registry file. When the version downloaded has a digest, the archive is
+    /// verified before it is built.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    checksums: BTreeMap<String, String>,
+
+    /// Features enabled unless the spec disables them (e.g., `pecl:redis?no-igbinary`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    default_features: Vec<String>,
+
+    /// Platform-specific overrides, applied in order. Later matching variants take
+    /// precedence over earlier ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    variants: Vec<PeclVariant>,
+
+    /// Optional features (requested as `pecl:<name>?with=<feature>`), each a list of
+    /// additions applied on matching platforms.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    features: BTreeMap<String, Vec<Feature>>,
+
+    /// Named sets of ini settings (requested as `pecl:<name>?preset=<preset>`), each a
+    /// list of settings applied on matching platforms.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    presets: BTreeMap<String, Vec<Preset>>,
+
+    /// The OpenPGP key that signs the files in `package_urls`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    package_key: Option<Box<PackageKey>>,
+}
+
+impl PeclData {
+    /// Returns a copy of this data with every variant matching `platform` applied.
+    fn resolve(&self, platform: &Platform) -> Self {
+        let mut resolved = self.clone();
+
+        for variant in self.variants.iter().filter(|v| v.when.matches(platform)) {
+            if let Some(packages) = &variant.packages {
+                resolved.packages = Some(packages.clone());
+            }
+
+            if let Some(runtime_packages) = &variant.runtime_packages {
+                resolved.runtime_packages = Some(runtime_packages.clone());
+            }
+
+            if let Some(repositories) = &variant.repositories {
+                resolved.repositories = Some(repositories.clone());
+            }
+
+            if let Some(requires) = &variant.requires {
+                resolved.requires = Some(requires.clone());
+            }
+
+            if let Some(ini) = &variant.ini {
+                resolved.ini = Some(ini.clone());
+            }
+
+            if let Some(patches) = &variant.patches {
+                resolved.patches = Some(patches.clone());
+            }
+
+            if let Some(configure_options) = &variant.configure_options {
+                resolved.configure_options = Some(configure_options.clone());
+            }
+
+            if let Some(package_urls) = &variant.package_urls {
+                resolved.package_urls = Some(package_urls.clone());
+            }
+
+            if let Some(version) = &variant.version {
+                resolved.version = Some(version.clone());
+            }
+
+            if let Some(cflags) = &variant.cflags {
+                resolved.cflags = Some(cflags.clone());
+            }
+        }
+
+        if let Some(repositories) = &mut resolved.repositories {
+            for repository in repositories.iter_mut() {
+                *repository = platform.expand(repository);
+            }
+        }
+
+        resolved.variants.clear();
+        resolved
+    }
+
+    /// Appends the additions from each requested feature that apply to `platform`.
+    fn apply_features(&mut self, features: &[String], platform: &Platform) {
+        let available = std::mem::take(&mut self.features);
+        let additions = features
+            .iter()
+            .filter_map(|name| available.get(name))
+            .flatten()
+            .filter(|addition| addition.matches(platform));
+
+        for addition in additions {
+            feature::append(&mut self.packages, &addition.packages);
+            feature::append_runtime(&mut self.runtime_packages, &addition.runtime_packages);
+            feature::append(&mut self.requires, &addition.requires);
+            feature::append(&mut self.configure_options, &addition.configure_options);
+        }
+    }
 }
 
 /// Represents the information needed to install and configure a PECL extension.
@@ -29,11 +274,40 @@ pub struct Pecl {
     /// The name of this PECL extension.
     name: String,
 
-    /// The version requested for this installation.
-    version: Version,
+    /// The version requested for this installation, if the spec named one.
+    version: Option<Version>,
+
+    /// The optional features requested for this installation.
+    features: Vec<String>,
+
+    /// The name of the ini file that loads this extension, if the spec chose one.
+    ini_name: Option<String>,
+
+    /// The SAPIs to load this extension for, or empty to load it for all of them.
+    sapis: Vec<String>,
+
+    /// Whether the spec asked for this extension to be enabled even if the registry
+    /// disables it by default (e.g., `pecl:xdebug?mode=debug`).
+    enable: bool,
+
+    /// The Xdebug modes requested, whose settings are added once the version is known.
+    xdebug: Option<Box<XdebugModes>>,
+
+    /// The ini directives requested for this extension by the user, which take
+    /// precedence over the registry's defaults.
+    settings: BTreeMap<String, String>,
+
+    /// The presets requested for this installation.
+    presets: Vec<String>,
+
+    /// The environments (see `--env`) this extension is limited to, if any.
+    envs: Vec<String>,
 
     /// The data for this extension.
     data: PeclData,
+
+    /// Where the data was found.
+    source: DataSource,
 }
 
 impl Pecl {
@@ -42,81 +316,255 @@ impl Pecl {
         &self.name
     }
 
+    /// Returns the optional features requested for this installation.
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// Returns where this extension's registry data was found.
+    pub fn source(&self) -> DataSource {
+        self.source
+    }
+
     /// Returns the list of external packages (if any) needed by this extension.
     pub fn packages(&self) -> Option<&Vec<String>> {
         self.data.packages.as_ref()
     }
 
+    /// Returns the URLs of vendor package files to install, if any.
+    pub fn package_urls(&self) -> Option<&Vec<String>> {
+        self.data.package_urls.as_ref()
+    }
+
+    /// Returns the URL and fingerprint of the key that signs the vendor package files, if
+    /// the registry names one.
+    pub fn package_key(&self) -> Option<&PackageKey> {
+        self.data.package_key.as_deref()
+    }
+
+    /// Returns the list of runtime packages, if the registry declares them.
+    pub fn runtime_packages(&self) -> Option<&Vec<String>> {
+        self.data.runtime_packages.as_ref()
+    }
+
+    /// Returns the extra package repositories needed by this extension, if any.
+    pub fn repositories(&self) -> Option<&Vec<String>> {
+        self.data.repositories.as_ref()
+    }
+
+    /// Returns the extensions that must be installed before this one.
+    pub fn requires(&self) -> Option<&Vec<String>> {
+        self.data.requires.as_ref()
+    }
+
+    /// Returns the default ini settings for this extension, if any.
+    pub fn ini(&self) -> Option<&BTreeMap<String, String>> {
+        self.data.ini.as_ref()
+    }
+
+    /// Returns the ini directives requested by the user for this extension.
+    pub fn settings(&self) -> &BTreeMap<String, String> {
+        &self.settings
+    }
+
+    /// Retrieves the environments this extension is limited to, or an empty list if it is
+    /// installed in all of them.
+    pub fn envs(&self) -> &[String] {
+        &self.envs
+    }
+
+    /// Adds an ini directive requested by the user (e.g., with `--ini`).
+    pub fn add_setting(&mut self, key: String, value: String) {
+        self.settings.insert(key, value);
+    }
+
+    /// Returns the patches to apply to this extension's source, if any.
+    pub fn patches(&self) -> Option<&Vec<String>> {
+        self.data.patches.as_ref()
+    }
+
+    /// Returns the pinned SHA-256 digest of this extension's `version` archive, if any.
+    pub fn checksum(&self, version: &str) -> Option<&str> {
+        self.data.checksums.get(version).map(String::as_str)
+    }
+
+    // Allow overriding the requirements for unit testing
+    #[cfg(test)]
+    pub fn set_requires(&mut self, requires: Vec<String>) {
+        self.data.requires = Some(requires);
+    }
+
+    // Allow pinning digests for unit testing
+    #[cfg(test)]
+    pub fn set_checksum(&mut self, version: &str, digest: &str) {
+        self.data
+            .checksums
+            .insert(String::from(version), String::from(digest));
+    }
+
+    // Allow overriding the configure answers for unit testing
+    #[cfg(test)]
+    pub fn set_configure_options(&mut self, options: Vec<String>) {
+        self.data.configure_options = Some(options);
+    }
+
+    /// Returns the answers (in `name=value` form) to this extension's configure prompts.
+    pub fn configure_options(&self) -> Option<&Vec<String>> {
+        self.data.configure_options.as_ref()
+    }
+
+    /// Returns the URL of the source archive to build this extension from, if it isn't
+    /// installed from PECL, with `{version}` replaced by the version requested.
+    pub fn source_url(&self) -> Option<String> {
+        let url = self.data.source_url.as_deref()?;
+        Some(url.replace("{version}", &self.version().to_string()))
+    }
+
+    /// Returns the extra compiler flags needed to build this extension, if any.
+    pub fn cflags(&self) -> Option<&str> {
+        self.data.cflags.as_deref()
+    }
+
+    /// Formats this extension's configure answers for `pecl install --configureoptions`,
+    /// which expects the form `name1="value1" name2="value2"`.
+    ///
+    /// Returns `None` if there are no answers to give.
+    pub fn configure_options_arg(&self) -> Option<String> {
+        let options = self.configure_options()?;
+        if options.is_empty() {
+            return None;
+        }
+
+        let formatted: Vec<_> = options
+            .iter()
+            .map(|option| match option.split_once('=') {
+                Some((name, value)) => {
+                    format!(r#"{}="{}""#, name.trim(), escape_option(value.trim()))
+                }
+                None => format!(r#"{}="yes""#, option.trim()),
+            })
+            .collect();
+
+        Some(formatted.join(" "))
+    }
+
     /// Determines if this extension should be enabled by default.
     pub fn is_enabled(&self) -> bool {
-        !self.data.disabled
+        self.enable || !self.data.disabled
+    }
+
+    /// Returns the name of the shared module this extension builds, which is usually the
+    /// same as its name.
+    pub fn module(&self) -> &str {
+        self.data.module.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Returns the directories (relative to `$PHP_INI_DIR`) that load this extension, or
+    /// an empty list if it is loaded from the shared `conf.d`.
+    ///
+    /// SAPIs requested by the spec take precedence over the registry's `ini_dir`.
+    pub fn ini_dirs(&self) -> Vec<String> {
+        if !self.sapis.is_empty() {
+            return self.sapis.iter().map(|sapi| sapi_ini_dir(sapi)).collect();
+        }
+
+        self.data.ini_dir.iter().cloned().collect()
+    }
+
+    /// Returns the name of the ini file that enables this extension, if it is overridden
+    /// by the spec or the registry (in that order of precedence).
+    pub fn ini_name(&self) -> Option<&str> {
+        self.ini_name.as_deref().or(self.data.ini_name.as_deref())
     }
 
     /// Returns the PECL extension specifier for this PECL extension, in the format NAME-VERSION.
     pub fn specifier(&self) -> String {
-        format!("{}-{}", self.name, self.version)
+        let version = self.version.clone().unwrap_or_default();
+        format!("{}-{}", self.name, version)
     }
 
-    // Allow access to the extension's version for unit testing
-    #[cfg(test)]
+    /// Selects the variants of this extension's data that apply to `platform`, then adds
+    /// the requested features and the registry's default version, if the spec didn't
+    /// name a version.
+    pub fn resolve(&mut self, platform: &Platform) {
+        self.data = self.data.resolve(platform);
+        self.data.apply_features(&self.features, platform);
+
+        let presets = std::mem::take(&mut self.data.presets);
+        preset::apply(&presets, &self.presets, platform, &mut self.settings);
+
+        if self.version.is_none() {
+            self.version = self.data.version.as_deref().map(Version::parse);
+        }
+
+        if let Some(xdebug) = &self.xdebug {
+            let legacy = match self.version() {
+                Version::Custom(version) => version
+                    .split('.')
+                    .next()
+                    .and_then(|major| major.parse::<u32>().ok())
+                    .is_some_and(|major| major < 3),
+                _ => false,
+            };
+            xdebug.add_settings(legacy, platform.docker_host(), &mut self.settings);
+        }
+    }
+
+    /// Returns the version or channel requested for this extension.
     pub fn version(&self) -> &Version {
-        &self.version
+        static DEFAULT: Version = Version::Stable;
+        self.version.as_ref().unwrap_or(&DEFAULT)
     }
 }
 
-lazy_static! {
-    static ref REGISTRY: BTreeMap<&'static str, PeclData> = btreemap! {
-        "imagick" => PeclData {
-            packages: Some(vec![String::from("imagemagick-dev")]),
-            ..PeclData::default()
-        },
-
-        "memcached" => PeclData {
-            packages: Some(vec![
-                String::from("libmemcached-dev"),
-                String::from("zlib-dev"),
-                String::from("libevent-dev"),
-            ]),
-            ..PeclData::default()
-        },
-
-        "xdebug" => PeclData {
-            disabled: true,
-            ..PeclData::default()
-        },
-    };
+/// Escapes the backslashes and double quotes in a configure option's value, which is
+/// passed to `pecl` in double quotes.
+fn escape_option(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#)
 }
 
-/// Finds a PECL extension's data from either the internal registry or the environment.
-/// If neither attempt succeeds, returns empty PECL data.
-fn find_pecl_data(name: &str) -> PeclData {
-    if let Some(found) = REGISTRY.get(name) {
-        return found.clone();
+/// Finds a PECL extension's data from either the supplemental registry, the internal
+/// registry, or the environment, along with where it was found. If no attempt succeeds,
+/// returns empty PECL data.
+pub(super) fn find_pecl_data(name: &str) -> (PeclData, DataSource) {
+    if let Some(found) = Registry::supplemental().and_then(|registry| registry.pecl.get(name)) {
+        return (found.clone(), DataSource::Supplemental);
+    }
+
+    if let Some(found) = Registry::internal().pecl.get(name) {
+        return (found.clone(), DataSource::Internal);
     }
 
     let prefix = format!("F1_PECL_{}_", name.to_ascii_uppercase());
+    let source = if registry::has_env_prefix(&prefix) {
+        DataSource::Environment
+    } else {
+        DataSource::Unlisted
+    };
+
     if let Ok(data) = envy::prefixed(prefix).from_env() {
-        return data;
+        return (data, source);
     }
 
-    PeclData::default()
+    (PeclData::default(), source)
 }
 
 impl FromStr for Pecl {
     type Err = ParseError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        lazy_static! {
-            static ref PECL: Regex = Regex::new(
+        static PECL: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
                 r#"(?x)
                 ^
                 (?P<name>[_a-zA-Z0-9]+)
-                (?:@(?P<version>stable|\d+\.\d+\.\d+))?
+                (?:@(?P<version>stable|beta|\d+\.\d+\.\d+(?:(?:alpha|beta|RC)\d+)?))?
+                (?:\?(?P<modifiers>[^\s?]+))?
                 $
-                "#
+                "#,
             )
-            .unwrap();
-        }
+            .unwrap()
+        });
 
         let caps = match PECL.captures(input) {
             Some(caps) => caps,
@@ -124,22 +572,30 @@ impl FromStr for Pecl {
         };
 
         let name = &caps["name"];
-        let version = match caps.name("version") {
-            Some(cap) => {
-                let cap = cap.as_str();
-                if cap == "stable" {
-                    Version::Stable
-                } else {
-                    Version::Custom(String::from(cap))
-                }
-            }
-            None => Version::default(),
-        };
+        let version = caps.name("version").map(|cap| Version::parse(cap.as_str()));
+
+        let (data, source) = find_pecl_data(name);
+        let modifiers = modifier::parse_modifiers(
+            name,
+            &data.features,
+            &data.default_features,
+            &data.presets,
+            caps.name("modifiers").map(|cap| cap.as_str()),
+        )?;
 
         Ok(Pecl {
             name: String::from(name),
             version,
-            data: find_pecl_data(name),
+            features: modifiers.features,
+            ini_name: modifiers.ini_name,
+            sapis: modifiers.sapis,
+            settings: modifiers.settings,
+            presets: modifiers.presets,
+            envs: modifiers.envs,
+            source,
+            enable: modifiers.enable,
+            xdebug: modifiers.xdebug.map(Box::new),
+            data,
         })
     }
 }
@@ -185,4 +641,198 @@ mod tests {
             "xdebug@2.5.5 should have custom version 2.5.5",
         );
     }
+
+    #[test]
+    fn test_prerelease_version() {
+        let xmlrpc: Pecl = "xmlrpc@1.0.0RC3".parse().unwrap();
+        assert_eq!(xmlrpc.specifier(), "xmlrpc-1.0.0RC3");
+    }
+
+    #[test]
+    fn test_redis_features() {
+        let mut redis: Pecl = "redis@5.3.7?with=igbinary,lz4".parse().unwrap();
+        assert_eq!(redis.specifier(), "redis-5.3.7");
+        redis.resolve(&Platform::default());
+
+        assert_eq!(
+            redis.requires().unwrap(),
+            &vec![String::from("pecl:igbinary")]
+        );
+        assert_eq!(
+            redis.configure_options_arg().unwrap(),
+            r#"enable-redis-igbinary="yes" enable-redis-lz4="yes" with-liblz4="yes""#
+        );
+    }
+
+    #[test]
+    fn test_memcached_serializers() {
+        let mut memcached: Pecl = "memcached?with=igbinary,msgpack".parse().unwrap();
+        memcached.resolve(&Platform::default());
+
+        assert_eq!(
+            memcached.requires().unwrap(),
+            &vec![String::from("pecl:igbinary"), String::from("pecl:msgpack")]
+        );
+        assert_eq!(
+            memcached.configure_options_arg().unwrap(),
+            r#"enable-memcached-igbinary="yes" enable-memcached-msgpack="yes""#
+        );
+    }
+
+    #[test]
+    fn test_swoole_features() {
+        let mut swoole: Pecl = "swoole?no-pgsql".parse().unwrap();
+        swoole.resolve(&Platform::default());
+
+        assert_eq!(
+            swoole.requires().unwrap(),
+            &vec![String::from("builtin:sockets")]
+        );
+        assert!(swoole
+            .packages()
+            .unwrap()
+            .contains(&String::from("c-ares-dev")));
+        assert!(!swoole
+            .configure_options_arg()
+            .unwrap()
+            .contains("enable-swoole-pgsql"));
+
+        let alpine = Platform::default().with_alpine_version(Some(semver::Version::new(3, 18, 0)));
+        let mut swoole: Pecl = "swoole".parse().unwrap();
+        swoole.resolve(&alpine);
+
+        let packages = swoole.packages().unwrap();
+        assert!(packages.contains(&String::from("libpq-dev")));
+        assert!(!packages.contains(&String::from("postgresql-dev")));
+    }
+
+    #[test]
+    fn test_default_version() {
+        let php81 = Platform::new(Some(semver::Version::new(8, 1, 0)));
+
+        let mut ssh2: Pecl = "ssh2".parse().unwrap();
+        ssh2.resolve(&php81);
+        assert_eq!(ssh2.specifier(), "ssh2-beta");
+
+        let mut ssh2: Pecl = "ssh2@stable".parse().unwrap();
+        ssh2.resolve(&php81);
+        assert_eq!(ssh2.specifier(), "ssh2-stable");
+    }
+
+    #[test]
+    fn test_xdebug_version_settings() {
+        let mut xdebug: Pecl = "xdebug?mode=debug".parse().unwrap();
+        xdebug.resolve(&Platform::default());
+        assert_eq!(xdebug.settings()["xdebug.mode"], "debug");
+
+        let mut xdebug: Pecl = "xdebug@2.9.8?mode=debug".parse().unwrap();
+        xdebug.resolve(&Platform::default());
+        assert!(!xdebug.settings().contains_key("xdebug.mode"));
+        assert_eq!(xdebug.settings()["xdebug.remote_enable"], "1");
+
+        let engine = Platform::default().with_host_gateway(Some("172.17.0.1"));
+        let mut xdebug: Pecl = "xdebug?mode=debug".parse().unwrap();
+        xdebug.resolve(&engine);
+        assert_eq!(xdebug.settings()["xdebug.client_host"], "172.17.0.1");
+    }
+
+    #[test]
+    fn test_pecl_http_chain() {
+        let php74 = Platform::new(Some(semver::Version::new(7, 4, 33)));
+        let php81 = Platform::new(Some(semver::Version::new(8, 1, 0)));
+
+        let mut http: Pecl = "pecl_http".parse().unwrap();
+        http.resolve(&php74);
+        assert_eq!(http.module(), "http");
+        assert_eq!(
+            http.requires().unwrap(),
+            &vec![String::from("pecl:raphf"), String::from("pecl:propro")]
+        );
+
+        let mut http: Pecl = "pecl_http".parse().unwrap();
+        http.resolve(&php81);
+        assert_eq!(http.requires().unwrap(), &vec![String::from("pecl:raphf")]);
+    }
+
+    #[test]
+    fn test_grpc_cflags() {
+        let grpc: Pecl = "grpc".parse().unwrap();
+        assert!(grpc.cflags().unwrap().contains("-D_LARGEFILE64_SOURCE"));
+
+        let redis: Pecl = "redis".parse().unwrap();
+        assert_eq!(redis.cflags(), None);
+    }
+
+    #[test]
+    fn test_source() {
+        let xdebug: Pecl = "xdebug".parse().unwrap();
+        assert_eq!(xdebug.source(), DataSource::Internal);
+
+        let unknown: Pecl = "f1_unlisted_extension".parse().unwrap();
+        assert_eq!(unknown.source(), DataSource::Unlisted);
+    }
+
+    #[test]
+    fn test_configure_options_arg() {
+        let pecl = Pecl {
+            name: String::from("redis"),
+            version: None,
+            features: Vec::new(),
+            ini_name: None,
+            sapis: Vec::new(),
+            settings: BTreeMap::new(),
+            presets: Vec::new(),
+            envs: Vec::new(),
+            enable: false,
+            xdebug: None,
+            data: PeclData {
+                configure_options: Some(vec![
+                    String::from("enable-redis-igbinary=yes"),
+                    String::from("enable-redis-lzf"),
+                    String::from(r#"with-prefix=C:\"a b""#),
+                ]),
+                ..PeclData::default()
+            },
+            source: DataSource::Unlisted,
+        };
+
+        assert_eq!(
+            pecl.configure_options_arg().unwrap(),
+            r#"enable-redis-igbinary="yes" enable-redis-lzf="yes" with-prefix="C:\\\"a b\"""#
+        );
+    }
+
+    #[test]
+    fn test_checksums() {
+        let data: PeclData = toml::from_str(
+            r#"
+            [checksums]
+            "6.0.2" = "01531a2be1e6ea5ef9d4d0e4e4a4ae0ff1c2fb5fb3d3a1e0e5b37b3ddf6d1f07"
+            "#,
+        )
+        .unwrap();
+        let mut redis: Pecl = "redis".parse().unwrap();
+        redis.data = data;
+
+        assert!(redis.checksum("6.0.2").unwrap().starts_with("01531a"));
+        assert_eq!(redis.checksum("6.0.1"), None);
+    }
+
+    #[test]
+    fn test_misspelled_condition() {
+        let tables = [
+            "[[variants]]\nalpne = \">=3.16\"\npackages = [\"libfoo-dev\"]",
+            "[[features.foo]]\narhc = [\"aarch64\"]\npackages = [\"libfoo-dev\"]",
+            "[[presets.dev]]\nphp_version = \">=8.0\"\nini = { \"foo.bar\" = \"1\" }",
+        ];
+        for table in tables {
+            let error = toml::from_str::<PeclData>(table).unwrap_err();
+            assert!(error.to_string().contains("unknown field"), "{}", error);
+        }
+
+        let data: PeclData =
+            toml::from_str("[[variants]]\narch = [\"aarch64\"]\npackages = [\"libfoo-dev\"]")
+                .unwrap();
+        assert_eq!(data.variants.len(), 1);
+    }
 }
@@ -0,0 +1,50 @@
+//! Named sets of ini settings that can be requested for an extension (e.g.,
+//! `builtin:opcache?preset=production`).
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::variant::{self, Condition};
+use crate::system::Platform;
+
+/// Ini settings written when a preset is requested and the platform matches a condition.
+///
+/// A preset is a list of these; later matching entries override earlier ones, so that
+/// version-specific values (e.g., JIT settings on PHP 8) can refine a common base.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Preset {
+    /// The platform requirements for these settings to apply.
+    #[serde(flatten)]
+    when: Condition,
+    /// The ini settings to write.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ini: BTreeMap<String, String>,
+    /// Rejects keys that aren't fields of this table (see [`variant::deny_unknown`]).
+    #[serde(flatten, deserialize_with = "variant::deny_unknown", skip_serializing)]
+    _unknown: (),
+}
+
+/// Adds the settings of each requested preset that apply to `platform` to `settings`,
+/// without replacing any the user gave explicitly.
+pub fn apply(
+    available: &BTreeMap<String, Vec<Preset>>,
+    presets: &[String],
+    platform: &Platform,
+    settings: &mut BTreeMap<String, String>,
+) {
+    let mut combined = BTreeMap::new();
+
+    let entries = presets
+        .iter()
+        .filter_map(|name| available.get(name))
+        .flatten()
+        .filter(|entry| entry.when.matches(platform));
+
+    for entry in entries {
+        combined.extend(entry.ini.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    for (key, value) in combined {
+        settings.entry(key).or_insert(value);
+    }
+}
@@ -0,0 +1,695 @@
+//! Helpers for reading and writing whole registries of extension data.
+//!
+//! The effective registry is the result of merging, in order of precedence, a registry
+//! passed in the `F1_REGISTRY_JSON` environment variable, an imported registry file (see
+//! [`REGISTRY_PATH`]), the internal registry compiled into this binary, and
+//! `F1_BUILTIN_*`/`F1_PECL_*` environment variables.
+//!
+//! Registry data decides which packages are installed, which URLs are downloaded, and
+//! which commands run, so it is trusted as much as the build itself. The internal
+//! registry is part of this binary. Whoever sets environment variables for a build can
+//! already run anything in it, so `F1_REGISTRY_JSON` and the `F1_BUILTIN_*`/`F1_PECL_*`
+//! variables are trusted as they are, unless a public key is configured (see
+//! [`crate::signature`]): then `F1_REGISTRY_JSON` must come with a minisign signature in
+//! `F1_REGISTRY_JSON_SIGNATURE`, just as imported files must come with one. The imported
+//! file's signature is kept next to it and checked every time it is loaded, so a file
+//! placed at [`REGISTRY_PATH`] (or changed) without going through `import` isn't trusted.
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{LazyLock, OnceLock},
+};
+
+use super::{
+    builtin::{self, BuiltinData},
+    pecl::{self, PeclData},
+};
+use crate::{
+    signature::{self, SignatureError},
+    system::command::{Command, CommandError},
+};
+
+/// The internal registry, assembled from `registry/*.toml` by the build script.
+const INTERNAL_REGISTRY: &str = include_str!(concat!(env!("OUT_DIR"), "/registry.toml"));
+
+/// Location of the registry file written by `f1-ext-install registry import`. The file
+/// is stored as imported (JSON or TOML), with its signature, if any, at `<path>.minisig`.
+pub const REGISTRY_PATH: &str = "/usr/local/etc/f1-ext-install/registry.json";
+
+/// Environment variable holding a complete supplemental registry as JSON.
+pub const REGISTRY_ENV: &str = "F1_REGISTRY_JSON";
+
+/// Environment variable holding the minisign signature of [`REGISTRY_ENV`], which is
+/// required when a public key is configured.
+pub const REGISTRY_SIGNATURE_ENV: &str = "F1_REGISTRY_JSON_SIGNATURE";
+
+/// Environment variable holding the minisign public key that registries are checked
+/// against, if no key was compiled into this binary.
+pub const PUBLIC_KEY_ENV: &str = "F1_REGISTRY_PUBLIC_KEY";
+
+/// Where an extension's registry data was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataSource {
+    /// The supplemental registry (an imported file or `F1_REGISTRY_JSON`).
+    Supplemental,
+    /// The internal registry compiled into this binary.
+    Internal,
+    /// `F1_BUILTIN_<NAME>_*` or `F1_PECL_<NAME>_*` environment variables.
+    Environment,
+    /// Nowhere: the extension is installed with no extra packages or options.
+    Unlisted,
+}
+
+impl fmt::Display for DataSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Supplemental => write!(f, "supplemental registry"),
+            Self::Internal => write!(f, "internal registry"),
+            Self::Environment => write!(f, "environment variables"),
+            Self::Unlisted => write!(f, "none (not in any registry)"),
+        }
+    }
+}
+
+/// Determines if any environment variable starts with `prefix` (e.g., `F1_PECL_REDIS_`).
+pub(super) fn has_env_prefix(prefix: &str) -> bool {
+    env::vars_os().any(|(key, _)| key.to_string_lossy().starts_with(prefix))
+}
+
+/// Suffixes of the environment variables that configure builtins.
+///
+/// Longer suffixes are listed first so that, e.g., `_RUNTIME_PACKAGES` isn't mistaken
+/// for `_PACKAGES`.
+const BUILTIN_ENV_SUFFIXES: &[&str] = &[
+    "_RUNTIME_PACKAGES",
+    "_REPOSITORIES",
+    "_PACKAGES",
+    "_CONFIGURE_CMD",
+    "_REQUIRES",
+    "_PATCHES",
+    "_REPLACED_BY",
+];
+
+/// Suffixes of the environment variables that configure PECL extensions.
+const PECL_ENV_SUFFIXES: &[&str] = &[
+    "_RUNTIME_PACKAGES",
+    "_REPOSITORIES",
+    "_PACKAGE_URLS",
+    "_PACKAGES",
+    "_VERSION",
+    "_DISABLED",
+    "_MODULE",
+    "_INI_DIR",
+    "_INI_NAME",
+    "_CONFIGURE_OPTIONS",
+    "_SOURCE_URL",
+    "_CFLAGS",
+    "_REQUIRES",
+    "_PATCHES",
+];
+
+/// The registry loaded from [`REGISTRY_PATH`] and [`REGISTRY_ENV`], if either was present.
+static SUPPLEMENTAL: OnceLock<Registry> = OnceLock::new();
+
+/// The registry built into this binary.
+///
+/// The build script has already checked that the registry is valid TOML, so parsing can
+/// only fail if an entry doesn't match the shape of the registry data types. The unit
+/// tests below catch that case.
+static INTERNAL: LazyLock<Registry> =
+    LazyLock::new(|| Registry::parse(INTERNAL_REGISTRY, Format::Toml).unwrap());
+
+/// Errors encountered while reading or writing registries.
+#[derive(Debug, Snafu)]
+pub enum RegistryError {
+    /// A registry file could not be read.
+    #[snafu(display("Failed to read registry {}: {}", path.display(), source))]
+    Read {
+        /// The underlying IO error
+        source: io::Error,
+        /// The file being read
+        path: PathBuf,
+    },
+
+    /// A registry or its signature could not be downloaded.
+    #[snafu(display("Failed to download registry {}: {}", url, source))]
+    Download {
+        /// The underlying command error
+        source: CommandError,
+        /// The URL being downloaded
+        url: String,
+    },
+
+    /// A registry file could not be written.
+    #[snafu(display("Failed to write registry {}: {}", path.display(), source))]
+    Write {
+        /// The underlying IO error
+        source: io::Error,
+        /// The file being written
+        path: PathBuf,
+    },
+
+    /// A registry was not valid JSON.
+    #[snafu(display("Invalid JSON registry: {}", source))]
+    Json {
+        /// The underlying JSON error
+        source: serde_json::Error,
+    },
+
+    /// The registry in `F1_REGISTRY_JSON` was not valid JSON.
+    #[snafu(display("Invalid JSON registry in ${}: {}", REGISTRY_ENV, source))]
+    EnvJson {
+        /// The underlying JSON error
+        source: serde_json::Error,
+    },
+
+    /// A registry was not valid TOML.
+    #[snafu(display("Invalid TOML registry: {}", source))]
+    TomlRead {
+        /// The underlying TOML error
+        source: toml::de::Error,
+    },
+
+    /// A registry could not be represented as TOML.
+    #[snafu(display("Failed to write TOML registry: {}", source))]
+    TomlWrite {
+        /// The underlying TOML error
+        source: toml::ser::Error,
+    },
+
+    /// A registry file failed signature verification.
+    #[snafu(display("Refusing to use registry {}: {}", path.display(), source))]
+    Signature {
+        /// The underlying verification error
+        source: SignatureError,
+        /// The registry file being verified
+        path: PathBuf,
+    },
+
+    /// The registry in `F1_REGISTRY_JSON` failed signature verification.
+    #[snafu(display("Refusing to use the registry in ${}: {}", REGISTRY_ENV, source))]
+    EnvSignature {
+        /// The underlying verification error
+        source: SignatureError,
+    },
+
+    /// A public key is configured, but `F1_REGISTRY_JSON` has no signature.
+    #[snafu(display(
+        "A registry public key is configured, so ${} needs a signature in ${}",
+        REGISTRY_ENV,
+        REGISTRY_SIGNATURE_ENV
+    ))]
+    MissingEnvSignature,
+
+    /// A public key is configured, but no signature was found for a registry file.
+    #[snafu(display(
+        "Registry {} has no signature (expected {} or --signature)",
+        path.display(),
+        expected.display()
+    ))]
+    MissingSignature {
+        /// The registry file being verified
+        path: PathBuf,
+        /// The default signature location that was checked
+        expected: PathBuf,
+    },
+
+    /// A public key is configured, but the imported registry file has no signature, e.g.
+    /// because it was imported without one or copied into place.
+    #[snafu(display(
+        "A registry public key is configured, but {} has no signature at {}; import it \
+         again with its signature",
+        path.display(),
+        expected.display()
+    ))]
+    UnsignedImport {
+        /// The imported registry file
+        path: PathBuf,
+        /// Where its signature was expected
+        expected: PathBuf,
+    },
+
+    /// A signature was provided without any public key to check it against.
+    #[snafu(display("Cannot verify a registry signature without a public key"))]
+    MissingPublicKey,
+
+    /// The requested registry format is not supported.
+    #[snafu(display(r#"Unknown registry format "{}" (expected "json" or "toml")"#, format))]
+    UnknownFormat {
+        /// The format name that was given
+        format: String,
+    },
+}
+
+/// Helper type for registry results.
+pub type Result<T> = std::result::Result<T, RegistryError>;
+
+/// The file formats a registry can be read from or written to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// JSON (the format of the imported registry file).
+    Json,
+    /// TOML, which is generally easier to review by hand.
+    Toml,
+}
+
+impl Format {
+    /// Guesses the format of a file from its extension, defaulting to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+
+    /// Guesses the format of a registry from its contents: a JSON registry is an object,
+    /// and a TOML document can't start with `{`.
+    pub fn detect(contents: &str) -> Self {
+        if contents.trim_start().starts_with('{') {
+            Self::Json
+        } else {
+            Self::Toml
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = RegistryError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            _ => Err(RegistryError::UnknownFormat {
+                format: String::from(input),
+            }),
+        }
+    }
+}
+
+/// A complete set of builtin and PECL extension data, keyed by extension name.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Registry {
+    /// Data for builtin extensions.
+    #[serde(default)]
+    pub(super) builtin: BTreeMap<String, BuiltinData>,
+
+    /// Data for PECL extensions.
+    #[serde(default)]
+    pub(super) pecl: BTreeMap<String, PeclData>,
+}
+
+/// Determines if `source` names a registry (or signature) by URL rather than by path.
+fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// Reads a registry or signature from a file path or an `http(s)` URL.
+fn read_source(source: &str) -> Result<String> {
+    if !is_url(source) {
+        return fs::read_to_string(source).context(Read { path: source });
+    }
+
+    let mut command = Command::new("curl");
+    command.args(["-fsSL", source]);
+    command.downloads();
+    command.stdout().context(Download { url: source })
+}
+
+/// Returns where the signature of the registry file at `path` is stored.
+fn signature_path(path: &Path) -> PathBuf {
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".minisig");
+    PathBuf::from(signature)
+}
+
+/// Finds the extension names mentioned by environment variables of the form
+/// `<prefix><NAME><suffix>`, lowercasing them to match the CLI syntax.
+fn env_names(prefix: &str, suffixes: &[&str]) -> BTreeSet<String> {
+    env::vars()
+        .filter_map(|(key, _)| {
+            let rest = key.strip_prefix(prefix)?;
+            let name = suffixes
+                .iter()
+                .find_map(|suffix| rest.strip_suffix(suffix))?;
+            Some(name.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+impl Registry {
+    /// Parses a registry in the given format.
+    pub fn parse(input: &str, format: Format) -> Result<Self> {
+        match format {
+            Format::Json => serde_json::from_str(input).context(Json),
+            Format::Toml => toml::from_str(input).context(TomlRead),
+        }
+    }
+
+    /// Reads a registry from a file, using the file extension to determine its format.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).context(Read { path })?;
+        Self::parse(&contents, Format::from_path(path))
+    }
+
+    /// Serializes this registry in the given format.
+    pub fn to_string(&self, format: Format) -> Result<String> {
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).context(Json),
+            Format::Toml => toml::to_string_pretty(self).context(TomlWrite),
+        }
+    }
+
+    /// Adds every entry of `other` to this registry, replacing entries with the same name.
+    fn merge(&mut self, other: Registry) {
+        self.builtin.extend(other.builtin);
+        self.pecl.extend(other.pecl);
+    }
+
+    /// Loads the supplemental registry from [`REGISTRY_PATH`] and [`REGISTRY_ENV`] so that
+    /// it takes part in extension lookups. Entries from the environment take precedence
+    /// over entries from the imported file.
+    ///
+    /// This needs to be called before any extensions are parsed.
+    pub fn load_supplemental() -> Result<()> {
+        let mut supplemental = Registry::default();
+        let public_key = env::var(PUBLIC_KEY_ENV).ok();
+        let public_key = public_key.as_deref().or(signature::BUILTIN_PUBLIC_KEY);
+
+        let path = Path::new(REGISTRY_PATH);
+        if path.exists() {
+            supplemental.merge(Self::from_imported_file(path, public_key)?);
+        }
+
+        if let Ok(json) = env::var(REGISTRY_ENV) {
+            if !json.trim().is_empty() {
+                let signature = env::var(REGISTRY_SIGNATURE_ENV).ok();
+                supplemental.merge(Self::from_env_json(
+                    &json,
+                    signature.as_deref(),
+                    public_key,
+                )?);
+            }
+        }
+
+        let _ = SUPPLEMENTAL.set(supplemental);
+
+        Ok(())
+    }
+
+    /// Reads the registry installed by [`Registry::import`] at `path`, first checking its
+    /// signature (at `<path>.minisig`) if a `public_key` is configured.
+    fn from_imported_file(path: &Path, public_key: Option<&str>) -> Result<Self> {
+        let contents = fs::read_to_string(path).context(Read { path })?;
+
+        if let Some(public_key) = public_key {
+            let expected = signature_path(path);
+            if !expected.exists() {
+                return Err(RegistryError::UnsignedImport {
+                    path: path.to_path_buf(),
+                    expected,
+                });
+            }
+
+            let signature = fs::read_to_string(&expected).context(Read { path: &expected })?;
+            signature::verify(contents.as_bytes(), &signature, public_key)
+                .context(Signature { path })?;
+        }
+
+        Self::parse(&contents, Format::detect(&contents))
+    }
+
+    /// Parses the registry passed in [`REGISTRY_ENV`], first checking its `signature`
+    /// if a `public_key` is configured.
+    fn from_env_json(
+        json: &str,
+        signature: Option<&str>,
+        public_key: Option<&str>,
+    ) -> Result<Self> {
+        if let Some(public_key) = public_key {
+            let signature = signature.ok_or(RegistryError::MissingEnvSignature)?;
+            signature::verify(json.as_bytes(), signature, public_key).context(EnvSignature)?;
+        }
+
+        serde_json::from_str(json).context(EnvJson)
+    }
+
+    /// Returns the internal registry compiled into this binary.
+    pub(super) fn internal() -> &'static Registry {
+        &INTERNAL
+    }
+
+    /// Returns the supplemental registry, if one was loaded.
+    pub(super) fn supplemental() -> Option<&'static Registry> {
+        SUPPLEMENTAL.get()
+    }
+
+    /// Determines if the supplemental or internal registry lists `name` as a PECL
+    /// extension but not as a builtin.
+    pub(super) fn is_pecl_only(name: &str) -> bool {
+        let registries = Self::supplemental().into_iter().chain([Self::internal()]);
+        let (builtin, pecl) = registries.fold((false, false), |(builtin, pecl), registry| {
+            (
+                builtin || registry.builtin.contains_key(name),
+                pecl || registry.pecl.contains_key(name),
+            )
+        });
+
+        pecl && !builtin
+    }
+
+    /// Validates the registry at `source` (a file path or an `http(s)` URL) and installs it
+    /// to [`REGISTRY_PATH`], where it will be consulted by later invocations.
+    ///
+    /// If a public key is available (either `public_key` or the key compiled into this
+    /// binary), the registry must have a valid minisign signature, read from `signature`
+    /// (also a path or URL) or from `<source>.minisig` by default. The signature is
+    /// installed next to the registry, which is stored byte for byte so that it can be
+    /// checked again whenever it is loaded.
+    pub fn import(source: &str, signature: Option<&str>, public_key: Option<&str>) -> Result<()> {
+        let contents = read_source(source)?;
+
+        let public_key = public_key.or(signature::BUILTIN_PUBLIC_KEY);
+        let expected = format!("{}.minisig", source);
+
+        let signature_text = match (public_key, signature) {
+            (Some(public_key), signature) => {
+                let signature_source = signature.unwrap_or(&expected);
+                if !is_url(signature_source) && !Path::new(signature_source).exists() {
+                    return Err(RegistryError::MissingSignature {
+                        path: PathBuf::from(source),
+                        expected: PathBuf::from(expected),
+                    });
+                }
+
+                let signature_text = read_source(signature_source)?;
+                signature::verify(contents.as_bytes(), &signature_text, public_key)
+                    .context(Signature { path: source })?;
+
+                Some(signature_text)
+            }
+            (None, Some(_)) => return Err(RegistryError::MissingPublicKey),
+            (None, None) => None,
+        };
+
+        let format = if is_url(source) {
+            Format::detect(&contents)
+        } else {
+            Format::from_path(Path::new(source))
+        };
+        Self::parse(&contents, format)?;
+
+        let target = Path::new(REGISTRY_PATH);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).context(Write { path: parent })?;
+        }
+
+        // A signature left over from an earlier import doesn't cover this registry.
+        let target_signature = signature_path(target);
+        match signature_text {
+            Some(signature_text) => {
+                fs::write(&target_signature, signature_text).context(Write {
+                    path: &target_signature,
+                })?
+            }
+            None if target_signature.exists() => {
+                fs::remove_file(&target_signature).context(Write {
+                    path: &target_signature,
+                })?
+            }
+            None => {}
+        }
+
+        fs::write(target, contents).context(Write { path: target })
+    }
+
+    /// Computes the effective registry: every extension known to the supplemental
+    /// registry, the internal registry, or the environment, with the data that would be
+    /// used to install it.
+    pub fn effective() -> Self {
+        let internal = Self::internal();
+
+        let mut builtin_names = env_names("F1_BUILTIN_", BUILTIN_ENV_SUFFIXES);
+        builtin_names.extend(internal.builtin.keys().cloned());
+
+        let mut pecl_names = env_names("F1_PECL_", PECL_ENV_SUFFIXES);
+        pecl_names.extend(internal.pecl.keys().cloned());
+
+        if let Some(supplemental) = Self::supplemental() {
+            builtin_names.extend(supplemental.builtin.keys().cloned());
+            pecl_names.extend(supplemental.pecl.keys().cloned());
+        }
+
+        let builtin = builtin_names
+            .into_iter()
+            .map(|name| {
+                let (data, _) = builtin::find_builtin_data(&name);
+                (name, data)
+            })
+            .collect();
+
+        let pecl = pecl_names
+            .into_iter()
+            .map(|name| {
+                let (data, _) = pecl::find_pecl_data(&name);
+                (name, data)
+            })
+            .collect();
+
+        Registry { builtin, pecl }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_registry() {
+        let internal = Registry::internal();
+        assert!(
+            internal.builtin.contains_key("gd"),
+            "gd should be a builtin"
+        );
+        assert!(
+            internal.pecl.contains_key("xdebug"),
+            "xdebug should be a PECL extension"
+        );
+    }
+
+    #[test]
+    fn test_env_json_signature() {
+        let json = r#"{"pecl":{"mcrypt":{"packages":["libmcrypt-dev"]}}}"#;
+        let registry = Registry::from_env_json(json, None, None).unwrap();
+        assert!(registry.pecl.contains_key("mcrypt"));
+
+        // Public key from the minisign-verify documentation
+        let public_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+        assert!(matches!(
+            Registry::from_env_json(json, None, Some(public_key)),
+            Err(RegistryError::MissingEnvSignature)
+        ));
+        assert!(matches!(
+            Registry::from_env_json(json, Some("not a signature"), Some(public_key)),
+            Err(RegistryError::EnvSignature { .. })
+        ));
+    }
+
+    #[test]
+    fn test_imported_file_signature() {
+        let dir = env::temp_dir().join(format!("f1-registry-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("registry.json");
+        fs::write(&path, "[pecl.mcrypt]\npackages = [\"libmcrypt-dev\"]\n").unwrap();
+
+        let registry = Registry::from_imported_file(&path, None).unwrap();
+        assert!(registry.pecl.contains_key("mcrypt"));
+
+        // A file copied into place without its signature isn't trusted.
+        let public_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+        assert!(matches!(
+            Registry::from_imported_file(&path, Some(public_key)),
+            Err(RegistryError::UnsignedImport { .. })
+        ));
+
+        fs::write(signature_path(&path), "not a signature").unwrap();
+        assert!(matches!(
+            Registry::from_imported_file(&path, Some(public_key)),
+            Err(RegistryError::Signature { .. })
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(Format::detect("\n  { \"pecl\": {} }"), Format::Json);
+        assert_eq!(Format::detect("# comment\n[pecl.foo]\n"), Format::Toml);
+    }
+
+    #[test]
+    fn test_round_trip_json() {
+        let registry = Registry::effective();
+        let json = registry.to_string(Format::Json).unwrap();
+        let parsed = Registry::parse(&json, Format::Json).unwrap();
+
+        assert_eq!(
+            registry.builtin.keys().collect::<Vec<_>>(),
+            parsed.builtin.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_round_trip_toml() {
+        let registry = Registry::effective();
+        let toml = registry.to_string(Format::Toml).unwrap();
+        let parsed = Registry::parse(&toml, Format::Toml).unwrap();
+
+        assert_eq!(
+            registry.pecl.keys().collect::<Vec<_>>(),
+            parsed.pecl.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let input = r#"
+            [builtin.ffi]
+            packages = ["libffi-dev"]
+            configure_cmd = ["--with-ffi"]
+
+            [[builtin.ffi.variants]]
+            php = "<7.4"
+            packages = []
+        "#;
+
+        let registry = Registry::parse(input, Format::Toml).unwrap();
+        assert!(registry.builtin.contains_key("ffi"));
+    }
+
+    #[test]
+    fn test_merge_replaces() {
+        let mut base = Registry::parse(
+            r#"{ "pecl": { "foo": { "packages": ["foo-dev"] } } }"#,
+            Format::Json,
+        )
+        .unwrap();
+
+        let overlay = Registry::parse(
+            r#"{ "pecl": { "foo": { "packages": ["libfoo-dev"] } } }"#,
+            Format::Json,
+        )
+        .unwrap();
+
+        base.merge(overlay);
+
+        let merged = base.to_string(Format::Json).unwrap();
+        assert!(merged.contains("libfoo-dev"), "overlay should replace foo");
+        assert!(!merged.contains("\"foo-dev"), "base foo should be replaced");
+    }
+}
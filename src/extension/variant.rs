@@ -0,0 +1,155 @@
+//! Conditions used to select platform-specific variants of registry entries.
+
+use semver::{Version, VersionReq};
+use serde::{
+    de::{Error, IgnoredAny},
+    Deserialize, Deserializer, Serialize,
+};
+use std::collections::BTreeMap;
+
+use crate::system::Platform;
+
+/// A set of requirements that the current platform must meet in order for a registry
+/// variant to apply.
+///
+/// An empty condition matches every platform, so unknown keys (e.g., a misspelled
+/// `alpne`) are rejected rather than ignored.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// The PHP versions this variant applies to (e.g., `"<7.4"` or `">=8.0, <8.4"`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    php: Option<VersionReq>,
+
+    /// The Alpine releases this variant applies to (e.g., `">=3.16"`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alpine: Option<VersionReq>,
+
+    /// The CPU architectures this variant applies to (e.g., `["aarch64"]`). Docker's
+    /// names (`amd64`, `arm64`) are accepted as aliases.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arch: Option<Vec<String>>,
+}
+
+/// Rejects the keys of a table that neither it nor its flattened [`Condition`] knows.
+///
+/// serde can't combine `deny_unknown_fields` with `flatten`, so tables that embed a
+/// condition collect the leftover keys with a flattened field using this instead.
+pub fn deny_unknown<'de, D>(deserializer: D) -> Result<(), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let unknown = BTreeMap::<String, IgnoredAny>::deserialize(deserializer)?;
+    match unknown.keys().next() {
+        Some(key) => Err(D::Error::custom(format!("unknown field `{}`", key))),
+        None => Ok(()),
+    }
+}
+
+/// Checks an optional version requirement against an optional detected version. An
+/// absent requirement always matches; a requirement against an unknown version never does.
+fn requirement_matches(requirement: Option<&VersionReq>, version: Option<&Version>) -> bool {
+    match (requirement, version) {
+        (None, _) => true,
+        (Some(requirement), Some(version)) => requirement.matches(version),
+        (Some(_), None) => false,
+    }
+}
+
+/// Maps Docker's architecture names onto the names used by Rust and Alpine.
+fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "amd64" => "x86_64",
+        "arm64" | "arm64v8" => "aarch64",
+        "386" | "i386" => "x86",
+        other => other,
+    }
+}
+
+/// Checks an optional architecture list against an optional detected architecture. As with
+/// versions, an absent list always matches and an unknown architecture never does.
+fn arch_matches(allowed: Option<&Vec<String>>, arch: Option<&str>) -> bool {
+    match (allowed, arch) {
+        (None, _) => true,
+        (Some(allowed), Some(arch)) => allowed
+            .iter()
+            .any(|allowed| normalize_arch(allowed) == normalize_arch(arch)),
+        (Some(_), None) => false,
+    }
+}
+
+impl Condition {
+    /// Determines if the given platform satisfies this condition.
+    ///
+    /// If a requirement is present but the corresponding platform version is unknown,
+    /// the condition does not match.
+    pub fn matches(&self, platform: &Platform) -> bool {
+        requirement_matches(self.php.as_ref(), platform.php_version())
+            && requirement_matches(self.alpine.as_ref(), platform.alpine_version())
+            && arch_matches(self.arch.as_ref(), platform.arch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a condition from its TOML representation.
+    fn condition(input: &str) -> Condition {
+        toml::from_str(input).unwrap()
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        assert!(toml::from_str::<Condition>(r#"alpne = ">=3.16""#).is_err());
+    }
+
+    #[test]
+    fn test_empty_matches() {
+        assert!(Condition::default().matches(&Platform::default()));
+    }
+
+    #[test]
+    fn test_php_requirement() {
+        let condition = condition(r#"php = "<7.4""#);
+
+        let php73 = Platform::new(Some(Version::new(7, 3, 33)));
+        let php74 = Platform::new(Some(Version::new(7, 4, 0)));
+
+        assert!(condition.matches(&php73), "<7.4 should match 7.3.33");
+        assert!(!condition.matches(&php74), "<7.4 should not match 7.4.0");
+        assert!(
+            !condition.matches(&Platform::default()),
+            "<7.4 should not match an unknown version"
+        );
+    }
+
+    #[test]
+    fn test_alpine_requirement() {
+        let condition = condition(r#"alpine = ">=3.16""#);
+
+        let old = Platform::default().with_alpine_version(Some(Version::new(3, 15, 4)));
+        let new = Platform::default().with_alpine_version(Some(Version::new(3, 18, 0)));
+
+        assert!(
+            !condition.matches(&old),
+            ">=3.16 should not match Alpine 3.15"
+        );
+        assert!(condition.matches(&new), ">=3.16 should match Alpine 3.18");
+    }
+
+    #[test]
+    fn test_arch_requirement() {
+        let condition = condition(r#"arch = ["arm64"]"#);
+
+        let amd64 = Platform::default().with_arch(Some("x86_64"));
+        let arm64 = Platform::default().with_arch(Some("aarch64"));
+
+        assert!(!condition.matches(&amd64), "arm64 should not match x86_64");
+        assert!(condition.matches(&arm64), "arm64 should match aarch64");
+        assert!(
+            !condition.matches(&Platform::default()),
+            "arm64 should not match an unknown architecture"
+        );
+    }
+}
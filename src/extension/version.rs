@@ -3,17 +3,25 @@
 use std::fmt;
 
 /// Represents a PECL version.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub enum Version {
     /// The `stable` version/channel.
+    #[default]
     Stable,
+    /// The `beta` channel: the latest release that is at least beta quality.
+    Beta,
     /// A specific version (in MAJOR.MINOR.PATCH format).
     Custom(String),
 }
 
-impl Default for Version {
-    fn default() -> Self {
-        Self::Stable
+impl Version {
+    /// Interprets `input` as a channel name (`stable` or `beta`) or a specific version.
+    pub fn parse(input: &str) -> Self {
+        match input {
+            "stable" => Self::Stable,
+            "beta" => Self::Beta,
+            _ => Self::Custom(String::from(input)),
+        }
     }
 }
 
@@ -21,6 +29,7 @@ impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Stable => write!(f, "stable"),
+            Self::Beta => write!(f, "beta"),
             Self::Custom(version) => write!(f, "{}", version),
         }
     }
@@ -43,8 +43,15 @@
 #![deny(rust_2018_compatibility)]
 #![deny(rust_2018_idioms)]
 #![deny(unused)]
-#![deny(rustdoc)]
+#![deny(rustdoc::all)]
 #![warn(clippy::missing_docs_in_private_items)]
 
+pub mod color;
+pub mod exit;
 pub mod extension;
+pub mod manifest;
+pub mod progress;
+pub mod report;
+pub mod sbom;
+pub mod signature;
 pub mod system;
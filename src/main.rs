@@ -1,14 +1,171 @@
-use anyhow::Result;
-use structopt::StructOpt;
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn, Level, LevelFilter};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    io::{self, Write as _},
+    iter, mem,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant},
+};
+use structopt::{
+    clap::{AppSettings, ArgMatches},
+    StructOpt,
+};
 
 use f1_ext_install::{
-    extension::Extension,
-    system::{self, Apk},
+    color::{self, ColorChoice, Style},
+    exit::{self, Stage},
+    extension::{self, Extension, Format, ParseError, Pecl, Registry, Version},
+    manifest::{self, Invocation, Manifest, MANIFEST_PATH},
+    progress::Progress,
+    report::{
+        self, FailedExtension, InstalledExtension, ModuleChanges, OutputFormat, Report, Sizes,
+        SkippedExtension,
+    },
+    sbom::{self, Component, ComponentKind, SbomFormat},
+    system::{
+        self,
+        command::{self, Mode},
+        disk,
+        imagick::{self, PdfPolicy},
+        ini::{self, PhpIni},
+        instantclient, jobs, source,
+        staging::Staging,
+        vendor::{self, PackageFile},
+        Apk, Platform, PreflightRepositories,
+    },
 };
 
+/// Subcommands for inspecting and managing the extension registry.
+#[derive(StructOpt, Debug)]
+enum RegistryCommand {
+    /// Print the effective registry (F1_REGISTRY_JSON, imported file, internal registry,
+    /// and environment variables merged together).
+    Export {
+        /// The output format: either `json` or `toml`.
+        #[structopt(long, default_value = "json")]
+        format: Format,
+    },
+
+    /// Validate a JSON or TOML registry file and install it for use by later invocations.
+    ///
+    /// Entries in an imported registry take precedence over the internal registry. If a
+    /// public key is configured, the file must carry a valid minisign signature, which is
+    /// installed with it and checked again every time the registry is loaded.
+    Import {
+        /// Path or `http(s)` URL of the registry file. Files ending in `.toml` are read as
+        /// TOML and all others as JSON; downloads are recognized by their contents.
+        source: String,
+
+        /// Path or URL of the detached minisign signature of the registry file (defaults to
+        /// `<source>.minisig`).
+        #[structopt(long)]
+        signature: Option<String>,
+
+        /// Base64-encoded minisign public key used to verify the registry signature.
+        #[structopt(long, env = "F1_REGISTRY_PUBLIC_KEY")]
+        public_key: Option<String>,
+    },
+}
+
+/// Subcommands that can be used in place of a list of extensions.
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Inspect or manage the extension registry.
+    Registry(RegistryCommand),
+
+    /// Load extensions that are installed but not enabled (e.g., `enable xdebug` in an image
+    /// derived from one that installed Xdebug), without building anything.
+    ///
+    /// Extensions are named as in `config:` specs, and accept the same modifiers (e.g.,
+    /// `enable 'xdebug?mode=debug'`).
+    Enable {
+        /// The extensions to enable.
+        #[structopt(required = true, min_values(1), parse(try_from_str = parse_installed))]
+        extensions: Vec<Extension>,
+
+        /// Don't write the registry's default ini settings for the extensions.
+        #[structopt(long)]
+        no_ini: bool,
+    },
+
+    /// Uninstall extensions: remove their shared modules (and headers) and the ini files
+    /// that load and configure them. Fails for an extension that none of these are found
+    /// for, such as one compiled into PHP.
+    ///
+    /// Extensions are named as in `config:` specs (e.g., `remove xdebug`).
+    #[structopt(alias = "uninstall")]
+    Remove {
+        /// The extensions to remove.
+        #[structopt(required = true, min_values(1), parse(try_from_str = parse_installed))]
+        extensions: Vec<Extension>,
+
+        /// Also drop runtime packages that the remaining extensions no longer need from
+        /// the `.docker-phpexts-rundeps` virtual package: libraries that nothing in
+        /// `/usr/local` links against anymore, and packages that no extension still
+        /// recorded as installed declares, unless they provide a library still linked.
+        #[structopt(long)]
+        prune_packages: bool,
+
+        /// Print the files that would be removed and the commands that would be run,
+        /// instead of removing or running anything.
+        #[structopt(long)]
+        dry_run: bool,
+    },
+
+    /// Explain what installing extensions would involve, without installing anything:
+    /// where each one's registry data came from, the packages it pulls in, its configure
+    /// arguments, and whether (and from where) it will be loaded.
+    ///
+    /// Extensions are named as for an install, and extensions they require are included.
+    #[structopt(alias = "plan")]
+    Explain {
+        /// The extensions to explain.
+        #[structopt(required = true, min_values(1))]
+        extensions: Vec<Extension>,
+    },
+
+    /// Print a POSIX shell script with the commands that installing the given extensions
+    /// would run (e.g., `emit-script pecl:redis builtin:gd`), for review without reading
+    /// this tool's source. Files that would be written appear as here-documents.
+    ///
+    /// The arguments are the same as for an install, including options like `--ini`.
+    #[structopt(setting = AppSettings::TrailingVarArg)]
+    EmitScript {
+        /// The install's arguments.
+        #[structopt(allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// List the extensions that this tool has installed in the image (in this or earlier
+    /// layers) and not since removed, with their versions and when they were installed.
+    ///
+    /// Every invocation is recorded in /usr/local/etc/f1-ext-install/manifest.json.
+    List,
+
+    /// Stop PHP from loading extensions that the base image enables (e.g., `disable
+    /// xdebug`), by removing or commenting out the ini lines that load them.
+    ///
+    /// Extensions are named as PHP loads them (e.g., `http` for `pecl:pecl_http`). Files
+    /// in `conf.d` and any `conf.d-<sapi>` directories are searched.
+    Disable {
+        /// The extensions to disable.
+        #[structopt(required = true, min_values(1))]
+        extensions: Vec<String>,
+    },
+}
+
+/// Parses the name of an extension that is already installed, following the rules of
+/// `config:` specs.
+fn parse_installed(spec: &str) -> Result<Extension, ParseError> {
+    format!("config:{}", spec).parse()
+}
+
 /// Command-line options provided to `f1-ext-install`.
 #[derive(StructOpt, Debug)]
-#[structopt(about)]
+#[structopt(about, setting = AppSettings::ArgsNegateSubcommands)]
 struct Opts {
     /// The extensions to install during this execution.
     ///
@@ -16,53 +173,2446 @@ struct Opts {
     ///
     /// * `builtin:<name>` - install the named PHP builtin
     ///
+    /// * `builtin:<name>?with=<a>,<b>` - also enable the optional features `a` and `b`
+    ///   (`without=<a>` or `no-<a>` disables one; join modifiers with `&`)
+    ///
     /// * `pecl:<name>` - install the latest stable version of the named PECL extension
+    ///   (or the registry's default version, for extensions with one)
     ///
     /// * `pecl:<name>@stable` - explicitly use the stable channel
     ///
+    /// * `pecl:<name>@beta` - use the beta channel (the latest release that is at least
+    ///   beta quality)
+    ///
     /// * `pecl:<name>@<version>` - install a specific version (in MAJOR.MINOR.PATCH) format
-    #[structopt(min_values(1))]
+    ///
+    /// * `config:<name>` - don't install anything, but write the ini settings for an
+    ///   extension that is already present (e.g., `config:opcache?preset=production`);
+    ///   the name is a builtin unless the registry only lists it on PECL, and
+    ///   `config:pecl:<name>` or `config:builtin:<name>` can be used to be explicit
+    ///
+    /// Either kind accepts feature modifiers after a `?` (e.g., `pecl:redis?with=igbinary`).
+    ///
+    /// The ini file that loads an extension can be named with `?priority=<prefix>` (e.g.,
+    /// `builtin:opcache?priority=10` writes `10-opcache.ini`) or `?ini-name=<file>`, since
+    /// PHP loads them in alphabetical order. `?sapi=cli` (or `fpm` or `apache`) loads an
+    /// extension from `$PHP_INI_DIR/conf.d-cli` instead of the shared `conf.d`, for
+    /// processes that add that directory to `PHP_INI_SCAN_DIR`.
+    ///
+    /// Ini directives can be attached with `?ini=<key>=<value>` (e.g.,
+    /// `builtin:opcache?ini=opcache.memory_consumption=256`) or with `--ini`, and sets
+    /// of them from the registry with `?preset=<name>` (e.g.,
+    /// `builtin:opcache?preset=production`); explicit directives override a preset's.
+    /// Values may refer to environment variables as `${VAR}` or `${VAR:-default}`, which
+    /// are substituted when the ini file is written.
+    ///
+    /// `pecl:xdebug?mode=debug,develop` enables Xdebug with those modes and, for step
+    /// debugging, `xdebug.client_host=host.docker.internal`, or the default gateway if that
+    /// doesn't resolve during the build (`&client-host=<host>` names another host, and
+    /// `&client-host=gateway` adds `xdebug.discover_client_host=1`, so that Xdebug connects
+    /// back to each HTTP request's address). Xdebug 2 (e.g., `pecl:xdebug@2.9.8`) gets the
+    /// `remote_*` settings instead.
+    #[structopt(name = "extensions", min_values(1))]
+    specs: Vec<String>,
+
+    /// The extensions parsed from `specs` (see [`parse_extensions`]).
+    #[structopt(skip)]
     extensions: Vec<Extension>,
+
+    /// The environment being built (e.g., `dev` or `prod`). Extensions tagged with
+    /// `?env=<name>` are only installed when it matches; untagged ones always are.
+    #[structopt(long, env = "F1_ENV", value_name = "name")]
+    env: Option<String>,
+
+    /// Show more output: `-v` prints each command as it runs, and `-vv` also streams the
+    /// output of the commands (e.g., compiler output), which is otherwise only shown if a
+    /// command fails.
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u8,
+
+    /// Print the commands that would be run (`apk`, `pecl`, `docker-php-ext-*`, etc.) to
+    /// stdout instead of running them. Other steps, like writing ini files, are printed
+    /// as shell comments.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Carry on past an extension that fails to build or enable, install the rest, and
+    /// then fail listing every extension that failed (e.g., for canary builds of the whole
+    /// registry). Builtins are built one at a time, so that one failure doesn't stop the
+    /// others.
+    #[structopt(long)]
+    keep_going: bool,
+
+    /// Also check that PHP can load the extensions its CLI doesn't: those loaded only for
+    /// some SAPIs (scanning their directories as those SAPIs would) and those installed
+    /// without being enabled (loading them explicitly). Extensions the CLI loads are
+    /// always checked.
+    #[structopt(long)]
+    require_loaded: bool,
+
+    /// Refuse to install PECL extensions without a SHA-256 digest pinned in the registry
+    /// (under `checksums`) for the version downloaded. Pinned digests are always checked,
+    /// but the internal registry pins none, so without this flag (and an imported registry
+    /// that pins digests) unpinned downloads are only reported, with their digests.
+    #[structopt(long)]
+    require_checksums: bool,
+
+    /// The packages providing the C compiler and other build tools (separated by spaces),
+    /// for images that don't set `PHPIZE_DEPS` the way the official php images do (e.g.,
+    /// `--phpize-deps "autoconf dpkg-dev dpkg file g++ gcc libc-dev make pkgconf re2c"`).
+    /// Overrides `PHPIZE_DEPS`.
+    #[structopt(long, value_name = "packages")]
+    phpize_deps: Option<String>,
+
+    /// Install even if an identical invocation (per the manifest) already installed the
+    /// same extensions, which otherwise makes repeating it a no-op.
+    #[structopt(long)]
+    force: bool,
+
+    /// Require every PECL archive to carry a minisign signature by one of the public keys
+    /// in this file (one per line; `.pub` files can be concatenated). PECL doesn't sign
+    /// its releases, so the signatures are read from `--source-signatures`.
+    #[structopt(
+        long,
+        env = "F1_SOURCE_KEYRING",
+        value_name = "path",
+        requires = "source-signatures"
+    )]
+    source_keyring: Option<PathBuf>,
+
+    /// The directory holding the signatures of PECL archives for `--source-keyring`, named
+    /// after the archives (e.g., `redis-6.0.2.tgz.minisig`).
+    #[structopt(
+        long,
+        env = "F1_SOURCE_SIGNATURES",
+        value_name = "path",
+        requires = "source-keyring"
+    )]
+    source_signatures: Option<PathBuf>,
+
+    /// Run the test suites PECL extensions bundle (with `pecl run-tests`) once they're
+    /// installed, failing if any test fails. Extensions the CLI doesn't load are skipped.
+    #[structopt(long)]
+    run_tests: bool,
+
+    /// How long each extension's tests may run with `--run-tests` (in the same form as
+    /// `--timeout`).
+    #[structopt(
+        long,
+        default_value = "10m",
+        parse(try_from_str = command::parse_duration),
+        value_name = "duration"
+    )]
+    test_timeout: Duration,
+
+    /// Show nothing but errors.
+    #[structopt(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// When to color errors and warnings. With `auto`, they are colored in a terminal,
+    /// unless `NO_COLOR` (or `CLICOLOR=0`) is set; `CLICOLOR_FORCE` colors them anywhere.
+    #[structopt(
+        long,
+        global = true,
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"],
+        value_name = "when"
+    )]
+    color: ColorChoice,
+
+    /// Don't write the default ini settings that the registry provides for some
+    /// extensions (e.g., opcache). Settings given with `--ini` are still written.
+    #[structopt(long)]
+    no_ini: bool,
+
+    /// Write an ini directive (`key=value`) for the extension named before it, e.g.
+    /// `pecl:apcu --ini apc.enable_cli=1`. May be repeated.
+    #[structopt(long = "ini", number_of_values = 1, value_name = "key=value")]
+    ini: Vec<String>,
+
+    /// Append the complete output of every command run (e.g., compiler output) to this
+    /// file, while keeping console output terse, so that it can be inspected after a
+    /// failed build.
+    #[structopt(long, global = true, value_name = "path")]
+    log_file: Option<PathBuf>,
+
+    /// How many times to retry commands that fail because of the network (e.g., `apk add`
+    /// or a PECL download against a flaky mirror). Compile errors are never retried.
+    #[structopt(
+        long,
+        global = true,
+        env = "F1_RETRIES",
+        default_value = "3",
+        value_name = "count"
+    )]
+    retries: u32,
+
+    /// Seconds to wait before retrying a network failure; each later retry waits twice as
+    /// long.
+    #[structopt(
+        long,
+        global = true,
+        env = "F1_RETRY_DELAY",
+        default_value = "2",
+        value_name = "seconds",
+        parse(try_from_str = command::parse_seconds)
+    )]
+    retry_delay: Duration,
+
+    /// Kill any command (e.g., a hung `pecl` download or a stuck configure script) that
+    /// runs longer than this, failing the build. Given in seconds, or with an `s`, `m`, or
+    /// `h` suffix (e.g., `30m`).
+    #[structopt(
+        long,
+        global = true,
+        env = "F1_TIMEOUT",
+        parse(try_from_str = command::parse_duration),
+        value_name = "duration"
+    )]
+    timeout: Option<Duration>,
+
+    /// Fail the build if the whole installation takes longer than this (in the same form
+    /// as `--timeout`).
+    #[structopt(
+        long,
+        global = true,
+        env = "F1_TOTAL_TIMEOUT",
+        parse(try_from_str = command::parse_duration),
+        value_name = "duration"
+    )]
+    total_timeout: Option<Duration>,
+
+    /// PHP's configuration directory, for PHP builds that don't use the official images'
+    /// layout (or to stage files under another root). Defaults to `$PHP_INI_DIR`, or
+    /// `/usr/local/etc/php` if that isn't set.
+    #[structopt(long, global = true, value_name = "dir")]
+    ini_dir: Option<PathBuf>,
+
+    /// The directory PHP scans for additional ini files (its `--with-config-file-scan-dir`
+    /// setting), if it isn't `conf.d` in the configuration directory.
+    #[structopt(long, global = true, value_name = "dir")]
+    scan_dir: Option<PathBuf>,
+
+    /// Copy an ini file into PHP's scan directory (`conf.d`). The path may also be a
+    /// directory, to copy every `.ini` file in it, or a quoted pattern like
+    /// `'/build/conf.d/*.ini'`. May be repeated.
+    #[structopt(long, number_of_values = 1, value_name = "path")]
+    conf: Vec<String>,
+
+    /// Copy PHP's `php.ini-production` or `php.ini-development` template to `php.ini`
+    /// in the configuration directory.
+    #[structopt(long, possible_values = &["production", "development"], value_name = "template")]
+    php_ini: Option<PhpIni>,
+
+    /// When installing imagick, allow or deny ImageMagick's Ghostscript-backed PDF and
+    /// PostScript coders (`allow` also installs Ghostscript).
+    #[structopt(long, possible_values = &["allow", "deny"])]
+    imagick_pdf: Option<PdfPolicy>,
+
+    /// When installing imagick, replace ImageMagick's policy.xml with this file.
+    #[structopt(long)]
+    imagick_policy: Option<PathBuf>,
+
+    /// Print a summary of the installation in this format (only `json` is supported)
+    /// when it finishes: the extensions installed and their versions, the packages
+    /// added and removed, the ini files written, warnings, and the duration.
+    #[structopt(long, possible_values = &["json"], value_name = "format")]
+    output: Option<OutputFormat>,
+
+    /// Write the summary to this file instead of stdout (implies `--output json`).
+    #[structopt(long, value_name = "path")]
+    output_file: Option<PathBuf>,
+
+    /// Print a Dockerfile `LABEL` instruction naming the extensions installed and their
+    /// versions (e.g., `org.forumone.php-ext.redis="6.0.2"`), for recording them in the
+    /// image's labels.
+    #[structopt(long, conflicts_with = "output")]
+    labels: bool,
+
+    /// Write the `LABEL` instruction to this file instead of stdout (implies `--labels`).
+    #[structopt(long, value_name = "path")]
+    labels_file: Option<PathBuf>,
+
+    /// Write an SBOM (JSON) to this file, listing the extensions installed and the apk
+    /// packages added to the image.
+    #[structopt(long, value_name = "path")]
+    sbom: Option<PathBuf>,
+
+    /// The SBOM standard to write with `--sbom`.
+    #[structopt(
+        long,
+        default_value = "cyclonedx",
+        possible_values = &["cyclonedx", "spdx"],
+        value_name = "format"
+    )]
+    sbom_format: SbomFormat,
+
+    /// Fail before installing anything if `/`, `/tmp`, or `/usr/local` has less free space
+    /// than this, in bytes or with a `K`, `M`, or `G` suffix (e.g., `2G`). `0` skips the
+    /// check.
+    #[structopt(
+        long,
+        env = "F1_MIN_FREE_SPACE",
+        default_value = disk::DEFAULT_MIN_FREE,
+        parse(try_from_str = disk::parse_size),
+        value_name = "size"
+    )]
+    min_free_space: u64,
+
+    /// The memory to allow each parallel compile job, in bytes or with a `K`, `M`, or `G`
+    /// suffix. Builds run one job per CPU, but no more than the available memory (or the
+    /// container's memory limit) allows, so that large C++ extensions like grpc aren't
+    /// killed for running out of memory. `0` ignores memory. Ignored with `--jobs`.
+    #[structopt(
+        long,
+        env = "F1_JOB_MEMORY",
+        default_value = "1G",
+        parse(try_from_str = disk::parse_size),
+        value_name = "size"
+    )]
+    job_memory: u64,
+
+    /// How many compile jobs to run at once (`docker-php-ext-install -j` and `make -j`),
+    /// instead of deciding from the CPUs and memory available.
+    #[structopt(
+        short,
+        long,
+        env = "F1_JOBS",
+        parse(try_from_str = jobs::parse_jobs),
+        value_name = "count"
+    )]
+    jobs: Option<usize>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> Result<()> {
-    let opts = Opts::from_args();
-    let manager = Apk;
+/// Runs a `registry` subcommand.
+fn run_registry_command(command: RegistryCommand) -> Result<()> {
+    match command {
+        RegistryCommand::Export { format } => {
+            let registry = Registry::effective();
+            println!("{}", registry.to_string(format)?);
+        }
+        RegistryCommand::Import {
+            source,
+            signature,
+            public_key,
+        } => Registry::import(&source, signature.as_deref(), public_key.as_deref())?,
+    }
 
-    manager.install_packages(&opts.extensions)?;
+    Ok(())
+}
 
-    let builtins: Vec<_> = opts
-        .extensions
-        .iter()
-        .filter_map(|extension| match extension {
-            Extension::Builtin(builtin) => Some(builtin),
-            _ => None,
+/// Runs the `disable` subcommand.
+fn disable_extensions(extensions: &[String]) -> Result<()> {
+    let dirs = ini::loader_dirs();
+
+    for name in extensions {
+        let changed = ini::disable_extension(&dirs, name)?;
+        if changed.is_empty() {
+            bail!(
+                "{} isn't loaded by an ini file in {} (it may be compiled into PHP)",
+                name,
+                ini::scan_dir().display()
+            );
+        }
+
+        for path in changed {
+            info!("Disabled {} in {}", name, path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the `remove` subcommand.
+fn remove_extensions(extensions: Vec<Extension>, prune_packages: bool) -> Result<()> {
+    let platform = Platform::detect();
+    let dirs = ini::loader_dirs();
+    let extension_dir = system::extension_dir()?;
+    let mut removed = Vec::new();
+
+    for extension in extensions {
+        // Parsed as config: specs, so unwrap them to reach the registry data.
+        let mut extension = match extension {
+            Extension::Config(extension) => *extension,
+            extension => extension,
+        };
+        extension.resolve(&platform)?;
+
+        // `pecl uninstall` removes the module and headers the PECL installed, along with
+        // its record of the package; builtins' are removed by hand below.
+        let mut found = match &extension {
+            Extension::Pecl(pecl) => system::uninstall_pecl_extension(pecl)?,
+            _ => false,
+        };
+
+        let mut paths = vec![extension_dir.join(format!("{}.so", extension.module()))];
+        if let Extension::Builtin(_) = &extension {
+            paths.push(system::include_dir()?.join("ext").join(extension.name()));
+        }
+        for path in paths {
+            let exists = system::remove_installed(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            if exists {
+                info!("Removed {}", path.display());
+                found = true;
+            }
+        }
+
+        for path in ini::disable_extension(&dirs, extension.module())? {
+            info!("Disabled {} in {}", extension.name(), path.display());
+            found = true;
+        }
+        for path in ini::remove_extension_ini(&dirs, extension.name())? {
+            info!("Removed {}", path.display());
+            found = true;
+        }
+
+        if !found {
+            bail!(
+                "{} isn't installed as a shared module (it may be compiled into PHP)",
+                extension.key()
+            );
+        }
+        removed.push(extension.key());
+    }
+
+    if prune_packages {
+        let declared = remaining_runtime_packages(&removed, &platform)?;
+        let manager = Apk::default();
+        for dependency in manager.prune_runtime_deps(&declared)? {
+            info!("Dropped runtime dependency {}", dependency);
+        }
+    }
+
+    record_invocation(Invocation {
+        removed,
+        ..Invocation::new()
+    });
+
+    Ok(())
+}
+
+/// Collects the runtime packages of the extensions that the manifest still lists as
+/// installed, apart from those in `removed`, so that pruning the packages of removed
+/// extensions keeps any that another extension also depends on.
+///
+/// Each extension is resolved from the spec it was installed with, so that the packages
+/// of any features it was built with are included.
+fn remaining_runtime_packages(removed: &[String], platform: &Platform) -> Result<Vec<String>> {
+    let manifest = Manifest::load(Path::new(MANIFEST_PATH))?;
+    let mut packages = Vec::new();
+
+    for (key, (_, invocation)) in manifest.installed() {
+        if removed.iter().any(|removed| removed == key) {
+            continue;
+        }
+
+        let spec = invocation
+            .args
+            .iter()
+            .filter_map(|arg| arg.parse::<Extension>().ok())
+            .find(|extension| extension.key() == key);
+        let mut extension = match spec {
+            Some(extension) => extension,
+            None => key.parse()?,
+        };
+        extension.resolve(platform)?;
+        if let Some(runtime_packages) = extension.runtime_packages() {
+            packages.extend(runtime_packages.iter().cloned());
+        }
+    }
+
+    Ok(packages)
+}
+
+/// Parses the extension specs given on the command line, reporting every invalid one at
+/// once.
+fn parse_extensions(specs: &[String]) -> Result<Vec<Extension>> {
+    let invalid = match extension::parse_specs(specs) {
+        Ok(extensions) => return Ok(extensions),
+        Err(invalid) => invalid,
+    };
+
+    let mut message = if invalid.len() == 1 {
+        String::from("An extension spec is invalid:")
+    } else {
+        format!(
+            "{} of {} extension specs are invalid:",
+            invalid.len(),
+            specs.len()
+        )
+    };
+    for spec in invalid {
+        message.push_str(&format!("\n  {}", spec));
+    }
+
+    bail!(message)
+}
+
+/// Attaches each `--ini` directive to the extension named before it on the command line.
+fn attach_ini_settings(matches: &ArgMatches<'_>, opts: &mut Opts) -> Result<()> {
+    let extension_indices: Vec<_> = matches
+        .indices_of("extensions")
+        .map(Iterator::collect)
+        .unwrap_or_default();
+    let ini_indices = matches.indices_of("ini").into_iter().flatten();
+
+    for (index, setting) in ini_indices.zip(&opts.ini) {
+        let position = match extension_indices.iter().rposition(|&i| i < index) {
+            Some(position) => position,
+            None => bail!("--ini {} must follow the extension it configures", setting),
+        };
+
+        let (key, value) = match ini::parse_setting(setting) {
+            Some(parsed) => parsed,
+            None => bail!("--ini {} is not in key=value form", setting),
+        };
+
+        opts.extensions[position].add_setting(key, value);
+    }
+
+    Ok(())
+}
+
+/// Returns the name of the file that loads an extension: `ini_name`, if the spec or
+/// registry chose one, or the default name for `name`.
+fn loader_file_name(ini_name: Option<&str>, name: &str) -> String {
+    match ini_name {
+        Some(ini_name) => String::from(ini_name),
+        None => ini::default_ini_name(name),
+    }
+}
+
+/// Returns the paths of the files that should load an extension: its loader file (see
+/// [`loader_file_name`]) in each of `dirs`, or in `conf.d` if there are none.
+fn loader_paths(dirs: &[String], ini_name: Option<&str>, name: &str) -> Vec<PathBuf> {
+    let file_name = loader_file_name(ini_name, name);
+
+    if dirs.is_empty() {
+        return vec![ini::scan_dir().join(file_name)];
+    }
+
+    dirs.iter()
+        .map(|dir| ini::ini_dir().join(dir).join(&file_name))
+        .collect()
+}
+
+/// Describes how to load an extension that was kept out of the shared `conf.d`.
+fn ini_dir_hints(name: &str, dirs: &[String]) -> Vec<String> {
+    dirs.iter()
+        .map(|dir| {
+            format!(
+                "{} is loaded from {}; add it to PHP_INI_SCAN_DIR to enable it",
+                name,
+                ini::ini_dir().join(dir).display()
+            )
         })
-        .collect();
+        .collect()
+}
 
-    for builtin in &builtins {
-        if let Some(configure_cmd) = builtin.configure_cmd() {
-            system::configure_builtin(builtin.name(), configure_cmd)?;
+/// Tells the user how to load an extension that was kept out of the shared `conf.d`.
+fn report_ini_dirs(name: &str, dirs: &[String]) {
+    for hint in ini_dir_hints(name, dirs) {
+        info!("{}", hint);
+    }
+}
+
+/// The extensions that have failed to install, for `--keep-going`.
+struct Failures {
+    /// Whether to carry on past a failed extension.
+    keep_going: bool,
+    /// The failed extensions' keys, with the stage each one failed in and why.
+    failed: Vec<(String, Stage, anyhow::Error)>,
+    /// The keys of the extensions skipped because an extension they require failed (or
+    /// was skipped itself), with that extension's key.
+    skipped: Vec<(String, String)>,
+}
+
+impl Failures {
+    /// Creates an empty list of failures.
+    fn new(keep_going: bool) -> Self {
+        Self {
+            keep_going,
+            failed: Vec::new(),
+            skipped: Vec::new(),
         }
     }
 
-    system::install_builtins(builtins.iter().map(|builtin| builtin.name()))?;
+    /// Passes on the result of a step installing the extension `key`. With
+    /// `--keep-going`, a failure is recorded and logged instead, and `None` returned.
+    fn check<T, E>(&mut self, key: &str, result: Result<T, E>) -> Result<Option<T>>
+    where
+        E: Into<anyhow::Error>,
+    {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if self.keep_going => {
+                let error = error.into();
+                error!("{} failed: {}", key, error);
+                self.failed.push((String::from(key), exit::stage(), error));
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
 
-    for extension in &opts.extensions {
-        let pecl = match extension {
-            Extension::Pecl(pecl) => pecl,
-            _ => continue,
+    /// Determines if the extension `key` has failed, or was skipped because an extension
+    /// it requires failed.
+    fn has_failed(&self, key: &str) -> bool {
+        self.failed.iter().any(|(failed, _, _)| failed == key)
+            || self.skipped.iter().any(|(skipped, _)| skipped == key)
+    }
+
+    /// Returns the keys of the failed and skipped extensions.
+    fn keys(&self) -> Vec<String> {
+        let failed = self.failed.iter().map(|(key, _, _)| key);
+        let skipped = self.skipped.iter().map(|(key, _)| key);
+        failed.chain(skipped).cloned().collect()
+    }
+
+    /// Skips each of `extensions` that requires a failed (or skipped) extension, rather
+    /// than building it without its requirement or letting it fail confusingly.
+    fn skip_dependents(&mut self, extensions: &[Extension]) {
+        loop {
+            let dependent = extensions.iter().find_map(|extension| {
+                let key = extension.key();
+                if self.has_failed(&key) {
+                    return None;
+                }
+
+                let required = extension.requires()?.iter().find_map(|spec| {
+                    let required = spec
+                        .parse::<Extension>()
+                        .map_or_else(|_| spec.clone(), |required| required.key());
+                    self.has_failed(&required).then_some(required)
+                })?;
+                Some((key, required))
+            });
+
+            match dependent {
+                Some((key, required)) => {
+                    warn!(
+                        "Skipping {}, since it requires {}, which failed",
+                        key, required
+                    );
+                    self.skipped.push((key, required));
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Lists the failures for the report.
+    fn to_report(&self) -> Vec<FailedExtension> {
+        self.failed
+            .iter()
+            .map(|(key, _, error)| FailedExtension {
+                key: key.clone(),
+                error: error.to_string(),
+            })
+            .collect()
+    }
+
+    /// Lists the skipped extensions for the report.
+    fn skipped_report(&self) -> Vec<SkippedExtension> {
+        self.skipped
+            .iter()
+            .map(|(key, required)| SkippedExtension {
+                key: key.clone(),
+                reason: format!("requires {}, which failed", required),
+            })
+            .collect()
+    }
+
+    /// Fails with every extension that failed, exiting with the code of the stage the
+    /// first one failed in.
+    fn into_result(self) -> Result<()> {
+        let stage = match self.failed.first() {
+            Some((_, stage, _)) => *stage,
+            None => return Ok(()),
+        };
+
+        exit::set_stage(stage);
+        let skipped = self
+            .skipped
+            .iter()
+            .map(|(key, required)| format!("  {}: skipped, since it requires {}", key, required));
+        let failures: Vec<_> = self
+            .failed
+            .iter()
+            .map(|(key, _, error)| format!("  {}: {}", key, error).replace('\n', "\n  "))
+            .chain(skipped)
+            .collect();
+        bail!(
+            "{} extension(s) failed to install:\n{}",
+            failures.len(),
+            failures.join("\n")
+        )
+    }
+}
+
+/// Lists the ini files that installing `extension` may write and that don't exist yet.
+fn new_ini_files(extension: &Extension) -> Vec<PathBuf> {
+    let module = extension.module();
+    let ini_name = extension.ini_name();
+    let dirs = extension.ini_dirs();
+
+    // The loader, as written by docker-php-ext-install and docker-php-ext-enable (before
+    // it's moved), or by this tool outside the official images.
+    let mut paths = loader_paths(&dirs, ini_name, module);
+    paths.push(ini::docker_scan_dir().join(loader_file_name(ini_name, module)));
+    paths.push(ini::docker_scan_dir().join(ini::default_ini_name(extension.name())));
+    if ini_name.is_none() {
+        for zend in [false, true] {
+            let native = ini::native_ini_name(module, zend);
+            paths.extend(loader_paths(&dirs, Some(&native), module));
+        }
+    }
+
+    // The extension's settings.
+    if dirs.is_empty() {
+        paths.push(ini::extension_ini_path(&ini::scan_dir(), extension.name()));
+    }
+    for dir in &dirs {
+        let dir = ini::ini_dir().join(dir);
+        paths.push(ini::extension_ini_path(&dir, extension.name()));
+    }
+
+    paths.sort();
+    paths.dedup();
+    paths.retain(|path| !path.exists());
+    paths
+}
+
+/// Undoes a failed installation when dropped, so that a failed `RUN` doesn't leave a
+/// half-configured layer behind: the modules built (see [`Staging`]) and the ini files
+/// written for the extensions (which may not load once the build dependencies are gone),
+/// PECL's build trees, the PHP source tree, and the build dependencies.
+struct Rollback {
+    /// The new ini files each extension (by key) may write.
+    ini_files: BTreeMap<String, Vec<PathBuf>>,
+    /// The files built for the extensions, held back until every build succeeds.
+    staging: Staging,
+    /// The files each extension (by key) built, in `staging`.
+    built_files: BTreeMap<String, Vec<PathBuf>>,
+    /// Whether the `.build-deps` virtual package is installed.
+    build_deps: bool,
+    /// Whether the PHP source tree is extracted.
+    php_source: bool,
+    /// Whether the installation finished, leaving nothing to undo.
+    finished: bool,
+}
+
+impl Rollback {
+    /// Prepares to undo the installation of `extensions`.
+    fn new(extensions: &[Extension]) -> Self {
+        let ini_files = extensions
+            .iter()
+            .map(|extension| (extension.key(), new_ini_files(extension)))
+            .collect();
+
+        Self {
+            ini_files,
+            staging: Staging::default(),
+            built_files: BTreeMap::new(),
+            build_deps: false,
+            php_source: false,
+            finished: false,
+        }
+    }
+
+    /// Stages `path`, built for the extension `key` (see [`Staging::stage`]).
+    fn stage(&mut self, key: &str, path: &Path) -> Result<()> {
+        self.staging.stage(path)?;
+        self.built_files
+            .entry(String::from(key))
+            .or_default()
+            .push(path.to_path_buf());
+
+        Ok(())
+    }
+
+    /// Takes back the files built for the extension `key`, even if they were committed,
+    /// and removes the ini files written for it.
+    fn undo_extension(&mut self, key: &str) {
+        for path in self.built_files.remove(key).unwrap_or_default() {
+            if let Err(error) = self.staging.revert(&path) {
+                warn!("{}", error);
+            }
+        }
+
+        for path in self.ini_files.remove(key).unwrap_or_default() {
+            match fs::remove_file(&path) {
+                Ok(()) => info!("Removed {}", path.display()),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => warn!("Failed to remove {}: {}", path.display(), error),
+            }
+        }
+    }
+
+    /// Records that the installation finished, so that nothing is undone.
+    fn finish(&mut self) {
+        self.staging.finish();
+        self.finished = true;
+    }
+}
+
+impl Drop for Rollback {
+    fn drop(&mut self) {
+        if self.finished || command::is_dry_run() {
+            return;
+        }
+
+        warn!("Rolling back the failed installation");
+        let keys: Vec<_> = self
+            .ini_files
+            .keys()
+            .chain(self.built_files.keys())
+            .cloned()
+            .collect();
+        for key in keys {
+            self.undo_extension(&key);
+        }
+
+        if let Err(error) = source::delete_pecl_temp() {
+            warn!("{}", error);
+        }
+        if self.php_source {
+            if let Err(error) = source::delete_php_source() {
+                warn!("Failed to remove the PHP source: {}", error);
+            }
+        }
+        if self.build_deps {
+            if let Err(error) = Apk::default().remove_build_deps() {
+                warn!("Failed to remove the build dependencies: {}", error);
+            }
+        }
+    }
+}
+
+/// Determines if PHP's CLI should load `extension` once it's installed: it's enabled, and
+/// loaded from the shared `conf.d` rather than only for some SAPIs.
+fn cli_loads(extension: &Extension) -> bool {
+    !extension.is_config_only() && extension.is_enabled() && extension.ini_dirs().is_empty()
+}
+
+/// Determines if `pecl run-tests` can run `extension`'s test suite: it's a PECL extension
+/// installed by `pecl` (rather than built from its `source_url`) that PHP's CLI loads.
+fn has_tests(extension: &Extension) -> bool {
+    match extension {
+        Extension::Pecl(pecl) => pecl.source_url().is_none() && cli_loads(extension),
+        _ => false,
+    }
+}
+
+/// Runs the test suite a PECL extension installed, failing if any test fails.
+fn run_tests(pecl: &Pecl, limit: Duration) -> Result<()> {
+    let summary = system::run_pecl_tests(pecl, limit)
+        .with_context(|| format!("Failed to run pecl:{}'s tests", pecl.name()))?;
+
+    let run = summary.passed + summary.failed;
+    if summary.failed > 0 {
+        bail!(
+            "{} of pecl:{}'s {} tests failed: {}",
+            summary.failed,
+            pecl.name(),
+            run,
+            summary.failures.join(", ")
+        );
+    }
+    if run + summary.skipped > 0 {
+        info!(
+            "pecl:{}: {} tests passed, {} skipped",
+            pecl.name(),
+            summary.passed,
+            summary.skipped
+        );
+    } else if !command::is_dry_run() {
+        info!("pecl:{} didn't install any tests", pecl.name());
+    }
+
+    Ok(())
+}
+
+/// Downloads, patches, and compiles a PECL extension.
+fn compile_pecl(pecl: &Pecl) -> Result<()> {
+    source::delete_stale_pecl_build(pecl.name())?;
+    exit::set_stage(Stage::Fetch);
+    let source = source::download_pecl_source(pecl)?;
+    exit::set_stage(Stage::Compile);
+    if pecl.source_url().is_some() {
+        let dir = source::extract_source(pecl, &source)?;
+        system::build_extension(pecl, &dir)?;
+    } else {
+        source::patch_pecl_source(pecl, &source)?;
+        system::install_pecl_extension(pecl, source.target())?;
+    }
+    Ok(())
+}
+
+/// Determines if the installed shared `module` is a Zend extension. In a dry run, the
+/// module hasn't been built, so it's assumed not to be.
+fn is_zend_extension(module: &str) -> Result<bool> {
+    if command::is_dry_run() {
+        return Ok(false);
+    }
+
+    let path = system::extension_dir()?.join(format!("{}.so", module));
+    Ok(ini::is_zend_extension(&path)?)
+}
+
+/// Checks that PHP loads an installed extension: as the CLI is configured, with the
+/// extension's SAPI directories scanned too, or (for an extension that isn't enabled)
+/// loading it explicitly.
+fn check_loaded(extension: &Extension) -> Result<()> {
+    let module = extension.module();
+    let dirs: Vec<_> = extension
+        .ini_dirs()
+        .iter()
+        .map(|dir| ini::ini_dir().join(dir))
+        .collect();
+    let directive = match (extension.is_enabled(), is_zend_extension(module)?) {
+        (true, _) => None,
+        (false, true) => Some("zend_extension"),
+        (false, false) => Some("extension"),
+    };
+
+    system::check_loaded(module, &dirs, directive)
+        .with_context(|| format!("{} was installed, but PHP doesn't load it", extension.key()))
+}
+
+/// Writes the file that loads an installed extension's shared module, in `conf.d` or the
+/// extension's own ini directories.
+fn enable_module(extension: &Extension) -> Result<()> {
+    let module = extension.module();
+    let ini_name = extension.ini_name();
+    let dirs = extension.ini_dirs();
+
+    if system::has_docker_php_ext_enable() {
+        system::enable_extension(module, ini_name)?;
+
+        // docker-php-ext-enable only writes to conf.d (and knows whether the module is a
+        // Zend extension), so extensions loaded from elsewhere are moved afterwards.
+        if !dirs.is_empty() || ini::scan_dir_overridden() {
+            let from = ini::docker_scan_dir().join(loader_file_name(ini_name, module));
+            let targets = loader_paths(&dirs, ini_name, module);
+            ini::relocate_loader_ini(&from, &targets)?;
+        }
+    } else {
+        // Outside the official images, check for a Zend extension the way
+        // docker-php-ext-enable would, and write the loader ourselves.
+        let zend = is_zend_extension(module)?;
+        let ini_name = match ini_name {
+            Some(ini_name) => String::from(ini_name),
+            None => ini::native_ini_name(module, zend),
         };
 
-        system::install_pecl_extension(pecl)?;
+        for target in loader_paths(&dirs, Some(&ini_name), module) {
+            ini::write_loader_ini(&target, module, zend)?;
+        }
+    }
+
+    report_ini_dirs(extension.name(), &dirs);
+
+    Ok(())
+}
+
+/// Writes an extension's ini settings (including the registry's defaults, if `defaults`
+/// is true) next to the file that loads it. Returns the paths written.
+fn write_settings(extension: &Extension, defaults: bool) -> Result<Vec<PathBuf>> {
+    let settings = extension.ini_settings(defaults);
+    if settings.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let dirs = extension.ini_dirs();
+    if dirs.is_empty() {
+        let path = ini::write_extension_ini(&ini::scan_dir(), extension.name(), &settings)?;
+        return Ok(vec![path]);
+    }
+
+    let mut paths = Vec::new();
+    for dir in dirs {
+        let dir = ini::ini_dir().join(dir);
+        paths.push(ini::write_extension_ini(&dir, extension.name(), &settings)?);
+    }
+
+    Ok(paths)
+}
+
+/// Returns the version of an installed extension: the PECL release (from `pecl list`, or
+/// the version requested if it can't be queried), or the PHP version for builtins.
+fn installed_version(
+    extension: &Extension,
+    platform: &Platform,
+    pecl_versions: &HashMap<String, String>,
+) -> Option<String> {
+    match extension {
+        Extension::Builtin(_) => platform.php_version().map(ToString::to_string),
+        Extension::Pecl(pecl) => pecl_versions
+            .get(&pecl.name().to_ascii_lowercase())
+            .cloned()
+            .or_else(|| Some(pecl.version().to_string())),
+        Extension::Config(_) => None,
+    }
+}
+
+/// Adds `invocation` to the manifest of everything this tool has done to the image. This
+/// is skipped for dry runs, and a failure only warns, since the installation itself
+/// succeeded.
+fn record_invocation(invocation: Invocation) {
+    if command::is_dry_run() {
+        return;
     }
 
-    let save_rundeps = opts.extensions.iter().any(Extension::has_packages);
-    if save_rundeps {
-        manager.save_runtime_deps()?;
+    if let Err(error) = Manifest::record(Path::new(MANIFEST_PATH), invocation) {
+        warn!("Failed to record this installation: {}", error);
     }
+}
 
-    manager.remove_build_deps()?;
+/// Runs the `list` subcommand.
+fn list_extensions() -> Result<()> {
+    let manifest = Manifest::load(Path::new(MANIFEST_PATH))?;
+    for (key, (extension, invocation)) in manifest.installed() {
+        match &extension.version {
+            Some(version) => println!("{} {} ({})", key, version, invocation.timestamp),
+            None => println!("{} ({})", key, invocation.timestamp),
+        }
+    }
 
     Ok(())
 }
+
+/// Writes an SBOM document to `path`.
+fn write_sbom(path: &Path, document: &serde_json::Value) -> Result<()> {
+    let contents =
+        serde_json::to_string_pretty(document).context("Failed to serialize the SBOM")?;
+    if command::skip_write(path, &contents) {
+        return Ok(());
+    }
+
+    fs::write(path, contents + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Wrote SBOM to {}", path.display());
+
+    Ok(())
+}
+
+/// Measures what an installation removed from and left in the image, from the installed
+/// package sizes before the build (`before`), before cleanup (`built`), and after it
+/// (`after`).
+fn measure_sizes(
+    extensions: &[Extension],
+    before: &BTreeMap<String, u64>,
+    built: &BTreeMap<String, u64>,
+    after: &BTreeMap<String, u64>,
+) -> Sizes {
+    let build_deps_removed = built
+        .iter()
+        .filter(|&(name, _)| !after.contains_key(name))
+        .map(|(_, size)| size)
+        .sum();
+    let runtime_packages = after
+        .iter()
+        .filter(|&(name, _)| !before.contains_key(name))
+        .map(|(name, size)| (name.clone(), *size))
+        .collect();
+
+    let mut modules = BTreeMap::new();
+    if let Ok(dir) = system::extension_dir() {
+        for extension in extensions.iter().filter(|e| !e.is_config_only()) {
+            let file = format!("{}.so", extension.module());
+            if let Ok(metadata) = fs::metadata(dir.join(&file)) {
+                modules.insert(file, metadata.len());
+            }
+        }
+    }
+
+    Sizes {
+        build_deps_removed,
+        runtime_packages,
+        modules,
+    }
+}
+
+/// Fails if this process can't install `extensions`, listing every missing prerequisite
+/// (root, the programs the builtins and PECL extensions need, and `PHPIZE_DEPS`) at once.
+/// In a dry run, only `PHPIZE_DEPS` is checked, since nothing is run.
+fn check_prerequisites(extensions: &[Extension]) -> Result<()> {
+    let mut programs = vec!["apk", "php", "phpize"];
+    if extensions.iter().any(|e| matches!(e, Extension::Pecl(_))) {
+        programs.push("pecl");
+    }
+    if extensions
+        .iter()
+        .any(|e| matches!(e, Extension::Builtin(_)))
+    {
+        programs.extend(["docker-php-ext-configure", "docker-php-ext-install"]);
+    }
+    // The PHP source tree is only extracted separately to patch builtins.
+    let patches_builtins = extensions.iter().any(|e| match e {
+        Extension::Builtin(builtin) => builtin.patches().is_some_and(|p| !p.is_empty()),
+        _ => false,
+    });
+    if patches_builtins {
+        programs.push("docker-php-source");
+    }
+
+    let mut missing = if command::is_dry_run() {
+        Vec::new()
+    } else {
+        system::missing_prerequisites(&programs)
+    };
+    if system::phpize_deps().is_none() {
+        missing.push(format!(
+            "PHPIZE_DEPS is missing or empty, so the C compiler and build tools that \
+             extensions need wouldn't be installed (and builds would fail with errors like \
+             \"cc: not found\"). The official php images set it, so either this image isn't \
+             based on one or the variable was cleared. Name the build tools with \
+             --phpize-deps (e.g., --phpize-deps \"{}\") or set PHPIZE_DEPS.",
+            system::DEFAULT_PHPIZE_DEPS
+        ));
+    }
+
+    if !missing.is_empty() {
+        let lines: Vec<_> = missing
+            .iter()
+            .map(|problem| format!("  - {}", problem))
+            .collect();
+        bail!(
+            "This image can't install extensions yet:\n{}",
+            lines.join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+/// Fails, naming each one, if PECL doesn't have the release requested of any of the PECL
+/// `extensions`. If PECL can't be reached, or answers with anything but the release or a
+/// 404 (e.g., a server error or a rate limit), the check is skipped with a warning.
+fn check_releases(extensions: &[Extension]) -> Result<()> {
+    let mut missing = Vec::new();
+    for extension in extensions {
+        let pecl = match extension {
+            Extension::Pecl(pecl) if pecl.source_url().is_none() => pecl,
+            _ => continue,
+        };
+
+        match system::pecl_release_exists(pecl) {
+            Ok(true) => {}
+            Ok(false) => missing.push(pecl.specifier()),
+            Err(error) => {
+                warn!(
+                    "Couldn't check that PECL has the releases requested: {}",
+                    error
+                );
+                return Ok(());
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        bail!(
+            "PECL has no release matching {} (check the names and versions on \
+             https://pecl.php.net)",
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Fails if any filesystem an installation writes to has less than `minimum` bytes free.
+/// If `df` can't report on a filesystem, the check is skipped with a warning.
+fn check_free_space(minimum: u64) -> Result<()> {
+    let mut checked = Vec::new();
+    for path in disk::INSTALL_PATHS {
+        let space = match disk::free_space(Path::new(path)) {
+            Ok(Some(space)) => space,
+            Ok(None) => {
+                warn!(
+                    "Couldn't determine the free space on {}; not checking it",
+                    path
+                );
+                continue;
+            }
+            Err(error) => {
+                warn!("Couldn't check free disk space: {}", error);
+                return Ok(());
+            }
+        };
+
+        if checked.contains(&space.mount) {
+            continue;
+        }
+
+        if space.available < minimum {
+            bail!(
+                "Only {} is free on {} (the filesystem holding {}), but installing needs at \
+                 least {}; free some space or lower --min-free-space",
+                report::format_size(space.available),
+                space.mount,
+                path,
+                report::format_size(minimum)
+            );
+        }
+
+        checked.push(space.mount);
+    }
+
+    Ok(())
+}
+
+impl Opts {
+    /// Returns the format to write the report in, if one was requested.
+    fn report_format(&self) -> Option<OutputFormat> {
+        match (self.output, &self.output_file) {
+            (Some(format), _) => Some(format),
+            (None, Some(_)) => Some(OutputFormat::Json),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Fingerprints what installing `extensions` would do: the extensions as resolved (with
+/// their versions, options, settings, and packages), the command line, the environment
+/// selected with `--env`, the `F1_PECL_*` and `F1_BUILTIN_*` overrides, and the effective
+/// registry (including the supplemental one).
+fn plan_fingerprint(extensions: &[Extension], env: Option<&str>) -> String {
+    let mut description = format!("{:?}\n", extensions);
+    description.push_str(&format!("{:?}\n", env::args().skip(1).collect::<Vec<_>>()));
+    description.push_str(&format!("{:?}\n", env));
+
+    let mut overrides: Vec<_> = env::vars()
+        .filter(|(name, _)| name.starts_with("F1_PECL_") || name.starts_with("F1_BUILTIN_"))
+        .collect();
+    overrides.sort();
+    description.push_str(&format!("{:?}\n", overrides));
+
+    if let Ok(registry) = Registry::effective().to_string(Format::Json) {
+        description.push_str(&registry);
+    }
+
+    manifest::fingerprint(&description)
+}
+
+/// Returns when an earlier invocation of this tool with the same `plan` (per the manifest)
+/// installed `extensions`, if one did and nothing has since replaced or removed them.
+fn previous_run(plan: &str, extensions: &[Extension]) -> Option<String> {
+    let manifest = Manifest::load(Path::new(MANIFEST_PATH)).ok()?;
+    let keys: Vec<_> = extensions.iter().map(Extension::key).collect();
+
+    manifest
+        .find_repeat(plan, &keys)
+        .map(|invocation| invocation.timestamp.clone())
+}
+
+/// Prints `report` to stdout, or writes it to `path`.
+fn write_report(report: &Report, format: OutputFormat, path: Option<&PathBuf>) -> Result<()> {
+    let output = report
+        .to_string(format)
+        .context("Failed to serialize the report")?;
+
+    match path {
+        Some(path) => fs::write(path, output + "\n")
+            .with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            println!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+/// Prints a `LABEL` instruction for the installed extensions to stdout, or writes it to
+/// `path`.
+fn write_labels(extensions: &[InstalledExtension], path: Option<&PathBuf>) -> Result<()> {
+    let labels = match report::oci_labels(extensions) {
+        Some(labels) => labels,
+        None => return Ok(()),
+    };
+
+    match path {
+        Some(path) => fs::write(path, labels + "\n")
+            .with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            println!("{}", labels);
+            Ok(())
+        }
+    }
+}
+
+/// Prints an indented `label: a, b` line, unless `items` is empty.
+fn print_list(label: &str, items: &[String]) {
+    if !items.is_empty() {
+        println!("  {}: {}", label, items.join(", "));
+    }
+}
+
+/// Runs the `explain` subcommand.
+fn explain_extensions(mut extensions: Vec<Extension>) -> Result<()> {
+    let platform = Platform::detect();
+    for extension in &mut extensions {
+        extension.resolve(&platform)?;
+    }
+    let extensions = extension::sort_by_requirements(extensions, &platform)?;
+    let loaded = system::loaded_extensions().unwrap_or_default();
+
+    // Variants and features are chosen by platform, so show what was detected.
+    let unknown = || String::from("unknown");
+    println!(
+        "platform: PHP {}, Alpine {}, {}",
+        platform
+            .php_version()
+            .map_or_else(unknown, ToString::to_string),
+        platform
+            .alpine_version()
+            .map_or_else(unknown, ToString::to_string),
+        platform.arch().map_or_else(unknown, String::from),
+    );
+
+    for extension in &extensions {
+        println!();
+
+        println!("{}", extension.key());
+        println!("  registry data: {}", extension.source());
+        if let Extension::Pecl(pecl) = extension {
+            println!("  version: {}", pecl.version());
+        }
+
+        let empty = Vec::new();
+        print_list("features", extension.features());
+        print_list("requires", extension.requires().unwrap_or(&empty));
+        print_list("build packages", extension.packages().unwrap_or(&empty));
+        print_list(
+            "runtime packages",
+            extension.runtime_packages().unwrap_or(&empty),
+        );
+        print_list("repositories", extension.repositories().unwrap_or(&empty));
+        print_list("package files", extension.package_urls().unwrap_or(&empty));
+        if let Some(key) = extension.package_key() {
+            println!("  package key: {} ({})", key.url, key.fingerprint);
+        }
+
+        match extension {
+            Extension::Builtin(builtin) => {
+                if let Some(args) = builtin.configure_cmd() {
+                    println!(
+                        "  configure: docker-php-ext-configure {} {}",
+                        builtin.name(),
+                        args.join(" ")
+                    );
+                }
+            }
+            Extension::Pecl(pecl) => {
+                if let Some(options) = pecl.configure_options_arg() {
+                    println!("  configure options: {}", options);
+                }
+                if let Some(cflags) = pecl.cflags() {
+                    println!("  cflags: {}", cflags);
+                }
+            }
+            Extension::Config(_) => {}
+        }
+
+        let patches = extension.patches().map_or(0, Vec::len);
+        if patches > 0 {
+            println!("  patches: {}", patches);
+        }
+
+        let enabled = match extension {
+            Extension::Builtin(builtin) if system::is_loaded(&loaded, builtin.name()) => {
+                String::from("already loaded; skipped")
+            }
+            Extension::Config(_) => String::from("assumed already loaded"),
+            _ if !extension.is_enabled() => String::from("no (installed but not loaded)"),
+            _ => {
+                let paths = loader_paths(
+                    &extension.ini_dirs(),
+                    extension.ini_name(),
+                    extension.module(),
+                );
+                let paths: Vec<_> = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                format!("yes, from {}", paths.join(", "))
+            }
+        };
+        println!("  enabled: {}", enabled);
+
+        let settings: Vec<_> = extension
+            .ini_settings(true)
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        print_list("ini settings", &settings);
+    }
+
+    Ok(())
+}
+
+/// Runs the `enable` subcommand.
+fn enable_extensions(mut extensions: Vec<Extension>, no_ini: bool) -> Result<()> {
+    let platform = Platform::detect();
+
+    for extension in &mut extensions {
+        extension.resolve(&platform)?;
+        enable_module(extension)?;
+        write_settings(extension, !no_ini)?;
+    }
+
+    Ok(())
+}
+
+/// Sets up logging to stderr at the level chosen with `-v` or `-q`, with errors and
+/// warnings colored as chosen with `--color`.
+fn init_logging(verbose: u8, quiet: bool, color: ColorChoice) {
+    color::init(color);
+
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+
+    // emit-script runs a second, nested command line, which may set the level again.
+    let _ = env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| match record.level() {
+            Level::Error => writeln!(
+                buf,
+                "{}: {}",
+                color::paint(Style::Error, "error"),
+                record.args()
+            ),
+            Level::Warn => writeln!(
+                buf,
+                "{}: {}",
+                color::paint(Style::Warning, "warn"),
+                record.args()
+            ),
+            _ => writeln!(buf, "{}", record.args()),
+        })
+        .try_init();
+}
+
+/// Parses the command line in `argv`. Invalid arguments exit with the code for parse
+/// errors (see [`Stage::Parse`]).
+fn parse_args<I>(argv: I) -> ArgMatches<'static>
+where
+    I: IntoIterator<Item = String>,
+{
+    match Opts::clap().get_matches_from_safe(argv) {
+        Ok(matches) => matches,
+        Err(error) if error.use_stderr() => {
+            eprintln!("{}", error.message);
+            process::exit(Stage::Parse.code());
+        }
+        // --help and --version are reported as errors, but print to stdout.
+        Err(error) => error.exit(),
+    }
+}
+
+/// Runs `emit-script`: prints a shell script with the commands that installing with
+/// `args` would run.
+fn emit_script(args: Vec<String>, start: Instant) -> Result<()> {
+    let argv = iter::once(String::from(env!("CARGO_PKG_NAME"))).chain(args);
+    let matches = parse_args(argv);
+    if let Some(name) = matches.subcommand_name() {
+        bail!("emit-script only supports installs, not {}", name);
+    }
+
+    command::set_mode(Mode::Script);
+    println!("#!/bin/sh");
+    println!("set -ex");
+
+    run(&matches, start)
+}
+
+/// An installation, carried from one phase to the next: [`preflight`](Self::preflight)
+/// resolves and checks the extensions, [`install`](Self::install) builds them,
+/// [`configure`](Self::configure) enables them and writes their settings,
+/// [`verify`](Self::verify) removes the build dependencies and checks that PHP loads
+/// them, and [`record`](Self::record) reports what was installed.
+struct Installation {
+    /// The command line, with the extensions that are left to install.
+    opts: Opts,
+    /// When the run started.
+    start: Instant,
+    /// The platform the extensions are installed on.
+    platform: Platform,
+    /// The package manager, with the repositories the extensions need.
+    manager: Apk,
+    /// What the installation has done so far.
+    report: Report,
+    /// The modules PHP loaded before the installation, if PHP could be queried.
+    loaded_before: Option<Vec<String>>,
+    /// The keys of the extensions that were already installed but aren't loaded, which
+    /// only need to be enabled.
+    unloaded: Vec<String>,
+    /// The fingerprint of the installation (see [`plan_fingerprint`]).
+    plan: String,
+    /// Whether anything is built, rather than only configured.
+    installing: bool,
+    /// The vendor packages the extensions need.
+    package_files: Vec<PackageFile>,
+    /// Whether any extension needs runtime packages.
+    save_rundeps: bool,
+    /// The installed packages and their versions before the installation, for the SBOM.
+    packages_before: BTreeMap<String, String>,
+    /// The installed package sizes before the build, if they're measured.
+    sizes_before: Option<BTreeMap<String, u64>>,
+    /// The installed package sizes before cleanup, if they're measured.
+    sizes_built: Option<BTreeMap<String, u64>>,
+    /// The keys of the builtins that were built.
+    builtins: Vec<String>,
+    /// The installation's progress through its steps.
+    progress: Progress,
+    /// The extensions that have failed.
+    failures: Failures,
+    /// Undoes the installation if it fails.
+    rollback: Rollback,
+}
+
+impl Installation {
+    /// Resolves the extensions in `opts`, drops the ones that are already installed, and
+    /// checks everything that can be checked before anything is changed. Returns `None`
+    /// if there's nothing to do.
+    fn preflight(mut opts: Opts, start: Instant) -> Result<Option<Self>> {
+        let mut report = Report::default();
+
+        exit::set_stage(Stage::Resolve);
+
+        let env = opts.env.as_deref();
+        opts.extensions.retain(|extension| {
+            let selected = extension.is_for_env(env);
+            if !selected {
+                info!("{} isn't for this environment; skipping", extension.key());
+                report.skipped.push(SkippedExtension {
+                    key: extension.key(),
+                    reason: String::from("not for this environment"),
+                });
+            }
+            selected
+        });
+
+        let platform = Platform::detect();
+        for extension in &mut opts.extensions {
+            extension.resolve(&platform)?;
+        }
+
+        opts.extensions = extension::sort_by_requirements(opts.extensions, &platform)?;
+
+        // Building a builtin that PHP already has (e.g., sodium in most official images,
+        // or pdo_mysql in some others) is at best wasted time, so skip building them,
+        // keeping any ini settings or SAPIs requested for them as if they were `config:`
+        // specs. If PHP can't be queried, install everything.
+        let loaded_before = system::loaded_extensions().ok();
+        let loaded = loaded_before.clone().unwrap_or_default();
+        opts.extensions = mem::take(&mut opts.extensions)
+            .into_iter()
+            .filter_map(|extension| match &extension {
+                Extension::Builtin(builtin) if system::is_loaded(&loaded, builtin.name()) => {
+                    let configure = !extension.ini_settings(false).is_empty()
+                        || !extension.ini_dirs().is_empty();
+                    let reason = if configure {
+                        "already loaded; only its settings are written"
+                    } else {
+                        "already loaded"
+                    };
+                    info!("{} is {}", extension.key(), reason);
+                    report.skipped.push(SkippedExtension {
+                        key: extension.key(),
+                        reason: String::from(reason),
+                    });
+                    configure.then(|| Extension::Config(Box::new(extension)))
+                }
+                _ => Some(extension),
+            })
+            .collect();
+
+        // Likewise, a PECL extension already installed at the version requested (e.g., by
+        // an earlier layer) needn't be downloaded and compiled again. It counts as
+        // installed if PHP loads it at that version, or if its module is in the extension
+        // directory and the manifest records that version. Only exact versions can be
+        // compared; channels like `stable` are always installed. As with builtins,
+        // requested settings (and enabling a module that isn't loaded yet) are kept as
+        // `config:` specs.
+        let pinned: Vec<_> = opts
+            .extensions
+            .iter()
+            .filter_map(|extension| match extension {
+                Extension::Pecl(pecl) if matches!(pecl.version(), Version::Custom(_)) => {
+                    Some(pecl.module())
+                }
+                _ => None,
+            })
+            .collect();
+        let (versions, manifest, extension_dir) = if pinned.is_empty() {
+            (HashMap::new(), Manifest::default(), None)
+        } else {
+            (
+                system::loaded_versions(&pinned).unwrap_or_default(),
+                Manifest::load(Path::new(MANIFEST_PATH)).unwrap_or_default(),
+                system::extension_dir().ok(),
+            )
+        };
+        let recorded = manifest.installed();
+        let mut unloaded = Vec::new();
+        opts.extensions = mem::take(&mut opts.extensions)
+            .into_iter()
+            .filter_map(|extension| {
+                let (pecl, requested) = match &extension {
+                    Extension::Pecl(pecl) => match pecl.version() {
+                        Version::Custom(requested) => (pecl, requested),
+                        _ => return Some(extension),
+                    },
+                    _ => return Some(extension),
+                };
+
+                let key = extension.key();
+                let loaded = versions.get(pecl.module()) == Some(requested);
+                let built = extension_dir
+                    .as_ref()
+                    .is_some_and(|dir| dir.join(format!("{}.so", pecl.module())).is_file());
+                let recorded = recorded
+                    .get(key.as_str())
+                    .and_then(|(installed, _)| installed.version.as_ref())
+                    == Some(requested);
+                let installed = loaded || (built && recorded);
+                if !installed {
+                    return Some(extension);
+                }
+
+                let enable = !loaded && pecl.is_enabled();
+                let configure = pecl.is_enabled()
+                    && (enable
+                        || !extension.ini_settings(false).is_empty()
+                        || !extension.ini_dirs().is_empty());
+                let reason = match (configure, enable) {
+                    (false, _) => format!("already installed at {}", requested),
+                    (true, false) => format!(
+                        "already installed at {}; only its settings are written",
+                        requested
+                    ),
+                    (true, true) => format!(
+                        "already installed at {}; only enabled and configured",
+                        requested
+                    ),
+                };
+                info!("{} is {}", key, reason);
+                report.skipped.push(SkippedExtension { key, reason });
+
+                let configured = Extension::Config(Box::new(extension));
+                if enable {
+                    unloaded.push(configured.key());
+                }
+                configure.then_some(configured)
+            })
+            .collect();
+
+        // Dockerfiles (especially generated ones) sometimes repeat an installation. If an
+        // identical invocation (the same resolved extensions, command line, and registry)
+        // already did everything, and nothing has undone it since, the repeat has nothing
+        // to do.
+        let plan = plan_fingerprint(&opts.extensions, opts.env.as_deref());
+        if let Some(timestamp) = previous_run(&plan, &opts.extensions).filter(|_| !opts.force) {
+            info!(
+                "An identical installation already ran at {}, and nothing has changed its \
+                 extensions since; skipping (--force installs them again)",
+                timestamp
+            );
+            for extension in &opts.extensions {
+                report.skipped.push(SkippedExtension {
+                    key: extension.key(),
+                    reason: String::from("installed by an identical earlier run"),
+                });
+            }
+            report.set_duration(start.elapsed());
+            if let Some(format) = opts.report_format() {
+                write_report(&report, format, opts.output_file.as_ref())?;
+            }
+            return Ok(None);
+        }
+
+        // config: specs only write ini files, so a run with nothing else to install
+        // doesn't need the build dependencies.
+        let installing = opts.extensions.iter().any(|e| !e.is_config_only());
+
+        if let Some(packages) = &opts.phpize_deps {
+            system::set_phpize_deps(packages);
+        }
+        if installing {
+            exit::set_stage(Stage::Other);
+            check_prerequisites(&opts.extensions)?;
+        }
+
+        if installing && !command::is_dry_run() && opts.min_free_space > 0 {
+            exit::set_stage(Stage::Other);
+            check_free_space(opts.min_free_space)?;
+        }
+
+        if installing {
+            let jobs = opts
+                .jobs
+                .unwrap_or_else(|| jobs::plan_jobs(opts.job_memory));
+            jobs::set_jobs(jobs);
+        }
+
+        // Vendor packages that aren't published for this architecture, or that the
+        // registry doesn't name a signing key for, fail the run before anything is
+        // installed.
+        exit::set_stage(Stage::Resolve);
+        let package_files = vendor::collect_package_files(&opts.extensions)
+            .context("Failed to find the vendor packages the extensions need")?;
+
+        let manager = Apk::new(system::collect_repositories(&opts.extensions));
+
+        // Check everything that can be checked before changing anything, so that a typo in
+        // the last extension doesn't fail the build after the others have been compiled.
+        // Packages from tagged repositories are checked against a copy of the repositories
+        // file, which is only changed once the checks pass.
+        if installing && !command::is_dry_run() {
+            exit::set_stage(Stage::Resolve);
+            check_releases(&opts.extensions)?;
+            exit::set_stage(Stage::Packages);
+            let repositories = manager
+                .preflight_repositories()
+                .context("Failed to copy the package repositories list")?;
+            manager
+                .check_packages(
+                    &opts.extensions,
+                    repositories.as_ref().map(PreflightRepositories::path),
+                )
+                .context("Some of the packages the extensions need can't be installed")?;
+        }
+
+        if installing {
+            exit::set_stage(Stage::Packages);
+            manager
+                .add_tagged_repositories()
+                .context("Failed to add the package repositories the extensions need")?;
+        }
+
+        exit::set_stage(Stage::Packages);
+
+        // Packages are listed before anything is installed, so that the SBOM can name the
+        // ones this run added.
+        let packages_before = match opts.sbom {
+            Some(_) => manager
+                .installed_packages()
+                .context("Failed to list installed packages")?,
+            None => BTreeMap::new(),
+        };
+
+        // Package sizes are compared before the build, before cleanup, and after it, to
+        // measure what removing the build dependencies saved and what the installation
+        // kept.
+        let measuring = installing && !command::is_dry_run();
+        let sizes_before = if measuring {
+            manager.package_sizes().ok()
+        } else {
+            None
+        };
+
+        let builtins = opts
+            .extensions
+            .iter()
+            .any(|e| matches!(e, Extension::Builtin(_)));
+        let pecls = opts
+            .extensions
+            .iter()
+            .filter(|e| matches!(e, Extension::Pecl(_)))
+            .count();
+        let save_rundeps = opts.extensions.iter().any(Extension::has_packages);
+
+        // Build dependencies, builtins, runtime dependencies, removing the build
+        // dependencies, and checking that PHP loads the extensions, then each PECL
+        // extension's compile and (if PHP's CLI loads it) its tests.
+        let steps = [
+            installing || !package_files.is_empty(),
+            builtins,
+            save_rundeps,
+            installing,
+            installing,
+        ]
+        .iter()
+        .filter(|&&step| step)
+        .count();
+        let tests = opts
+            .extensions
+            .iter()
+            .filter(|e| opts.run_tests && has_tests(e))
+            .count();
+
+        Ok(Some(Self {
+            progress: Progress::new(steps + pecls + tests),
+            failures: Failures::new(opts.keep_going),
+            rollback: Rollback::new(&opts.extensions),
+            opts,
+            start,
+            platform,
+            manager,
+            report,
+            loaded_before,
+            unloaded,
+            plan,
+            installing,
+            package_files,
+            save_rundeps,
+            packages_before,
+            sizes_before,
+            sizes_built: None,
+            builtins: Vec::new(),
+        }))
+    }
+
+    /// Installs the build dependencies and builds the extensions, holding their modules
+    /// back until every build has succeeded (see [`Rollback`]).
+    fn install(&mut self) -> Result<()> {
+        let opts = &self.opts;
+        let progress = &mut self.progress;
+        let report = &mut self.report;
+        let failures = &mut self.failures;
+        let rollback = &mut self.rollback;
+
+        let mut builtins: Vec<_> = opts
+            .extensions
+            .iter()
+            .filter_map(|extension| match extension {
+                Extension::Builtin(builtin) => Some(builtin),
+                _ => None,
+            })
+            .collect();
+        let pecls = opts
+            .extensions
+            .iter()
+            .filter(|e| matches!(e, Extension::Pecl(_)))
+            .count();
+
+        // When several builds run, each line of their output is prefixed with what is
+        // being built (builtins are built together by docker-php-ext-install).
+        let builtins_label = builtins
+            .iter()
+            .map(|builtin| builtin.name())
+            .collect::<Vec<_>>()
+            .join(",");
+        let prefix_width = opts
+            .extensions
+            .iter()
+            .filter(|e| matches!(e, Extension::Pecl(_)))
+            .map(|e| e.name().len())
+            .chain(iter::once(builtins_label.len()))
+            .max()
+            .unwrap_or_default();
+        let builds = pecls + usize::from(!builtins.is_empty());
+        let output_prefix = |label: &str| {
+            if builds > 1 {
+                Some(format!("{:width$} | ", label, width = prefix_width))
+            } else {
+                None
+            }
+        };
+
+        if self.installing || !self.package_files.is_empty() {
+            let phase = Instant::now();
+            progress.step("Installing build dependencies");
+            if self.installing {
+                self.manager.install_packages(&opts.extensions)?;
+                rollback.build_deps = true;
+                report.build_packages = system::collect_packages(&opts.extensions);
+            }
+            if !self.package_files.is_empty() {
+                let paths = vendor::download_package_files(&self.package_files)
+                    .context("Failed to download and verify the vendor packages")?;
+                self.manager.install_package_files(&paths)?;
+                vendor::delete_package_files()?;
+            }
+            progress.finish();
+            report.add_phase("build dependencies", phase.elapsed());
+        }
+
+        if opts
+            .extensions
+            .iter()
+            .any(|extension| extension.key() == "builtin:oci8" || extension.key() == "pecl:oci8")
+        {
+            instantclient::install_instant_client()?;
+        }
+
+        // Built modules are held back (in the rollback's staging) until every build has
+        // succeeded, so that a failed run changes nothing that PHP loads.
+        let extension_dir = match (self.installing, command::is_dry_run()) {
+            (true, false) => Some(system::extension_dir()?),
+            _ => None,
+        };
+        let built_files = |module: &str, builtin: bool| {
+            let mut files = Vec::new();
+            if let Some(dir) = &extension_dir {
+                files.push(dir.join(format!("{}.so", module)));
+                // docker-php-ext-install enables the builtins it builds.
+                if builtin {
+                    files.push(ini::docker_scan_dir().join(ini::default_ini_name(module)));
+                }
+            }
+            files
+        };
+
+        exit::set_stage(Stage::Compile);
+        let phase = Instant::now();
+        if !builtins.is_empty() {
+            let names: Vec<_> = builtins.iter().map(|builtin| builtin.name()).collect();
+            progress.step(format_args!("Compiling builtins ({})", names.join(", ")));
+            command::set_output_prefix(output_prefix(&builtins_label));
+        }
+
+        // Builtins are normally configured without touching the PHP source tree, so only
+        // extract it when something needs patching.
+        let patch_builtins = builtins
+            .iter()
+            .any(|builtin| builtin.patches().is_some_and(|p| !p.is_empty()));
+        if patch_builtins {
+            source::extract_php_source()?;
+            rollback.php_source = true;
+            for builtin in &builtins {
+                if let Some(patches) = builtin.patches() {
+                    let dir = source::builtin_source_dir(builtin.name());
+                    failures.check(&builtin.key(), source::apply_patches(&dir, patches))?;
+                }
+            }
+        }
+
+        for builtin in &builtins {
+            if let (false, Some(configure_cmd)) =
+                (failures.has_failed(&builtin.key()), builtin.configure_cmd())
+            {
+                let result = system::configure_builtin(builtin.name(), configure_cmd);
+                failures.check(&builtin.key(), result)?;
+            }
+        }
+
+        failures.skip_dependents(&opts.extensions);
+        builtins.retain(|builtin| !failures.has_failed(&builtin.key()));
+        for builtin in &builtins {
+            for path in built_files(builtin.name(), true) {
+                rollback.staging.protect(&path)?;
+            }
+        }
+        if opts.keep_going {
+            for builtin in &builtins {
+                let result = system::install_builtins(iter::once(builtin.name()));
+                failures.check(&builtin.key(), result)?;
+            }
+            failures.skip_dependents(&opts.extensions);
+            builtins.retain(|builtin| !failures.has_failed(&builtin.key()));
+        } else {
+            system::install_builtins(builtins.iter().map(|builtin| builtin.name()))?;
+        }
+        for builtin in &builtins {
+            for path in built_files(builtin.name(), true) {
+                rollback.stage(&builtin.key(), &path)?;
+            }
+        }
+        command::set_output_prefix(None);
+        progress.finish();
+
+        // docker-php-ext-install builds every builtin in one step, so they are timed
+        // together.
+        if !builtins.is_empty() {
+            let names: Vec<_> = builtins.iter().map(|builtin| builtin.name()).collect();
+            report.add_phase(
+                format!("compile builtins ({})", names.join(", ")),
+                phase.elapsed(),
+            );
+        }
+
+        if patch_builtins {
+            source::delete_php_source()?;
+            rollback.php_source = false;
+        }
+        self.builtins = builtins.iter().map(|builtin| builtin.key()).collect();
+
+        for extension in &opts.extensions {
+            let pecl = match extension {
+                Extension::Pecl(pecl) => pecl,
+                _ => continue,
+            };
+            failures.skip_dependents(&opts.extensions);
+            if failures.has_failed(&extension.key()) {
+                progress.skip();
+                continue;
+            }
+
+            exit::set_stage(Stage::Compile);
+            let phase = Instant::now();
+            progress.step(format_args!("Compiling {}", extension.key()));
+            command::set_output_prefix(output_prefix(extension.name()));
+            let module = built_files(pecl.module(), false);
+            for path in &module {
+                rollback.staging.protect(path)?;
+            }
+            let result = compile_pecl(pecl);
+            command::set_output_prefix(None);
+            progress.finish();
+            report.add_phase(format!("compile {}", extension.key()), phase.elapsed());
+            if failures.check(&extension.key(), result)?.is_some() {
+                for path in &module {
+                    rollback.stage(&extension.key(), path)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Puts the built modules in place, enables the extensions and writes their settings
+    /// and the requested ini files, and installs their runtime dependencies.
+    fn configure(&mut self) -> Result<()> {
+        let opts = &mut self.opts;
+        let report = &mut self.report;
+        let failures = &mut self.failures;
+        let rollback = &mut self.rollback;
+
+        // Every extension has been built (or, with --keep-going, has failed), so the
+        // modules can be put in place and enabled.
+        exit::set_stage(Stage::Enable);
+        rollback.staging.commit()?;
+
+        // docker-php-ext-install always writes docker-php-ext-<name>.ini to conf.d, so a
+        // builtin with a chosen load order, SAPIs, or scan directory has its file moved
+        // afterwards.
+        for extension in &opts.extensions {
+            let builtin = match extension {
+                Extension::Builtin(builtin) if self.builtins.contains(&extension.key()) => builtin,
+                _ => continue,
+            };
+            let dirs = builtin.ini_dirs();
+            if builtin.ini_name().is_none() && dirs.is_empty() && !ini::scan_dir_overridden() {
+                continue;
+            }
+
+            let from = ini::docker_scan_dir().join(ini::default_ini_name(builtin.name()));
+            let targets = loader_paths(&dirs, builtin.ini_name(), builtin.name());
+            ini::relocate_loader_ini(&from, &targets)?;
+            report_ini_dirs(builtin.name(), &dirs);
+            report.warnings.extend(ini_dir_hints(builtin.name(), &dirs));
+        }
+
+        for extension in &opts.extensions {
+            let enable = match extension {
+                Extension::Pecl(pecl) => pecl.is_enabled(),
+                Extension::Config(_) => self.unloaded.contains(&extension.key()),
+                Extension::Builtin(_) => false,
+            };
+            if !enable {
+                continue;
+            }
+            if failures.has_failed(&extension.key()) {
+                continue;
+            }
+
+            if failures
+                .check(&extension.key(), enable_module(extension))?
+                .is_some()
+            {
+                report
+                    .warnings
+                    .extend(ini_dir_hints(extension.name(), &extension.ini_dirs()));
+            }
+        }
+
+        exit::set_stage(Stage::Enable);
+        for extension in opts.extensions.iter().filter(|e| e.is_enabled()) {
+            if failures.has_failed(&extension.key()) {
+                continue;
+            }
+
+            let result = write_settings(extension, !opts.no_ini);
+            if let Some(paths) = failures.check(&extension.key(), result)? {
+                report.ini_files.extend(paths);
+            }
+        }
+
+        failures.skip_dependents(&opts.extensions);
+        for key in failures.keys() {
+            rollback.undo_extension(&key);
+        }
+
+        // Everything after this (runtime dependencies, the report, and the manifest) is
+        // only for the extensions that installed.
+        opts.extensions
+            .retain(|extension| !failures.has_failed(&extension.key()));
+
+        for spec in &opts.conf {
+            for file in ini::find_conf_files(spec)? {
+                let path = ini::install_conf_file(&file)?;
+                info!("Installed {}", path.display());
+                report.ini_files.push(path);
+            }
+        }
+
+        if let Some(template) = opts.php_ini.take() {
+            let path = ini::install_php_ini(template)?;
+            info!("Installed {}", path.display());
+            report.ini_files.push(path);
+        }
+
+        exit::set_stage(Stage::Packages);
+        if self.save_rundeps {
+            let phase = Instant::now();
+            self.progress.step("Saving runtime dependencies");
+            report.runtime_packages = match system::collect_runtime_packages(&opts.extensions) {
+                Some(packages) => {
+                    self.manager.add_runtime_packages(&packages)?;
+                    packages
+                }
+                None => self.manager.save_runtime_deps()?,
+            };
+            self.progress.finish();
+            report.add_phase("runtime dependencies", phase.elapsed());
+        }
+
+        let imagick = opts
+            .extensions
+            .iter()
+            .any(|extension| extension.key() == "pecl:imagick");
+        if imagick && (opts.imagick_pdf.is_some() || opts.imagick_policy.is_some()) {
+            // The policy file is owned by the main imagemagick package, not the libraries.
+            let mut packages = vec!["imagemagick"];
+            if let Some(pdf) = opts.imagick_pdf {
+                packages.extend(pdf.packages());
+            }
+            self.manager.add_packages(&packages)?;
+            report
+                .runtime_packages
+                .extend(packages.iter().map(|package| String::from(*package)));
+
+            exit::set_stage(Stage::Enable);
+            let script = imagick::policy_script(opts.imagick_policy.as_deref(), opts.imagick_pdf);
+            if !command::skip_step("update ImageMagick's policy.xml", script) {
+                let policy = imagick::find_policy()?;
+                if let Some(source) = &opts.imagick_policy {
+                    imagick::install_policy(&policy, source)?;
+                }
+                if let Some(pdf) = opts.imagick_pdf {
+                    imagick::apply_pdf_policy(&policy, pdf)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the build dependencies, then checks that PHP loads the extensions and
+    /// runs their tests. Once this succeeds, nothing is rolled back.
+    fn verify(&mut self) -> Result<()> {
+        let opts = &mut self.opts;
+        let progress = &mut self.progress;
+        let report = &mut self.report;
+        let failures = &mut self.failures;
+        let rollback = &mut self.rollback;
+
+        self.sizes_built = match self.sizes_before {
+            Some(_) => self.manager.package_sizes().ok(),
+            None => None,
+        };
+
+        exit::set_stage(Stage::Packages);
+        if self.installing {
+            let phase = Instant::now();
+            progress.step("Removing build dependencies");
+            self.manager.remove_build_deps()?;
+            rollback.build_deps = false;
+            progress.finish();
+            report.add_phase("cleanup", phase.elapsed());
+        }
+
+        // Check that PHP loads what was installed only now, so that a library that went
+        // with the build dependencies fails the build instead of the application.
+        exit::set_stage(Stage::Enable);
+        if self.installing {
+            progress.step("Checking that PHP loads the extensions");
+            let require_loaded = opts.require_loaded;
+            let checked = opts.extensions.iter().filter(|extension| {
+                if require_loaded {
+                    !extension.is_config_only()
+                } else {
+                    cli_loads(extension)
+                }
+            });
+            for extension in checked {
+                failures.check(&extension.key(), check_loaded(extension))?;
+            }
+            progress.finish();
+
+            exit::set_stage(Stage::Other);
+            for extension in opts.extensions.iter().filter(|_| opts.run_tests) {
+                let pecl = match extension {
+                    Extension::Pecl(pecl) => pecl,
+                    _ => continue,
+                };
+                if failures.has_failed(&extension.key()) {
+                    if has_tests(extension) {
+                        progress.skip();
+                    }
+                    continue;
+                }
+                if !cli_loads(extension) {
+                    warn!(
+                        "Not running {}'s tests, since PHP's CLI doesn't load it",
+                        extension.key()
+                    );
+                    continue;
+                }
+                if pecl.source_url().is_some() {
+                    warn!(
+                        "Not running {}'s tests, since it wasn't installed by pecl",
+                        extension.key()
+                    );
+                    continue;
+                }
+
+                let phase = Instant::now();
+                progress.step(format_args!("Testing {}", extension.key()));
+                let result = run_tests(pecl, opts.test_timeout);
+                progress.finish();
+                report.add_phase(format!("test {}", extension.key()), phase.elapsed());
+                failures.check(&extension.key(), result)?;
+            }
+
+            failures.skip_dependents(&opts.extensions);
+            for key in failures.keys() {
+                rollback.undo_extension(&key);
+            }
+            opts.extensions
+                .retain(|extension| !failures.has_failed(&extension.key()));
+        }
+        rollback.finish();
+
+        Ok(())
+    }
+
+    /// Reports what the installation did, and writes the requested SBOM, labels, and
+    /// report, and the manifest.
+    fn record(self) -> Result<()> {
+        let Self {
+            opts,
+            start,
+            platform,
+            manager,
+            mut report,
+            loaded_before,
+            plan,
+            packages_before,
+            sizes_before,
+            sizes_built,
+            failures,
+            ..
+        } = self;
+
+        if let (Some(before), Some(built)) = (&sizes_before, &sizes_built) {
+            if let Ok(after) = manager.package_sizes() {
+                let sizes = measure_sizes(&opts.extensions, before, built, &after);
+                info!("{}", sizes.summary());
+                report.sizes = Some(sizes);
+            }
+        }
+
+        // Comparing `php -m` confirms which extensions this run actually made PHP load.
+        if let (false, Some(before)) = (command::is_dry_run(), &loaded_before) {
+            if let Ok(after) = system::loaded_extensions() {
+                let changes = ModuleChanges::between(before, &after);
+                if !changes.loaded.is_empty() {
+                    info!("PHP now loads: {}", changes.loaded.join(", "));
+                }
+                if !changes.unloaded.is_empty() {
+                    warn!("PHP no longer loads: {}", changes.unloaded.join(", "));
+                }
+                report.modules = Some(changes);
+            }
+        }
+
+        report.set_duration(start.elapsed());
+        if !command::is_dry_run() && !report.phases.is_empty() {
+            info!("{}", report.timing_summary());
+        }
+
+        exit::set_stage(Stage::Other);
+        let format = opts.report_format();
+
+        let pecl_versions = if opts
+            .extensions
+            .iter()
+            .any(|e| matches!(e, Extension::Pecl(_)))
+        {
+            system::pecl_versions().unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+        let installed: Vec<_> = opts
+            .extensions
+            .iter()
+            .map(|extension| InstalledExtension {
+                key: extension.key(),
+                version: installed_version(extension, &platform, &pecl_versions),
+            })
+            .collect();
+
+        if let Some(path) = &opts.sbom {
+            let packages_after = manager
+                .installed_packages()
+                .context("Failed to list installed packages")?;
+            let mut components: Vec<_> = opts
+                .extensions
+                .iter()
+                .zip(&installed)
+                .filter_map(|(extension, installed)| {
+                    let kind = match extension {
+                        Extension::Builtin(_) => ComponentKind::Builtin,
+                        Extension::Pecl(_) => ComponentKind::Pecl,
+                        Extension::Config(_) => return None,
+                    };
+                    Some(Component::new(
+                        kind,
+                        extension.name(),
+                        installed.version.as_deref(),
+                    ))
+                })
+                .collect();
+            components.extend(
+                packages_after
+                    .iter()
+                    .filter(|&(name, version)| packages_before.get(name) != Some(version))
+                    .map(|(name, version)| {
+                        Component::new(ComponentKind::Apk, name, Some(version))
+                            .with_arch(platform.arch())
+                    }),
+            );
+
+            write_sbom(path, &sbom::document(opts.sbom_format, &components))?;
+        }
+
+        if opts.labels || opts.labels_file.is_some() {
+            write_labels(&installed, opts.labels_file.as_ref())?;
+        }
+
+        report.extensions = installed;
+        record_invocation(Invocation {
+            extensions: report.extensions.clone(),
+            build_packages: report.build_packages.clone(),
+            runtime_packages: report.runtime_packages.clone(),
+            plan: Some(plan),
+            ..Invocation::new()
+        });
+
+        report.failed = failures.to_report();
+        report.skipped.extend(failures.skipped_report());
+        if let Some(format) = format {
+            write_report(&report, format, opts.output_file.as_ref())?;
+        }
+
+        failures.into_result()
+    }
+}
+
+/// Runs the command given by the parsed command line.
+fn run(matches: &ArgMatches<'_>, start: Instant) -> Result<()> {
+    exit::set_stage(Stage::Parse);
+    let mut opts = Opts::from_clap(matches);
+    init_logging(opts.verbose, opts.quiet, opts.color);
+    opts.extensions = parse_extensions(&opts.specs)?;
+    attach_ini_settings(matches, &mut opts)?;
+
+    ini::set_dirs(opts.ini_dir.take(), opts.scan_dir.take());
+
+    command::set_retries(opts.retries, opts.retry_delay);
+    command::set_timeouts(opts.timeout, opts.total_timeout.map(|total| start + total));
+    command::set_report_on_stdout(opts.report_format().is_some() && opts.output_file.is_none());
+    source::set_require_checksums(opts.require_checksums);
+    if let (Some(keyring), Some(dir)) = (&opts.source_keyring, &opts.source_signatures) {
+        source::require_signatures(keyring, dir)?;
+    }
+
+    if let Some(path) = &opts.log_file {
+        command::set_log_file(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+    }
+
+    // docker-php-ext-* read PHP_INI_DIR, which is exported to them when it is overridden.
+    // (With emit-script, global options like --ini-dir may have been set by the outer
+    // command line.)
+    if ini::ini_dir_overridden() {
+        let dir = ini::ini_dir();
+        command::skip_step(
+            format_args!("export PHP_INI_DIR={}", dir.display()),
+            format_args!("export PHP_INI_DIR={}", command::quote_path(&dir)),
+        );
+    }
+
+    exit::set_stage(Stage::Other);
+    match opts.command {
+        Some(Command::Registry(command)) => return run_registry_command(command),
+        Some(Command::Enable { extensions, no_ini }) => {
+            exit::set_stage(Stage::Enable);
+            return enable_extensions(extensions, no_ini);
+        }
+        Some(Command::Remove {
+            extensions,
+            prune_packages,
+            dry_run,
+        }) => {
+            if dry_run {
+                command::set_mode(Mode::DryRun);
+            }
+            return remove_extensions(extensions, prune_packages);
+        }
+        Some(Command::Disable { extensions }) => return disable_extensions(&extensions),
+        Some(Command::Explain { extensions }) => return explain_extensions(extensions),
+        Some(Command::EmitScript { args }) => return emit_script(args, start),
+        Some(Command::List) => return list_extensions(),
+        None => {}
+    }
+
+    if opts.dry_run {
+        command::set_mode(Mode::DryRun);
+    }
+
+    let mut installation = match Installation::preflight(opts, start)? {
+        Some(installation) => installation,
+        None => return Ok(()),
+    };
+    installation.install()?;
+    installation.configure()?;
+    installation.verify()?;
+    installation.record()
+}
+
+fn main() {
+    let start = Instant::now();
+
+    // The supplemental registry has to be loaded before the command line is parsed, since
+    // parsing an extension looks up its registry data.
+    let result = Registry::load_supplemental()
+        .map_err(anyhow::Error::from)
+        .and_then(|()| run(&parse_args(env::args()), start));
+
+    if let Err(error) = result {
+        let mut message = error.to_string();
+        eprintln!("{}: {}", color::paint(Style::Error, "error"), message);
+
+        // Most of this tool's errors already include their cause.
+        for cause in error.chain().skip(1) {
+            let cause = cause.to_string();
+            if !message.contains(&cause) {
+                eprintln!("  caused by: {}", cause);
+            }
+            message = cause;
+        }
+        process::exit(exit::stage().code());
+    }
+}
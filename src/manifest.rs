@@ -0,0 +1,285 @@
+//! A record, kept inside the image, of what every `f1-ext-install` invocation did, so that
+//! later layers (or `f1-ext-install list`) can see what the tool installed across the
+//! image's history.
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{report::InstalledExtension, sbom::format_timestamp};
+
+/// Location of the manifest.
+pub const MANIFEST_PATH: &str = "/usr/local/etc/f1-ext-install/manifest.json";
+
+/// Errors encountered while reading or writing the manifest.
+#[derive(Debug, Snafu)]
+pub enum ManifestError {
+    /// The manifest could not be read.
+    #[snafu(display("Failed to read {}: {}", path.display(), source))]
+    Read {
+        /// The underlying IO error
+        source: io::Error,
+        /// The path being read
+        path: PathBuf,
+    },
+
+    /// The manifest could not be written.
+    #[snafu(display("Failed to write {}: {}", path.display(), source))]
+    Write {
+        /// The underlying IO error
+        source: io::Error,
+        /// The path being written
+        path: PathBuf,
+    },
+
+    /// The manifest is not valid JSON.
+    #[snafu(display("Failed to parse {}: {}", path.display(), source))]
+    Parse {
+        /// The underlying JSON error
+        source: serde_json::Error,
+        /// The path being parsed
+        path: PathBuf,
+    },
+}
+
+/// Helper type for the result of manifest operations.
+pub type Result<T> = std::result::Result<T, ManifestError>;
+
+/// What one invocation of `f1-ext-install` did.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Invocation {
+    /// When the invocation finished, as an RFC 3339 timestamp.
+    pub timestamp: String,
+    /// The command-line arguments.
+    pub args: Vec<String>,
+    /// A fingerprint (see [`fingerprint`]) of everything that decided what this
+    /// invocation installed, for recognizing an identical repeat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    /// The extensions installed or configured, with their versions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<InstalledExtension>,
+    /// The extensions removed (e.g., `pecl:xdebug`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+    /// The packages installed for the build and removed afterwards.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub build_packages: Vec<String>,
+    /// The packages (or `so:` library dependencies) kept at run time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runtime_packages: Vec<String>,
+}
+
+impl Invocation {
+    /// Starts a record of the current invocation, stamped with the current time.
+    pub fn new() -> Self {
+        Self {
+            timestamp: format_timestamp(SystemTime::now()),
+            args: env::args().skip(1).collect(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Returns a stable fingerprint (a 64-bit FNV-1a hash, in hex) of `description`. Unlike
+/// the standard library's hashers, it doesn't change between builds of this tool.
+pub fn fingerprint(description: &str) -> String {
+    let hash = description
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+    format!("{:016x}", hash)
+}
+
+/// Every recorded invocation, oldest first.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    /// The recorded invocations.
+    pub invocations: Vec<Invocation>,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, or returns an empty one if there is none yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => {
+                return Err(ManifestError::Read {
+                    source,
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+
+        serde_json::from_str(&contents).context(Parse { path })
+    }
+
+    /// Adds `invocation` to the manifest at `path`.
+    pub fn record(path: &Path, invocation: Invocation) -> Result<()> {
+        let mut manifest = Self::load(path)?;
+        manifest.invocations.push(invocation);
+
+        // Serializing plain data to JSON can't fail.
+        let contents = serde_json::to_string_pretty(&manifest).unwrap_or_default();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(Write { path: dir })?;
+        }
+        fs::write(path, contents + "\n").context(Write { path })
+    }
+
+    /// Returns the extensions still installed after every recorded invocation, keyed by
+    /// extension key (e.g., `pecl:redis`), along with the invocation that installed them.
+    /// `config:` specs only configured an extension, so they aren't included.
+    pub fn installed(&self) -> BTreeMap<&str, (&InstalledExtension, &Invocation)> {
+        let mut installed = BTreeMap::new();
+
+        for invocation in &self.invocations {
+            for extension in &invocation.extensions {
+                if !extension.key.starts_with("config:") {
+                    installed.insert(extension.key.as_str(), (extension, invocation));
+                }
+            }
+            for key in &invocation.removed {
+                installed.remove(key.as_str());
+            }
+        }
+
+        installed
+    }
+
+    /// Finds the latest invocation with the same `plan` fingerprint, if it installed or
+    /// configured every extension in `keys` (e.g., `pecl:redis`) and no later invocation
+    /// has since reinstalled or removed the ones it installed.
+    pub fn find_repeat(&self, plan: &str, keys: &[String]) -> Option<&Invocation> {
+        let previous = self
+            .invocations
+            .iter()
+            .rev()
+            .find(|invocation| invocation.plan.as_deref() == Some(plan))?;
+        let installed = self.installed();
+
+        let unchanged = keys.iter().all(|key| {
+            if key.starts_with("config:") {
+                previous
+                    .extensions
+                    .iter()
+                    .any(|extension| &extension.key == key)
+            } else {
+                installed
+                    .get(key.as_str())
+                    .is_some_and(|(_, by)| std::ptr::eq(*by, previous))
+            }
+        });
+
+        unchanged.then_some(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installed() {
+        let extension = |key: &str, version: &str| InstalledExtension {
+            key: String::from(key),
+            version: Some(String::from(version)),
+        };
+
+        let manifest = Manifest {
+            invocations: vec![
+                Invocation {
+                    extensions: vec![
+                        extension("pecl:redis", "5.3.7"),
+                        extension("pecl:xdebug", "3.3.1"),
+                    ],
+                    ..Invocation::default()
+                },
+                Invocation {
+                    extensions: vec![InstalledExtension {
+                        key: String::from("config:builtin:opcache"),
+                        version: None,
+                    }],
+                    ..Invocation::default()
+                },
+                Invocation {
+                    extensions: vec![extension("pecl:redis", "6.0.2")],
+                    removed: vec![String::from("pecl:xdebug")],
+                    ..Invocation::default()
+                },
+            ],
+        };
+
+        let installed = manifest.installed();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed["pecl:redis"].0.version.as_deref(), Some("6.0.2"));
+    }
+
+    #[test]
+    fn test_find_repeat() {
+        let strings = |items: &[&str]| -> Vec<String> {
+            items.iter().map(|item| String::from(*item)).collect()
+        };
+        let extension = |key: &str| InstalledExtension {
+            key: String::from(key),
+            version: None,
+        };
+
+        let mut manifest = Manifest {
+            invocations: vec![
+                Invocation {
+                    args: strings(&["pecl:redis"]),
+                    extensions: vec![extension("pecl:redis")],
+                    ..Invocation::default()
+                },
+                Invocation {
+                    args: strings(&["pecl:redis", "config:opcache"]),
+                    plan: Some(String::from("plan")),
+                    extensions: vec![extension("pecl:redis"), extension("config:builtin:opcache")],
+                    ..Invocation::default()
+                },
+            ],
+        };
+        let keys = strings(&["pecl:redis", "config:builtin:opcache"]);
+
+        assert!(manifest.find_repeat("plan", &keys).is_some());
+        assert!(manifest.find_repeat("other", &keys[..1]).is_none());
+        assert!(manifest
+            .find_repeat("plan", &strings(&["pecl:redis", "pecl:apcu"]))
+            .is_none());
+
+        manifest.invocations.push(Invocation {
+            removed: strings(&["pecl:redis"]),
+            ..Invocation::default()
+        });
+        assert!(manifest.find_repeat("plan", &keys).is_none());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(""), "cbf29ce484222325");
+        assert_eq!(fingerprint("a"), "af63dc4c8601ec8c");
+        assert_ne!(fingerprint("pecl:redis"), fingerprint("pecl:redis "));
+    }
+
+    #[test]
+    fn test_record() {
+        let dir = std::env::temp_dir().join(format!("f1-manifest-{}", std::process::id()));
+        let path = dir.join("manifest.json");
+
+        for _ in 0..2 {
+            Manifest::record(&path, Invocation::default()).unwrap();
+        }
+        assert_eq!(Manifest::load(&path).unwrap().invocations.len(), 2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
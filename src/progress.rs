@@ -0,0 +1,171 @@
+//! Progress indicators for the steps of an installation.
+//!
+//! In a terminal, the current step is shown with a spinner and its elapsed time, so that
+//! long compiles (e.g., grpc or mongodb) don't look like a hang. Elsewhere (such as in
+//! `docker build`), each step is logged as a plain line.
+
+use log::{info, LevelFilter};
+use std::{
+    fmt,
+    io::{self, IsTerminal as _, Write as _},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::system::command;
+
+/// The frames of the spinner.
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// How often the spinner is redrawn.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Held while the spinner is drawn, and while anything else is written to stderr during a
+/// step (see [`suspend`]), so that the two don't overwrite each other.
+static TERMINAL: Mutex<()> = Mutex::new(());
+
+/// Whether a spinner is currently drawn.
+static SPINNING: AtomicBool = AtomicBool::new(false);
+
+/// Erases the spinner, if one is drawn, and keeps it from being redrawn until the returned
+/// guard is dropped, so that output written meanwhile (e.g., a failed command's output or
+/// a warning) isn't drawn over. The spinner then continues on the line after the output.
+pub fn suspend() -> MutexGuard<'static, ()> {
+    let guard = TERMINAL.lock().unwrap_or_else(|error| error.into_inner());
+    if SPINNING.load(Ordering::Relaxed) {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r\x1b[K");
+        let _ = stderr.flush();
+    }
+
+    guard
+}
+
+/// A spinner redrawn on stderr by a background thread until it is stopped.
+#[derive(Debug)]
+struct Spinner {
+    /// Set to stop the thread.
+    stop: Arc<AtomicBool>,
+    /// The thread drawing the spinner.
+    thread: JoinHandle<()>,
+}
+
+impl Spinner {
+    /// Starts drawing a spinner in front of `line`.
+    fn start(line: String) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        SPINNING.store(true, Ordering::Relaxed);
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let start = Instant::now();
+                let mut stderr = io::stderr();
+                for frame in FRAMES.iter().cycle() {
+                    let terminal = TERMINAL.lock().unwrap_or_else(|error| error.into_inner());
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    let _ = write!(
+                        stderr,
+                        "\r\x1b[K{} {} ({}s)",
+                        frame,
+                        line,
+                        start.elapsed().as_secs()
+                    );
+                    let _ = stderr.flush();
+                    drop(terminal);
+                    thread::sleep(TICK);
+                }
+            })
+        };
+
+        Self { stop, thread }
+    }
+
+    /// Stops the spinner and erases it.
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+        SPINNING.store(false, Ordering::Relaxed);
+        let _ = write!(io::stderr(), "\r\x1b[K");
+    }
+}
+
+/// Shows which step of an installation is running, out of how many.
+#[derive(Debug)]
+pub struct Progress {
+    /// The number of steps expected.
+    total: usize,
+    /// The number of steps started so far.
+    current: usize,
+    /// The running step and when it started, if a spinner is shown.
+    running: Option<(String, Instant)>,
+    /// The spinner for the running step.
+    spinner: Option<Spinner>,
+    /// Whether to draw spinners (only in a terminal, and only when nothing else is
+    /// logged while commands run).
+    interactive: bool,
+}
+
+impl Progress {
+    /// Creates an indicator for `total` steps. Spinners are drawn when stderr is a
+    /// terminal and only informational messages are logged; otherwise, steps are logged.
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            current: 0,
+            running: None,
+            spinner: None,
+            interactive: io::stderr().is_terminal() && log::max_level() == LevelFilter::Info,
+        }
+    }
+
+    /// Finishes the running step (if any) and starts the next one. Nothing is shown for
+    /// dry runs, whose output is the commands themselves.
+    pub fn step(&mut self, message: impl fmt::Display) {
+        self.finish();
+        if command::is_dry_run() {
+            return;
+        }
+
+        self.current += 1;
+
+        let line = format!("[{}/{}] {}", self.current, self.total, message);
+        if self.interactive {
+            self.spinner = Some(Spinner::start(line.clone()));
+            self.running = Some((line, Instant::now()));
+        } else {
+            info!("{}", line);
+        }
+    }
+
+    /// Drops a step that was counted but won't run after all (e.g., compiling an extension
+    /// whose requirement failed), so that the last step still ends the count.
+    pub fn skip(&mut self) {
+        self.total = self.total.saturating_sub(1);
+    }
+
+    /// Finishes the running step, leaving a line with how long it took.
+    pub fn finish(&mut self) {
+        if let Some(spinner) = self.spinner.take() {
+            spinner.stop();
+        }
+        if let Some((line, start)) = self.running.take() {
+            info!("{} ({:.1}s)", line, start.elapsed().as_secs_f64());
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Erases the spinner if the installation failed partway through.
+        if let Some(spinner) = self.spinner.take() {
+            spinner.stop();
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! A machine-readable summary of an installation, emitted with `--output json` for CI
+//! pipelines and image-scanning tools.
+
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::{collections::BTreeMap, path::PathBuf, str::FromStr, time::Duration};
+
+/// Errors encountered while choosing a report format.
+#[derive(Debug, Snafu)]
+pub enum ReportError {
+    /// The requested report format is not supported.
+    #[snafu(display(r#"Unknown output format "{}" (expected "json")"#, format))]
+    UnknownFormat {
+        /// The format name that was given
+        format: String,
+    },
+}
+
+/// The formats a report can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// JSON.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ReportError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "json" => Ok(Self::Json),
+            _ => Err(ReportError::UnknownFormat {
+                format: String::from(input),
+            }),
+        }
+    }
+}
+
+/// An extension that was installed (or, for `config:` specs, configured).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InstalledExtension {
+    /// The extension's key (e.g., `pecl:redis`).
+    pub key: String,
+    /// The version installed: the PECL release, or the PHP version for builtins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// An extension that was requested but not installed.
+#[derive(Debug, Serialize)]
+pub struct SkippedExtension {
+    /// The extension's key (e.g., `builtin:sodium`).
+    pub key: String,
+    /// Why the extension was skipped.
+    pub reason: String,
+}
+
+/// An extension that failed to install with `--keep-going`.
+#[derive(Debug, Serialize)]
+pub struct FailedExtension {
+    /// The extension's key (e.g., `pecl:grpc`).
+    pub key: String,
+    /// Why the extension failed.
+    pub error: String,
+}
+
+/// How long one phase of an installation took.
+#[derive(Debug, Serialize)]
+pub struct Phase {
+    /// What the phase did (e.g., `compile pecl:grpc`).
+    pub name: String,
+    /// How long the phase took, in seconds.
+    pub duration_secs: f64,
+}
+
+/// How much an installation removed from and left in the image, in bytes.
+#[derive(Debug, Default, Serialize)]
+pub struct Sizes {
+    /// The installed size of the build dependencies removed afterwards.
+    pub build_deps_removed: u64,
+    /// The installed size of each package the installation left in the image.
+    pub runtime_packages: BTreeMap<String, u64>,
+    /// The size of each extension module (e.g., `redis.so`) installed.
+    pub modules: BTreeMap<String, u64>,
+}
+
+/// How many of the largest runtime packages [`Sizes::summary`] names.
+const LARGEST_PACKAGES: usize = 5;
+
+/// Formats a size in bytes for people (e.g., `1.5 MiB`).
+pub fn format_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = units[0];
+    for next in &units[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+
+    format!("{:.1} {}", size, unit)
+}
+
+impl Sizes {
+    /// Describes what the installation saved by removing its build dependencies, and
+    /// what it kept, naming the largest runtime packages.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Removed {} of build dependencies; kept {} of runtime packages and {} of \
+             extension modules",
+            format_size(self.build_deps_removed),
+            format_size(self.runtime_packages.values().sum()),
+            format_size(self.modules.values().sum()),
+        );
+
+        let mut packages: Vec<_> = self.runtime_packages.iter().collect();
+        packages.sort_by(|a, b| b.1.cmp(a.1));
+        let largest: Vec<_> = packages
+            .iter()
+            .take(LARGEST_PACKAGES)
+            .map(|(name, size)| format!("{} ({})", name, format_size(**size)))
+            .collect();
+        if !largest.is_empty() {
+            summary.push_str("\nLargest runtime packages: ");
+            summary.push_str(&largest.join(", "));
+        }
+
+        summary
+    }
+}
+
+/// The change in the modules PHP loads (as listed by `php -m`) over an installation.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ModuleChanges {
+    /// The modules loaded afterwards that weren't before.
+    pub loaded: Vec<String>,
+    /// The modules loaded before that aren't afterwards.
+    pub unloaded: Vec<String>,
+}
+
+impl ModuleChanges {
+    /// Compares the modules loaded `before` and `after` an installation.
+    pub fn between(before: &[String], after: &[String]) -> Self {
+        let difference = |a: &[String], b: &[String]| {
+            let mut modules: Vec<_> = a.iter().filter(|m| !b.contains(m)).cloned().collect();
+            modules.sort();
+            modules
+        };
+
+        Self {
+            loaded: difference(after, before),
+            unloaded: difference(before, after),
+        }
+    }
+}
+
+/// The outcome of an installation.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// The extensions installed or configured, in installation order.
+    pub extensions: Vec<InstalledExtension>,
+    /// The extensions that were requested but skipped.
+    pub skipped: Vec<SkippedExtension>,
+    /// The extensions that failed to install with `--keep-going`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<FailedExtension>,
+    /// The packages installed for the build and removed afterwards.
+    pub build_packages: Vec<String>,
+    /// The packages (or `so:` library dependencies) kept for the extensions at run time.
+    pub runtime_packages: Vec<String>,
+    /// The ini files written.
+    pub ini_files: Vec<PathBuf>,
+    /// Things the user should know about, such as extensions that are only loaded with an
+    /// adjusted `PHP_INI_SCAN_DIR`.
+    pub warnings: Vec<String>,
+    /// The modules PHP started (or stopped) loading, if `php -m` could be run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modules: Option<ModuleChanges>,
+    /// How much space the installation saved and used, if it could be measured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sizes: Option<Sizes>,
+    /// How long each phase of the installation took, in order.
+    pub phases: Vec<Phase>,
+    /// How long the installation took, in seconds.
+    pub duration_secs: f64,
+}
+
+impl Report {
+    /// Records how long the installation took.
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration_secs = duration.as_secs_f64();
+    }
+
+    /// Records how long a phase of the installation took.
+    pub fn add_phase(&mut self, name: impl Into<String>, duration: Duration) {
+        self.phases.push(Phase {
+            name: name.into(),
+            duration_secs: duration.as_secs_f64(),
+        });
+    }
+
+    /// Formats the phase timings as a table for people, ending with the total duration.
+    pub fn timing_summary(&self) -> String {
+        let width = self
+            .phases
+            .iter()
+            .map(|phase| phase.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("total".len());
+
+        let mut summary = String::from("Timing:");
+        let rows = self
+            .phases
+            .iter()
+            .map(|phase| (phase.name.as_str(), phase.duration_secs))
+            .chain(std::iter::once(("total", self.duration_secs)));
+        for (name, secs) in rows {
+            summary.push_str(&format!(
+                "\n  {:width$}  {:>7.1}s",
+                name,
+                secs,
+                width = width
+            ));
+        }
+
+        summary
+    }
+
+    /// Renders this report in the given format.
+    pub fn to_string(&self, format: OutputFormat) -> serde_json::Result<String> {
+        match format {
+            OutputFormat::Json => serde_json::to_string_pretty(self),
+        }
+    }
+}
+
+/// The prefix of the OCI labels suggested for installed extensions.
+const LABEL_PREFIX: &str = "org.forumone.php-ext.";
+
+/// Formats a Dockerfile `LABEL` instruction naming each installed extension and its
+/// version (e.g., `org.forumone.php-ext.redis="6.0.2"`), so that the image's extensions
+/// can be seen with `docker inspect`. `config:` specs don't install anything, so they
+/// are left out. Returns `None` if there is nothing to label.
+pub fn oci_labels(extensions: &[InstalledExtension]) -> Option<String> {
+    let labels: Vec<_> = extensions
+        .iter()
+        .filter(|extension| !extension.key.starts_with("config:"))
+        .map(|extension| {
+            let name = extension.key.rsplit(':').next().unwrap_or(&extension.key);
+            let version = extension.version.as_deref().unwrap_or_default();
+            format!(
+                r#"{}{}="{}""#,
+                LABEL_PREFIX,
+                name.to_ascii_lowercase(),
+                version
+            )
+        })
+        .collect();
+
+    if labels.is_empty() {
+        return None;
+    }
+
+    Some(format!("LABEL {}", labels.join(" \\\n      ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oci_labels() {
+        let extensions = [
+            InstalledExtension {
+                key: String::from("pecl:redis"),
+                version: Some(String::from("6.0.2")),
+            },
+            InstalledExtension {
+                key: String::from("config:builtin:opcache"),
+                version: None,
+            },
+            InstalledExtension {
+                key: String::from("builtin:gd"),
+                version: Some(String::from("8.2.13")),
+            },
+        ];
+
+        assert_eq!(
+            oci_labels(&extensions).unwrap(),
+            "LABEL org.forumone.php-ext.redis=\"6.0.2\" \\\n      org.forumone.php-ext.gd=\"8.2.13\""
+        );
+        assert!(oci_labels(&extensions[1..2]).is_none());
+    }
+
+    #[test]
+    fn test_json() {
+        let mut report = Report::default();
+        report.extensions.push(InstalledExtension {
+            key: String::from("pecl:redis"),
+            version: Some(String::from("6.0.2")),
+        });
+        report.extensions.push(InstalledExtension {
+            key: String::from("config:builtin:opcache"),
+            version: None,
+        });
+        report.add_phase("build dependencies", Duration::from_millis(500));
+        report.set_duration(Duration::from_millis(1500));
+
+        let value: serde_json::Value =
+            serde_json::from_str(&report.to_string(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(value["extensions"][0]["version"], "6.0.2");
+        assert!(value["extensions"][1].get("version").is_none());
+        assert_eq!(value["duration_secs"], 1.5);
+        assert_eq!(value["phases"][0]["duration_secs"], 0.5);
+        assert_eq!(value["warnings"], serde_json::json!([]));
+
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_module_changes() {
+        let modules = |names: &[&str]| -> Vec<String> {
+            names.iter().map(|name| String::from(*name)).collect()
+        };
+
+        let changes = ModuleChanges::between(
+            &modules(&["core", "xdebug", "zlib"]),
+            &modules(&["zlib", "redis", "core", "igbinary"]),
+        );
+        assert_eq!(changes.loaded, modules(&["igbinary", "redis"]));
+        assert_eq!(changes.unloaded, modules(&["xdebug"]));
+    }
+
+    #[test]
+    fn test_sizes_summary() {
+        let mut sizes = Sizes {
+            build_deps_removed: 190_000_000,
+            ..Sizes::default()
+        };
+        sizes
+            .runtime_packages
+            .insert(String::from("libzip"), 90_112);
+        sizes
+            .runtime_packages
+            .insert(String::from("imagemagick-libs"), 12_582_912);
+        sizes.modules.insert(String::from("redis.so"), 800);
+
+        assert_eq!(
+            sizes.summary(),
+            "Removed 181.2 MiB of build dependencies; kept 12.1 MiB of runtime packages and \
+             800 B of extension modules\n\
+             Largest runtime packages: imagemagick-libs (12.0 MiB), libzip (88.0 KiB)"
+        );
+    }
+
+    #[test]
+    fn test_timing_summary() {
+        let mut report = Report::default();
+        report.add_phase("compile pecl:grpc", Duration::from_secs(312));
+        report.set_duration(Duration::from_millis(330_250));
+
+        assert_eq!(
+            report.timing_summary(),
+            "Timing:\n  compile pecl:grpc    312.0s\n  total                330.2s"
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Software bills of materials (SBOMs) listing what an installation added to an image, so
+//! that image SBOM tooling captures the extensions and packages `f1-ext-install`
+//! introduced.
+
+use serde_json::{json, Value};
+use snafu::Snafu;
+use std::{
+    process,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Errors encountered while choosing an SBOM format.
+#[derive(Debug, Snafu)]
+pub enum SbomError {
+    /// The requested SBOM format is not supported.
+    #[snafu(display(r#"Unknown SBOM format "{}" (expected "cyclonedx" or "spdx")"#, format))]
+    UnknownFormat {
+        /// The format name that was given
+        format: String,
+    },
+}
+
+/// The SBOM standards that can be written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbomFormat {
+    /// CycloneDX 1.5, as JSON.
+    CycloneDx,
+    /// SPDX 2.3, as JSON.
+    Spdx,
+}
+
+impl FromStr for SbomFormat {
+    type Err = SbomError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "cyclonedx" => Ok(Self::CycloneDx),
+            "spdx" => Ok(Self::Spdx),
+            _ => Err(SbomError::UnknownFormat {
+                format: String::from(input),
+            }),
+        }
+    }
+}
+
+/// What kind of thing a [`Component`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentKind {
+    /// A PHP extension compiled from PHP's source (e.g., `gd`).
+    Builtin,
+    /// A PHP extension from PECL (e.g., `redis`).
+    Pecl,
+    /// An Alpine package.
+    Apk,
+}
+
+/// An extension or package added to the image.
+#[derive(Clone, Debug)]
+pub struct Component {
+    /// What kind of component this is.
+    kind: ComponentKind,
+    /// The extension or package name.
+    name: String,
+    /// The version installed, if known.
+    version: Option<String>,
+    /// The package's architecture (for apk packages), if known.
+    arch: Option<String>,
+}
+
+impl Component {
+    /// Creates a component for a PHP extension or apk package.
+    pub fn new(kind: ComponentKind, name: &str, version: Option<&str>) -> Self {
+        Self {
+            kind,
+            name: String::from(name),
+            version: version.map(String::from),
+            arch: None,
+        }
+    }
+
+    /// Records the architecture of an apk package (e.g., `x86_64`).
+    pub fn with_arch(mut self, arch: Option<&str>) -> Self {
+        self.arch = arch.map(String::from);
+        self
+    }
+
+    /// Returns a package URL identifying this component.
+    ///
+    /// There is no package URL type for PHP extensions, so they use the `generic` type
+    /// with the `php` (builtins) or `pecl` namespace.
+    fn purl(&self) -> String {
+        let mut purl = match self.kind {
+            ComponentKind::Builtin => format!("pkg:generic/php/{}", self.name),
+            ComponentKind::Pecl => format!("pkg:generic/pecl/{}", self.name),
+            ComponentKind::Apk => format!("pkg:apk/alpine/{}", self.name),
+        };
+
+        if let Some(version) = &self.version {
+            purl.push('@');
+            purl.push_str(version);
+        }
+        if let Some(arch) = &self.arch {
+            purl.push_str("?arch=");
+            purl.push_str(arch);
+        }
+
+        purl
+    }
+
+    /// Returns an SPDX identifier for this component, which may only contain letters,
+    /// digits, `.`, and `-`.
+    fn spdx_id(&self) -> String {
+        let kind = match self.kind {
+            ComponentKind::Builtin => "php",
+            ComponentKind::Pecl => "pecl",
+            ComponentKind::Apk => "apk",
+        };
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+
+        format!("SPDXRef-Package-{}-{}", kind, name)
+    }
+
+    /// Returns where this component can be downloaded, as an SPDX `downloadLocation`.
+    fn download_location(&self) -> String {
+        match (self.kind, &self.version) {
+            (ComponentKind::Pecl, Some(version)) => {
+                format!("https://pecl.php.net/get/{}-{}.tgz", self.name, version)
+            }
+            _ => String::from("NOASSERTION"),
+        }
+    }
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC (e.g., `2024-01-31T12:00:00Z`).
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // Converts days since the epoch to a civil date; see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Builds an SBOM listing `components` in the given format.
+pub fn document(format: SbomFormat, components: &[Component]) -> Value {
+    match format {
+        SbomFormat::CycloneDx => cyclonedx(components),
+        SbomFormat::Spdx => spdx(components),
+    }
+}
+
+/// Builds a CycloneDX (1.5) SBOM listing `components`.
+fn cyclonedx(components: &[Component]) -> Value {
+    let components: Vec<_> = components
+        .iter()
+        .map(|component| {
+            let purl = component.purl();
+            let mut value = json!({
+                "type": "library",
+                "bom-ref": purl,
+                "name": component.name,
+                "purl": purl,
+            });
+            if let Some(version) = &component.version {
+                value["version"] = json!(version);
+            }
+
+            value
+        })
+        .collect();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": format_timestamp(SystemTime::now()),
+            "tools": [{
+                "vendor": "Forum One",
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            }],
+        },
+        "components": components,
+    })
+}
+
+/// Builds an SPDX (2.3) SBOM listing `components`.
+fn spdx(components: &[Component]) -> Value {
+    let now = SystemTime::now();
+
+    let packages: Vec<_> = components
+        .iter()
+        .map(|component| {
+            let mut value = json!({
+                "name": component.name,
+                "SPDXID": component.spdx_id(),
+                "downloadLocation": component.download_location(),
+                "filesAnalyzed": false,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": "NOASSERTION",
+                "copyrightText": "NOASSERTION",
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": component.purl(),
+                }],
+            });
+            if let Some(version) = &component.version {
+                value["versionInfo"] = json!(version);
+            }
+
+            value
+        })
+        .collect();
+
+    let relationships: Vec<_> = components
+        .iter()
+        .map(|component| {
+            json!({
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": component.spdx_id(),
+            })
+        })
+        .collect();
+
+    // The namespace only has to be unique to this document.
+    let nanos = now
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+    let namespace = format!(
+        "https://github.com/forumone/f1-ext-install/spdx/{}-{}",
+        nanos,
+        process::id()
+    );
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": env!("CARGO_PKG_NAME"),
+        "documentNamespace": namespace,
+        "creationInfo": {
+            "created": format_timestamp(now),
+            "creators": [
+                "Organization: Forum One",
+                format!("Tool: {}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            ],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_208_245);
+        assert_eq!(format_timestamp(time), "2024-02-29T12:04:05Z");
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let components = [
+            Component::new(ComponentKind::Pecl, "redis", Some("6.0.2")),
+            Component::new(ComponentKind::Apk, "libzip", Some("1.10.1-r0"))
+                .with_arch(Some("aarch64")),
+        ];
+
+        let bom = document(SbomFormat::CycloneDx, &components);
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(bom["components"][0]["purl"], "pkg:generic/pecl/redis@6.0.2");
+        assert_eq!(
+            bom["components"][1]["purl"],
+            "pkg:apk/alpine/libzip@1.10.1-r0?arch=aarch64"
+        );
+        assert_eq!(bom["components"][1]["version"], "1.10.1-r0");
+    }
+
+    #[test]
+    fn test_spdx() {
+        let components = [
+            Component::new(ComponentKind::Pecl, "pecl_http", Some("4.2.4")),
+            Component::new(ComponentKind::Builtin, "gd", None),
+        ];
+
+        let bom = document(SbomFormat::Spdx, &components);
+        assert_eq!(bom["spdxVersion"], "SPDX-2.3");
+
+        let package = &bom["packages"][0];
+        assert_eq!(package["SPDXID"], "SPDXRef-Package-pecl-pecl-http");
+        assert_eq!(
+            package["downloadLocation"],
+            "https://pecl.php.net/get/pecl_http-4.2.4.tgz"
+        );
+        assert!(bom["packages"][1].get("versionInfo").is_none());
+        assert_eq!(
+            bom["relationships"][1]["relatedSpdxElement"],
+            "SPDXRef-Package-php-gd"
+        );
+
+        assert!("swid".parse::<SbomFormat>().is_err());
+    }
+}
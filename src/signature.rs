@@ -0,0 +1,151 @@
+//! Verification of detached [minisign](https://jedisct1.github.io/minisign/) signatures.
+//!
+//! Signatures protect data that `f1-ext-install` reads from outside the binary (such as
+//! imported registries) against tampering: a compromised mirror could otherwise inject
+//! arbitrary package names or configure flags into an image build. They can also be
+//! required of PECL archives, checked against a keyring of trusted keys.
+
+use minisign_verify::{PublicKey, Signature};
+use snafu::{ResultExt, Snafu};
+
+/// A minisign public key (in base64) compiled into this binary, used when no key is
+/// provided at runtime. Set the `F1_REGISTRY_PUBLIC_KEY` environment variable when
+/// building to bake one in.
+pub const BUILTIN_PUBLIC_KEY: Option<&str> = option_env!("F1_REGISTRY_PUBLIC_KEY");
+
+/// Errors encountered during signature verification.
+#[derive(Debug, Snafu)]
+pub enum SignatureError {
+    /// The public key could not be decoded.
+    #[snafu(display("Invalid minisign public key: {}", source))]
+    InvalidPublicKey {
+        /// The underlying minisign error
+        source: minisign_verify::Error,
+    },
+
+    /// The signature could not be decoded.
+    #[snafu(display("Invalid minisign signature: {}", source))]
+    InvalidSignature {
+        /// The underlying minisign error
+        source: minisign_verify::Error,
+    },
+
+    /// The signature does not match the data or the public key.
+    #[snafu(display("Signature verification failed: {}", source))]
+    Mismatch {
+        /// The underlying minisign error
+        source: minisign_verify::Error,
+    },
+
+    /// A keyring has no public keys in it.
+    #[snafu(display("The keyring has no minisign public keys"))]
+    EmptyKeyring,
+}
+
+/// Verifies that `signature` (the contents of a `.minisig` file) is a valid signature of
+/// `data` by the holder of `public_key` (a base64-encoded minisign public key).
+pub fn verify(data: &[u8], signature: &str, public_key: &str) -> Result<(), SignatureError> {
+    let public_key = PublicKey::from_base64(public_key.trim()).context(InvalidPublicKey)?;
+    let signature = Signature::decode(signature).context(InvalidSignature)?;
+
+    public_key.verify(data, &signature, false).context(Mismatch)
+}
+
+/// Parses a keyring: minisign public keys (in base64), one per line. Blank lines, `#`
+/// comments, and the `untrusted comment:` lines of minisign `.pub` files are skipped, so
+/// `.pub` files can simply be concatenated. Every key must be valid.
+pub fn parse_keyring(contents: &str) -> Result<Vec<String>, SignatureError> {
+    let keys: Vec<_> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            !line.is_empty() && !line.starts_with('#') && !line.starts_with("untrusted comment:")
+        })
+        .map(String::from)
+        .collect();
+
+    for key in &keys {
+        PublicKey::from_base64(key).context(InvalidPublicKey)?;
+    }
+
+    if keys.is_empty() {
+        return Err(SignatureError::EmptyKeyring);
+    }
+
+    Ok(keys)
+}
+
+/// Verifies that `signature` is a valid signature of `data` by the holder of any of the
+/// public `keys`.
+pub fn verify_any(data: &[u8], signature: &str, keys: &[String]) -> Result<(), SignatureError> {
+    let mut result = Err(SignatureError::EmptyKeyring);
+    for key in keys {
+        result = verify(data, signature, key);
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+
+    /// Public key from the minisign-verify documentation.
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+
+    /// Signature of the bytes `test` by `PUBLIC_KEY`.
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1633700835\tfile:test\tprehashed
+wLMDjy9FLAuxZ3q4NlEvkgtyhrr0gtTu6KC4KBJdITbbOeAi1zBIYo0v4iTgt8jJpIidRJnp94ABQkJAgAooBQ==";
+
+    #[test]
+    fn test_valid_signature() {
+        verify(b"test", SIGNATURE, PUBLIC_KEY).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_data() {
+        assert_matches!(
+            verify(b"tampered", SIGNATURE, PUBLIC_KEY),
+            Err(SignatureError::Mismatch { .. })
+        );
+    }
+
+    #[test]
+    fn test_keyring() {
+        let keyring = format!(
+            "# Release keys\n\nuntrusted comment: minisign public key\n{}\n",
+            PUBLIC_KEY
+        );
+        let keys = parse_keyring(&keyring).unwrap();
+        assert_eq!(keys, vec![String::from(PUBLIC_KEY)]);
+        verify_any(b"test", SIGNATURE, &keys).unwrap();
+        assert_matches!(
+            verify_any(b"tampered", SIGNATURE, &keys),
+            Err(SignatureError::Mismatch { .. })
+        );
+
+        assert_matches!(
+            parse_keyring("# nothing\n"),
+            Err(SignatureError::EmptyKeyring)
+        );
+        assert_matches!(
+            parse_keyring("not a key\n"),
+            Err(SignatureError::InvalidPublicKey { .. })
+        );
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert_matches!(
+            verify(b"test", SIGNATURE, "not a key"),
+            Err(SignatureError::InvalidPublicKey { .. })
+        );
+    }
+}
@@ -1,11 +1,18 @@
 //! Helper for Alpine `apk` package management.
 
-use lazy_static::lazy_static;
 use regex::Regex;
-use std::{collections::HashSet, fs::File, path::Path};
+use std::{
+    collections::{BTreeMap, HashSet},
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::LazyLock,
+};
 
 use super::{
-    collect_packages,
+    collect_packages, collect_runtime_packages,
     command::{self, Command},
 };
 
@@ -13,17 +20,268 @@ use crate::extension::Extension;
 
 /// Helper function to split the output of `scanelf`.
 fn split_scanelf_output(input: &str) -> HashSet<&str> {
-    lazy_static! {
-        static ref DELIM: Regex = Regex::new(r"[,\s]+").unwrap();
-    };
+    static DELIM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[,\s]+").unwrap());
 
     DELIM.split(input).filter(|s| !s.is_empty()).collect()
 }
 
+/// The virtual package that holds the runtime dependencies of installed extensions.
+const RUNDEPS_PACKAGE: &str = ".docker-phpexts-rundeps";
+
+/// Parses the output of `apk info --depends` or `apk info --provides` into the listed
+/// names, without any version constraints.
+fn parse_info_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.ends_with(':'))
+        .map(|line| line.split(['=', '>', '<', '~']).next().unwrap_or(line))
+        .map(String::from)
+        .collect()
+}
+
+/// Shell equivalent of reading the runtime virtual package's current dependencies with
+/// [`parse_info_list`], for scripts.
+const CURRENT_RUNDEPS_SCRIPT: &str =
+    r#"$(apk info --depends .docker-phpexts-rundeps 2>/dev/null | sed '/:$/d; s/[=<>~].*//')"#;
+
+/// Adds `packages` to the `current` dependencies of the runtime virtual package, without
+/// duplicates.
+fn merge_dependencies(current: Vec<String>, packages: &[String]) -> Vec<String> {
+    let mut merged = current;
+    for package in packages {
+        if !merged.contains(package) {
+            merged.push(package.clone());
+        }
+    }
+
+    merged
+}
+
+/// Parses the output of `apk info -v` (`<name>-<version>-r<release>` lines) into a map of
+/// package names to versions.
+fn parse_installed_list(output: &str) -> BTreeMap<String, String> {
+    static PACKAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(.+)-([^-]+-r\d+)$").unwrap());
+
+    output
+        .lines()
+        .filter_map(|line| PACKAGE.captures(line.trim()))
+        .map(|caps| (String::from(&caps[1]), String::from(&caps[2])))
+        .collect()
+}
+
+/// apk's database of installed packages.
+const INSTALLED_DB: &str = "/lib/apk/db/installed";
+
+/// Parses apk's installed database (blank-line separated records, with the package name
+/// in `P:` and the installed size in bytes in `I:`) into a map of package names to sizes.
+fn parse_installed_sizes(db: &str) -> BTreeMap<String, u64> {
+    let mut sizes = BTreeMap::new();
+
+    for record in db.split("\n\n") {
+        let mut name = None;
+        let mut size = 0;
+        for line in record.lines() {
+            if let Some(value) = line.strip_prefix("P:") {
+                name = Some(value);
+            } else if let Some(value) = line.strip_prefix("I:") {
+                size = value.parse().unwrap_or_default();
+            }
+        }
+
+        if let Some(name) = name {
+            sizes.insert(String::from(name), size);
+        }
+    }
+
+    sizes
+}
+
+/// Shell equivalent of [`needed_libraries`], setting `$runDeps` to the `so:` dependencies.
+const RUNDEPS_SCRIPT: &str = r#"runDeps="$(scanelf --needed --nobanner --format '%n#p' --recursive /usr/local \
+  | tr ',' '\n' \
+  | sort -u \
+  | awk 'system("[ -e /usr/local/lib/" $1 " ]") == 0 { next } { print "so:" $1 }')""#;
+
+/// Lists the shared libraries that binaries in `/usr/local` link against, other than
+/// those in `/usr/local/lib` itself.
+fn needed_libraries() -> command::Result<HashSet<String>> {
+    let mut command = Command::new("scanelf");
+    command.args([
+        "--needed",
+        "--nobanner",
+        "--format",
+        "%n#p",
+        "--recursive",
+        "/usr/local",
+    ]);
+
+    let output = command.stdout()?;
+    let libraries = split_scanelf_output(&output)
+        .into_iter()
+        .filter(|library| File::open(Path::new("/usr/local/lib").join(library)).is_err())
+        .map(String::from)
+        .collect();
+
+    Ok(libraries)
+}
+
+/// Splits the runtime virtual package's `current` dependencies into those to keep and
+/// those to drop (see [`Apk::prune_runtime_deps`]): `so:` libraries are kept if they are
+/// `needed`, and packages if they are `declared` or if they provide (as listed by
+/// `provides`) a needed library.
+fn partition_dependencies<F>(
+    current: Vec<String>,
+    declared: &[String],
+    needed: &HashSet<String>,
+    mut provides: F,
+) -> command::Result<(Vec<String>, Vec<String>)>
+where
+    F: FnMut(&str) -> command::Result<Vec<String>>,
+{
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    for dependency in current {
+        let keep = match dependency.strip_prefix("so:") {
+            Some(library) => needed.contains(library),
+            None if declared.contains(&dependency) => true,
+            None => provides(&dependency)?
+                .iter()
+                .filter_map(|provided| provided.strip_prefix("so:"))
+                .any(|library| needed.contains(library)),
+        };
+
+        if keep {
+            kept.push(dependency);
+        } else {
+            dropped.push(dependency);
+        }
+    }
+
+    Ok((kept, dropped))
+}
+
+/// apk's list of repositories, the only place it reads repository tags from.
+const REPOSITORIES_FILE: &str = "/etc/apk/repositories";
+
+/// Determines if `repository` is tagged (e.g., `@v312 <url>`), so that apk only installs
+/// the packages requested from it as `<name>@<tag>`.
+fn is_tagged(repository: &str) -> bool {
+    repository.starts_with('@')
+}
+
+/// Appends each of `repositories` that isn't already listed to the `contents` of an apk
+/// repositories file. Returns whether anything was added.
+fn append_repositories<'a, I>(contents: &mut String, repositories: I) -> bool
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let mut added = false;
+    for repository in repositories {
+        if contents.lines().any(|line| line.trim() == repository) {
+            continue;
+        }
+
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(repository);
+        contents.push('\n');
+        added = true;
+    }
+
+    added
+}
+
+/// A temporary copy of `/etc/apk/repositories` with the tagged repositories added, for
+/// checking packages against before the real file is changed (see
+/// [`Apk::preflight_repositories`]). The copy is removed when this is dropped.
+#[derive(Debug)]
+pub struct PreflightRepositories {
+    /// Where the copy is written.
+    path: PathBuf,
+}
+
+impl PreflightRepositories {
+    /// Returns the path of the copy, for apk's `--repositories-file`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PreflightRepositories {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
 /// Struct representing an Alpine package manager.
-pub struct Apk;
+#[derive(Debug, Default)]
+pub struct Apk {
+    /// Extra repositories to search when installing packages.
+    repositories: Vec<String>,
+}
 
 impl Apk {
+    /// Creates a package manager that also searches the given repositories.
+    pub fn new(repositories: Vec<String>) -> Self {
+        Apk { repositories }
+    }
+
+    /// Adds the `--repository` flags for this package manager's extra repositories.
+    /// Tagged repositories are declared by [`Apk::add_tagged_repositories`] instead.
+    fn add_repositories<'a>(&'a self, command: &mut Command<'a>) {
+        for repository in self.repositories.iter().filter(|r| !is_tagged(r)) {
+            command.arg("--repository");
+            command.arg(repository);
+        }
+    }
+
+    /// Adds this package manager's tagged repositories to `/etc/apk/repositories`, unless
+    /// they are there already. They are left there, since the installed packages keep
+    /// referring to their tags.
+    pub fn add_tagged_repositories(&self) -> io::Result<()> {
+        let path = Path::new(REPOSITORIES_FILE);
+        for repository in self.repositories.iter().filter(|r| is_tagged(r)) {
+            let added = command::skip_step(
+                format_args!("add {} to {}", repository, path.display()),
+                format_args!(
+                    "grep -qxF {repository} {path} || echo {repository} >> {path}",
+                    repository = command::quote(repository),
+                    path = command::quote_path(path),
+                ),
+            );
+            if added {
+                continue;
+            }
+
+            let mut contents = fs::read_to_string(path)?;
+            if append_repositories(&mut contents, [repository]) {
+                fs::write(path, contents)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a temporary copy of `/etc/apk/repositories` with this package manager's
+    /// tagged repositories added, so that [`Apk::check_packages`] can find the packages
+    /// they hold before [`Apk::add_tagged_repositories`] changes anything. Returns `None`
+    /// if there are no tagged repositories.
+    pub fn preflight_repositories(&self) -> io::Result<Option<PreflightRepositories>> {
+        let mut tagged = self.repositories.iter().filter(|r| is_tagged(r)).peekable();
+        if tagged.peek().is_none() {
+            return Ok(None);
+        }
+
+        let mut contents = fs::read_to_string(REPOSITORIES_FILE)?;
+        append_repositories(&mut contents, tagged);
+
+        let path = env::temp_dir().join(format!("f1-ext-install-{}.repositories", process::id()));
+        fs::write(&path, contents)?;
+        Ok(Some(PreflightRepositories { path }))
+    }
+
     /// Uses the system package manager to install the packages required by the given
     /// list of extensions.
     ///
@@ -33,7 +291,9 @@ impl Apk {
         let packages = collect_packages(extensions);
 
         let mut command = Command::new("apk");
-        command.args(&["add", "--no-cache", "--virtual", ".build-deps"]);
+        command.args(["add", "--no-cache", "--virtual", ".build-deps"]);
+        command.downloads();
+        self.add_repositories(&mut command);
         command.args(&packages);
 
         let _ = command.status()?;
@@ -41,50 +301,219 @@ impl Apk {
         Ok(())
     }
 
+    /// Checks that the packages required by the given list of extensions (and
+    /// `$PHPIZE_DEPS`) can be installed, without installing them, using `apk add
+    /// --simulate`. The runtime packages the registry declares are checked the same way,
+    /// in a separate transaction, as they are installed after the build.
+    ///
+    /// Tagged repositories are read from `repositories_file` (see
+    /// [`Apk::preflight_repositories`]), if given, instead of `/etc/apk/repositories`.
+    pub fn check_packages(
+        &self,
+        extensions: &[Extension],
+        repositories_file: Option<&Path>,
+    ) -> command::Result<()> {
+        let packages = collect_packages(extensions);
+
+        let mut command = Command::new("apk");
+        if let Some(path) = repositories_file {
+            command.arg("--repositories-file");
+            command.arg(path.to_string_lossy());
+        }
+        command.args([
+            "add",
+            "--simulate",
+            "--no-cache",
+            "--virtual",
+            ".build-deps",
+        ]);
+        self.add_repositories(&mut command);
+        command.args(&packages);
+        command.wait()?;
+
+        let runtime_packages = collect_runtime_packages(extensions).unwrap_or_default();
+        if runtime_packages.is_empty() {
+            return Ok(());
+        }
+
+        let mut command = Command::new("apk");
+        if let Some(path) = repositories_file {
+            command.arg("--repositories-file");
+            command.arg(path.to_string_lossy());
+        }
+        command.args([
+            "add",
+            "--simulate",
+            "--no-cache",
+            "--virtual",
+            RUNDEPS_PACKAGE,
+        ]);
+        self.add_repositories(&mut command);
+        command.args(&runtime_packages);
+        command.wait()
+    }
+
+    /// Installs the given vendor `.apk` files, which must already have been verified (see
+    /// [`super::vendor::download_package_files`]).
+    ///
+    /// These come from vendors rather than an Alpine repository, so `apk` has no key to
+    /// check them with. They are installed outside of `.build-deps` so that they remain
+    /// available at run time.
+    pub fn install_package_files(&self, files: &[PathBuf]) -> command::Result<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        let mut command = Command::new("apk");
+        command.args(["add", "--no-cache", "--allow-untrusted"]);
+        command.args(files.iter().map(|file| file.to_string_lossy()));
+        command.wait()
+    }
+
     /// Marks all runtime dependencies of binaries in `/usr/local` as required in the
     /// system package manager.
     ///
     /// This method ensures that, when cleaning build-time dependencies, packages that
-    /// provide needed `.so` files aren't cleared away.
-    pub fn save_runtime_deps(&self) -> command::Result<()> {
-        let mut command = Command::new("scanelf");
-        command.args(&[
-            "--needed",
-            "--nobanner",
-            "--format",
-            "%n#p",
-            "--recursive",
-            "/usr/local",
-        ]);
+    /// provide needed `.so` files aren't cleared away. Returns the dependencies saved.
+    pub fn save_runtime_deps(&self) -> command::Result<Vec<String>> {
+        // The libraries can only be found once the extensions have been built.
+        let printed = command::skip_step(
+            format_args!(
+                "apk add --virtual {} <libraries found with scanelf>",
+                RUNDEPS_PACKAGE
+            ),
+            format_args!(
+                "{}\napk add --virtual {} {} $runDeps",
+                RUNDEPS_SCRIPT, RUNDEPS_PACKAGE, CURRENT_RUNDEPS_SCRIPT
+            ),
+        );
+        if printed {
+            return Ok(Vec::new());
+        }
 
-        let output = command.stdout()?;
-        let deps_found = split_scanelf_output(&output);
-        let rundeps: Vec<_> = deps_found
-            .iter()
-            .filter_map(|dep_name| {
-                let path = Path::new("/usr/local/lib").join(dep_name);
-                if File::open(path).is_ok() {
-                    return None;
-                }
-
-                Some(format!("so:{}", dep_name))
-            })
+        let mut rundeps: Vec<_> = needed_libraries()?
+            .into_iter()
+            .map(|library| format!("so:{}", library))
             .collect();
+        rundeps.sort();
 
-        if !rundeps.is_empty() {
+        self.add_runtime_packages(&rundeps)?;
+        Ok(rundeps)
+    }
+
+    /// Removes the runtime dependencies that the installed extensions no longer need:
+    /// libraries that no binary in `/usr/local` links against, and packages that none of
+    /// the `declared` packages (those the registry declares for the extensions that
+    /// remain) name, unless they provide a library that is still linked. Returns the
+    /// dependencies removed.
+    pub fn prune_runtime_deps(&self, declared: &[String]) -> command::Result<Vec<String>> {
+        let mut command = Command::new("apk");
+        command.args(["info", "--depends", RUNDEPS_PACKAGE]);
+        let current = parse_info_list(&command.stdout()?);
+        if current.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let needed = needed_libraries()?;
+        let (kept, dropped) = partition_dependencies(current, declared, &needed, |package| {
+            let mut command = Command::new("apk");
+            command.args(["info", "--provides", package]);
+            Ok(parse_info_list(&command.stdout()?))
+        })?;
+
+        if dropped.is_empty() {
+            return Ok(dropped);
+        }
+
+        // Re-adding a virtual package replaces its dependencies; apk then removes any
+        // packages that nothing depends on anymore.
+        if kept.is_empty() {
             let mut command = Command::new("apk");
-            command.args(&["add", "--virtual", ".docker-phpexts-rundeps"]);
-            command.args(rundeps);
+            command.args(["del", RUNDEPS_PACKAGE]);
             command.wait()?;
+        } else {
+            self.set_runtime_packages(&kept)?;
         }
 
-        Ok(())
+        Ok(dropped)
+    }
+
+    /// Marks the given packages as required at run time, so that they survive the removal
+    /// of build-time dependencies.
+    ///
+    /// This is used in place of [`Apk::save_runtime_deps`] when the registry already
+    /// declares every runtime package needed. The virtual package's current dependencies
+    /// (e.g., from an earlier run or image layer) are kept, since re-adding it replaces them.
+    pub fn add_runtime_packages(&self, packages: &[String]) -> command::Result<()> {
+        if packages.is_empty() {
+            return Ok(());
+        }
+
+        let printed = command::skip_step(
+            format_args!(
+                "apk add --virtual {} <current dependencies> {}",
+                RUNDEPS_PACKAGE,
+                packages.join(" ")
+            ),
+            format_args!(
+                "apk add --virtual {} {} {}",
+                RUNDEPS_PACKAGE,
+                CURRENT_RUNDEPS_SCRIPT,
+                packages.join(" ")
+            ),
+        );
+        if printed {
+            return Ok(());
+        }
+
+        let mut command = Command::new("apk");
+        command.args(["info", "--depends", RUNDEPS_PACKAGE]);
+        let current = parse_info_list(&command.stdout()?);
+
+        self.set_runtime_packages(&merge_dependencies(current, packages))
+    }
+
+    /// Replaces the dependencies of the runtime virtual package with `packages`.
+    fn set_runtime_packages(&self, packages: &[String]) -> command::Result<()> {
+        let mut command = Command::new("apk");
+        command.args(["add", "--virtual", RUNDEPS_PACKAGE]);
+        command.downloads();
+        self.add_repositories(&mut command);
+        command.args(packages);
+        command.wait()
+    }
+
+    /// Installs the given packages permanently, outside of any virtual package.
+    pub fn add_packages(&self, packages: &[&str]) -> command::Result<()> {
+        if packages.is_empty() {
+            return Ok(());
+        }
+
+        let mut command = Command::new("apk");
+        command.args(["add", "--no-cache"]);
+        command.downloads();
+        self.add_repositories(&mut command);
+        command.args(packages);
+        command.wait()
+    }
+
+    /// Lists the installed packages and their versions.
+    pub fn installed_packages(&self) -> command::Result<BTreeMap<String, String>> {
+        let mut command = Command::new("apk");
+        command.args(["info", "-v"]);
+        Ok(parse_installed_list(&command.stdout()?))
+    }
+
+    /// Lists the installed packages and their installed sizes, in bytes.
+    pub fn package_sizes(&self) -> io::Result<BTreeMap<String, u64>> {
+        let db = fs::read_to_string(INSTALLED_DB)?;
+        Ok(parse_installed_sizes(&db))
     }
 
     /// Clear out all build-time dependencies (both `$PHPIZE_DEPS` and user-requested).
     pub fn remove_build_deps(&self) -> command::Result<()> {
         let mut command = Command::new("apk");
-        command.args(&["del", ".build-deps"]);
+        command.args(["del", ".build-deps"]);
         command.wait()
     }
 }
@@ -93,6 +522,116 @@ impl Apk {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_info_list() {
+        let output = ".docker-phpexts-rundeps-20240101.000000 depends on:\n\
+                      so:libmemcached.so.11\n\
+                      libevent>=2.1\n\
+                      \n";
+
+        assert_eq!(
+            parse_info_list(output),
+            vec!["so:libmemcached.so.11", "libevent"]
+        );
+    }
+
+    #[test]
+    fn test_append_repositories() {
+        let tagged = vec![
+            String::from("@v312 https://dl-cdn.alpinelinux.org/alpine/v3.12/main"),
+            String::from("@edge https://dl-cdn.alpinelinux.org/alpine/edge/testing"),
+        ];
+
+        let mut contents = String::from(
+            "https://dl-cdn.alpinelinux.org/alpine/v3.18/main\n\
+             @v312 https://dl-cdn.alpinelinux.org/alpine/v3.12/main",
+        );
+        assert!(append_repositories(&mut contents, &tagged));
+        assert_eq!(
+            contents,
+            "https://dl-cdn.alpinelinux.org/alpine/v3.18/main\n\
+             @v312 https://dl-cdn.alpinelinux.org/alpine/v3.12/main\n\
+             @edge https://dl-cdn.alpinelinux.org/alpine/edge/testing\n"
+        );
+
+        assert!(!append_repositories(&mut contents, &tagged));
+    }
+
+    #[test]
+    fn test_merge_dependencies() {
+        let output = ".docker-phpexts-rundeps-20240101.000000 depends on:\n\
+                      so:libzip.so.5\n\
+                      libmemcached-libs\n\
+                      \n";
+        let declared = vec![String::from("libmemcached-libs"), String::from("unixodbc")];
+
+        assert_eq!(
+            merge_dependencies(parse_info_list(output), &declared),
+            vec!["so:libzip.so.5", "libmemcached-libs", "unixodbc"]
+        );
+    }
+
+    #[test]
+    fn test_partition_dependencies() {
+        let current = vec![
+            String::from("so:libzip.so.5"),
+            String::from("so:libmemcached.so.11"),
+            String::from("libmemcached-libs"),
+            String::from("unixodbc"),
+            String::from("icu-data-full"),
+            String::from("libzip"),
+        ];
+        let declared = vec![String::from("unixodbc")];
+        let needed = HashSet::from([String::from("libzip.so.5")]);
+
+        let (kept, dropped) = partition_dependencies(current, &declared, &needed, |package| {
+            Ok(match package {
+                "libzip" => vec![String::from("so:libzip.so.5")],
+                "libmemcached-libs" => vec![String::from("so:libmemcached.so.11")],
+                _ => Vec::new(),
+            })
+        })
+        .unwrap();
+
+        assert_eq!(kept, ["so:libzip.so.5", "unixodbc", "libzip"]);
+        assert_eq!(
+            dropped,
+            [
+                "so:libmemcached.so.11",
+                "libmemcached-libs",
+                "icu-data-full"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_installed_list() {
+        let output = "musl-1.2.4-r2\n\
+                      libmemcached-libs-1.0.18-r5\n\
+                      .docker-phpexts-rundeps-20240101.000000\n";
+
+        let packages = parse_installed_list(output);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages["libmemcached-libs"], "1.0.18-r5");
+    }
+
+    #[test]
+    fn test_parse_installed_sizes() {
+        let db = "C:Q1abc=\n\
+                  P:musl\n\
+                  V:1.2.4-r2\n\
+                  I:643072\n\
+                  \n\
+                  P:.build-deps\n\
+                  V:20240101.000000\n\
+                  \n";
+
+        let sizes = parse_installed_sizes(db);
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes["musl"], 643_072);
+        assert_eq!(sizes[".build-deps"], 0);
+    }
+
     #[test]
     fn test_split_elements() {
         let expected: HashSet<_> = [
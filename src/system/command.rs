@@ -1,14 +1,367 @@
 //! Helpers for interacting with system commands.
 
-use snafu::{ResultExt, Snafu};
+use log::{debug, log_enabled, warn, Level};
+use snafu::Snafu;
 use std::{
-    convert::Into,
-    io,
-    os::unix::process::ExitStatusExt as _,
-    process::{Command as SystemCommand, ExitStatus, Stdio},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufRead as _, BufReader, Read, Write as _},
+    os::unix::process::{CommandExt as _, ExitStatusExt as _},
+    path::{Path, PathBuf},
+    process::{Child, Command as SystemCommand, ExitStatus, Stdio},
     string::FromUtf8Error,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+use super::hints;
+use crate::progress;
+
+/// How commands that change the system are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Commands are run.
+    Run,
+    /// Commands are printed instead of run, and other steps (such as writing files) are
+    /// described in shell comments (`--dry-run`).
+    DryRun,
+    /// Commands are printed instead of run, and other steps are printed as equivalent
+    /// shell commands, producing a script (`emit-script`).
+    Script,
+}
+
+/// The current [`Mode`], as its index.
+#[cfg(not(test))]
+static MODE: AtomicU8 = AtomicU8::new(0);
+
+// Each test gets its own mode, so that testing a dry run doesn't change what the tests
+// running alongside it do.
+#[cfg(test)]
+thread_local! {
+    static MODE: AtomicU8 = const { AtomicU8::new(0) };
+}
+
+/// Calls `f` with the current [`Mode`]'s index.
+fn with_mode<T>(f: impl FnOnce(&AtomicU8) -> T) -> T {
+    #[cfg(not(test))]
+    return f(&MODE);
+    #[cfg(test)]
+    return MODE.with(f);
+}
+
+/// Sets how commands that change the system are handled. Outside of [`Mode::Run`],
+/// [`Command::status`] and [`Command::wait`] print the command line to stdout instead of
+/// running it, while [`Command::stdout`] (used only for queries like `php -m`) still runs.
+pub fn set_mode(mode: Mode) {
+    with_mode(|current| current.store(mode as u8, Ordering::Relaxed));
+}
+
+/// Returns how commands that change the system are handled.
+pub fn mode() -> Mode {
+    match with_mode(|current| current.load(Ordering::Relaxed)) {
+        0 => Mode::Run,
+        1 => Mode::DryRun,
+        _ => Mode::Script,
+    }
+}
+
+/// Returns true if commands are printed rather than run.
+pub fn is_dry_run() -> bool {
+    mode() != Mode::Run
+}
+
+/// Unless commands are being run, prints a step that isn't a command (such as moving a
+/// file): either its `description` as a shell comment, or its `shell` equivalent when
+/// emitting a script. Returns true if the step was printed, so that the caller skips it.
+pub fn skip_step(description: impl fmt::Display, shell: impl fmt::Display) -> bool {
+    match mode() {
+        Mode::Run => return false,
+        Mode::DryRun => println!("# {}", description),
+        Mode::Script => println!("{}", shell),
+    }
+
+    true
+}
+
+/// The delimiter of the here-documents that write files in a script.
+const HEREDOC_DELIMITER: &str = "F1_EOF";
+
+/// Unless commands are being run, prints the file that would be written to `path` (as a
+/// shell comment, or as a here-document when emitting a script) and returns true, so
+/// that the caller skips writing it.
+pub fn skip_write(path: &Path, contents: &str) -> bool {
+    if !is_dry_run() {
+        return false;
+    }
+
+    let mut script = String::new();
+    if let Some(parent) = path.parent() {
+        script.push_str(&format!("mkdir -p {}\n", quote_path(parent)));
+    }
+    script.push_str(&format!(
+        "cat > {} <<'{}'\n{}",
+        quote_path(path),
+        HEREDOC_DELIMITER,
+        contents
+    ));
+    if !contents.ends_with('\n') {
+        script.push('\n');
+    }
+    script.push_str(HEREDOC_DELIMITER);
+
+    let mut description = format!("write {}", path.display());
+    for line in contents.lines() {
+        description.push_str("\n#   ");
+        description.push_str(line);
+    }
+
+    skip_step(description, script)
+}
+
+/// Quotes `arg` for a shell if it contains whitespace or characters the shell would
+/// interpret. Glob characters are left alone, so that paths like `/tmp/build/*.tgz`
+/// expand in scripts.
+pub fn quote(arg: &str) -> String {
+    let special = |c: char| c.is_whitespace() || "\"'$`\\;&|<>()".contains(c);
+    if !arg.is_empty() && !arg.contains(special) {
+        return String::from(arg);
+    }
+
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Quotes `path` for a shell (see [`quote`]).
+pub fn quote_path(path: &Path) -> String {
+    quote(&path.to_string_lossy())
+}
+
+/// How many times a command that fails because of the network is retried.
+static RETRIES: AtomicU32 = AtomicU32::new(DEFAULT_RETRIES);
+
+/// How long to wait, in milliseconds, before the first retry; each later retry waits
+/// twice as long as the one before.
+static RETRY_DELAY: AtomicU64 = AtomicU64::new(DEFAULT_RETRY_DELAY.as_millis() as u64);
+
+/// The default for [`RETRIES`].
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The default for [`RETRY_DELAY`].
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Sets how many times commands that fail because of the network (e.g., `apk add` or
+/// `pecl download` against a flaky mirror) are retried, and how long to wait before the
+/// first retry. Other failures, such as compile errors, are never retried.
+pub fn set_retries(retries: u32, delay: Duration) {
+    RETRIES.store(retries, Ordering::Relaxed);
+    RETRY_DELAY.store(delay.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Returns how many times to retry network failures, and the delay before the first
+/// retry.
+fn retries() -> (u32, Duration) {
+    (
+        RETRIES.load(Ordering::Relaxed),
+        Duration::from_millis(RETRY_DELAY.load(Ordering::Relaxed)),
+    )
+}
+
+/// The longest any one command may run, if limited.
+static TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// When all commands must have finished by, if limited.
+static DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Limits how long each command may run (`timeout`) and when all of them must have
+/// finished (`deadline`). A command still running at its limit is killed, along with any
+/// processes it started, and fails.
+pub fn set_timeouts(timeout: Option<Duration>, deadline: Option<Instant>) {
+    if let Ok(mut current) = TIMEOUT.lock() {
+        *current = timeout;
+    }
+    if let Ok(mut current) = DEADLINE.lock() {
+        *current = deadline;
+    }
+}
+
+/// Returns how long the next command may run, if it is limited.
+fn time_limit() -> Option<Duration> {
+    let timeout = TIMEOUT.lock().ok().and_then(|timeout| *timeout);
+    let remaining = DEADLINE
+        .lock()
+        .ok()
+        .and_then(|deadline| *deadline)
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+    match (timeout, remaining) {
+        (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+        (timeout, remaining) => timeout.or(remaining),
+    }
+}
+
+/// Parses a duration given in seconds, or with an `s`, `m`, or `h` suffix (e.g., `90`,
+/// `30m`, or `1.5h`).
+pub fn parse_duration(input: &str) -> std::result::Result<Duration, String> {
+    let (number, unit) = match input.char_indices().last() {
+        Some((index, 's')) => (&input[..index], 1.0),
+        Some((index, 'm')) => (&input[..index], 60.0),
+        Some((index, 'h')) => (&input[..index], 3600.0),
+        _ => (input, 1.0),
+    };
+
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 => Duration::try_from_secs_f64(number * unit).map_err(|_| {
+            format!(
+                "{:?} is too long a duration (e.g., 90, 90s, 30m, or 1.5h)",
+                input
+            )
+        }),
+        _ => Err(format!(
+            "{:?} isn't a duration (e.g., 90, 90s, 30m, or 1.5h)",
+            input
+        )),
+    }
+}
+
+/// Parses a (possibly zero or fractional) number of seconds, such as a retry delay.
+pub fn parse_seconds(input: &str) -> std::result::Result<Duration, String> {
+    input
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("{:?} isn't a number of seconds", input))
+}
+
+/// How often a command with a time limit is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits for `child` to exit, killing it (and the processes in its process group) if it
+/// runs longer than `limit`. Returns `None` if it was killed.
+fn wait_with_limit(child: &mut Child, limit: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return child.wait().map(Some),
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        if start.elapsed() >= limit {
+            // The child leads its own process group, so that builds it started (e.g.,
+            // make under pecl) are killed with it and release its output pipes.
+            let _ = SystemCommand::new("kill")
+                .args(["-KILL", "--", &format!("-{}", child.id())])
+                .stderr(Stdio::null())
+                .status();
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The file that receives the complete output of every command run, if any.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Appends the complete output of every command run from now on to the file at `path`
+/// (`--log-file`), along with the command lines. Console output is unaffected.
+pub fn set_log_file(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    if let Ok(mut log_file) = LOG_FILE.lock() {
+        *log_file = Some(file);
+    }
+
+    Ok(())
+}
+
+/// Appends `bytes` to the log file, if there is one.
+fn write_log(bytes: &[u8]) {
+    if let Ok(mut log_file) = LOG_FILE.lock() {
+        if let Some(file) = log_file.as_mut() {
+            let _ = file.write_all(bytes);
+        }
+    }
+}
+
+/// Returns true if commands' output is written to a log file.
+fn is_logging() -> bool {
+    LOG_FILE.lock().is_ok_and(|log_file| log_file.is_some())
+}
+
+/// The prefix of each line of commands' output, if any.
+static OUTPUT_PREFIX: Mutex<Option<String>> = Mutex::new(None);
+
+/// Prefixes each line of output from the commands run from now on with `prefix` (e.g.,
+/// `redis | `), so that output from several extensions' builds can be told apart. `None`
+/// stops prefixing.
+pub fn set_output_prefix(prefix: Option<String>) {
+    if let Ok(mut output_prefix) = OUTPUT_PREFIX.lock() {
+        *output_prefix = prefix;
+    }
+}
+
+/// Whether stdout is reserved for a report, so commands' output has to stay off it.
+static REPORT_ON_STDOUT: AtomicBool = AtomicBool::new(false);
+
+/// Sends the output of commands streamed from now on (at `-vv`) to stderr, even the
+/// output they wrote to stdout, so that a report written to stdout (`--output json`)
+/// stays parseable.
+pub fn set_report_on_stdout(report_on_stdout: bool) {
+    REPORT_ON_STDOUT.store(report_on_stdout, Ordering::Relaxed);
+}
+
+/// Returns the prefix of each line of commands' output, if any.
+fn output_prefix() -> Option<String> {
+    OUTPUT_PREFIX.lock().ok().and_then(|prefix| prefix.clone())
+}
+
+/// Copies each line read from `pipe`, after `prefix`, to the log file and to `captured`,
+/// and also to `console` when streaming.
+fn copy_output(
+    pipe: impl Read,
+    mut console: impl io::Write,
+    prefix: &str,
+    stream: bool,
+    captured: &Mutex<Vec<u8>>,
+) {
+    let mut pipe = BufReader::new(pipe);
+    let mut line = Vec::from(prefix);
+    loop {
+        line.truncate(prefix.len());
+        match pipe.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+
+        write_log(&line);
+        if stream {
+            let _ = console.write_all(&line);
+        }
+        if let Ok(mut captured) = captured.lock() {
+            captured.extend_from_slice(&line);
+        }
+    }
+}
+
+/// Formats a hint for fixing a failure, if there is one, to follow an error message.
+fn format_hint(hint: &Option<String>) -> String {
+    match hint {
+        Some(hint) => format!("\n  hint: {}", hint),
+        None => String::new(),
+    }
+}
+
 /// Returns a message indicating the cause of a process exit.
 fn exit_status_reason(status: ExitStatus) -> String {
     if let Some(code) = status.code() {
@@ -24,22 +377,42 @@ fn exit_status_reason(status: ExitStatus) -> String {
 /// Indicates how a process failed.
 pub enum CommandError {
     /// General errors from `std::io`, usually indicating a failure to start a process.
-    #[snafu(display("Failed to run {}: {}", command, source))]
+    #[snafu(display("Failed to run {}: {}{}", command, source, format_hint(hint)))]
     Io {
         /// The underlying IO error
         source: io::Error,
         /// The command that failed
         command: String,
+        /// How to fix the failure, if it is a well-known one
+        hint: Option<String>,
     },
 
     /// Indicates that a process exited with a non-zero code. On *nix systems, also
     /// indicates death by signal.
-    #[snafu(display("{} exited unsuccessfully: {}", command, exit_status_reason(*exit)))]
+    #[snafu(display(
+        "{} exited unsuccessfully: {}{}",
+        command,
+        exit_status_reason(*exit),
+        format_hint(hint)
+    ))]
     BadExit {
         /// The command that failed
         command: String,
         /// The exit cause
         exit: ExitStatus,
+        /// How to fix the failure, recognized from the process's output
+        hint: Option<String>,
+        /// Whether the process's output shows that it failed because of the network
+        network: bool,
+    },
+
+    /// Indicates that a process ran longer than its time limit, and was killed.
+    #[snafu(display("{} timed out after {}s and was killed", command, limit.as_secs_f64()))]
+    TimedOut {
+        /// The command that timed out
+        command: String,
+        /// How long the command was allowed to run
+        limit: Duration,
     },
 
     /// Indicates that process output could not be decoded as valid UTF-8.
@@ -60,15 +433,40 @@ impl From<FromUtf8Error> for CommandError {
 /// Helper type for the result of command execution.
 pub type Result<T> = std::result::Result<T, CommandError>;
 
-/// Convert an `ExitStatus` into a Result, using `command` for context to the user
-fn status_result(status: ExitStatus, command: &str) -> Result<ExitStatus> {
+/// Convert an `ExitStatus` into a Result, using `command` for context to the user and its
+/// `output` to look for a hint to fix a failure, and, if it `downloads`, to check for a
+/// network failure.
+fn status_result(
+    status: ExitStatus,
+    command: &str,
+    output: &[u8],
+    downloads: bool,
+) -> Result<ExitStatus> {
     if status.success() {
-        Ok(status)
-    } else {
-        Err(CommandError::BadExit {
-            command: String::from(command),
-            exit: status,
-        })
+        return Ok(status);
+    }
+
+    let output = String::from_utf8_lossy(output);
+    Err(CommandError::BadExit {
+        command: String::from(command),
+        exit: status,
+        hint: hints::diagnose(&output),
+        network: downloads && hints::is_network_failure(&output),
+    })
+}
+
+/// Returns the error for a `command` that couldn't be run, with a hint if the program
+/// is missing.
+fn io_error(source: io::Error, command: &str) -> CommandError {
+    let hint = match source.kind() {
+        io::ErrorKind::NotFound => hints::diagnose_missing_program(command),
+        _ => None,
+    };
+
+    CommandError::Io {
+        source,
+        command: String::from(command),
+        hint,
     }
 }
 
@@ -82,6 +480,16 @@ pub struct Command<'a> {
     program: &'a str,
     /// The arguments to pass to the program, if any.
     args: Vec<String>,
+    /// The working directory for the program, if not inherited from this process.
+    current_dir: Option<PathBuf>,
+    /// Environment variables to set for the program, in addition to those inherited.
+    envs: Vec<(String, String)>,
+    /// The longest this command may run, in addition to the limits set with
+    /// [`set_timeouts`].
+    timeout: Option<Duration>,
+    /// Whether the program downloads anything, so that its failures may be network
+    /// failures (see [`Command::downloads`]).
+    downloads: bool,
 }
 
 impl<'a> Command<'a> {
@@ -90,9 +498,55 @@ impl<'a> Command<'a> {
         Command {
             program,
             args: Vec::new(),
+            current_dir: None,
+            envs: Vec::new(),
+            timeout: None,
+            downloads: false,
         }
     }
 
+    /// Set the working directory for the program.
+    pub fn current_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Mark the program as one that downloads (e.g., `apk add`, `curl`, or `pecl
+    /// download`), so that failures showing a network error are retried (see
+    /// [`set_retries`]). Other programs' output isn't checked for network errors, since a
+    /// build log can mention them without the build having failed because of one.
+    pub fn downloads(&mut self) -> &mut Self {
+        self.downloads = true;
+        self
+    }
+
+    /// Limit how long the program may run (see [`set_timeouts`]).
+    pub fn timeout(&mut self, limit: Duration) -> &mut Self {
+        self.timeout = Some(limit);
+        self
+    }
+
+    /// Returns how long the program may run, if it is limited.
+    fn limit(&self) -> Option<Duration> {
+        match (self.timeout, time_limit()) {
+            (Some(timeout), Some(limit)) => Some(timeout.min(limit)),
+            (timeout, limit) => timeout.or(limit),
+        }
+    }
+
+    /// Set an environment variable for the program.
+    pub fn env<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
     /// Add an argument to the program's argument list.
     pub fn arg<S>(&mut self, arg: S) -> &mut Self
     where
@@ -114,17 +568,189 @@ impl<'a> Command<'a> {
         self
     }
 
+    /// Formats this command as it would be typed into a shell, for logging.
+    fn command_line(&self) -> String {
+        let mut line = String::from(self.program);
+        for arg in &self.args {
+            line.push(' ');
+            line.push_str(&quote(arg));
+        }
+
+        line
+    }
+
+    /// Formats this command as a line of a shell script, including its environment and
+    /// working directory (changed in a subshell, so that later lines are unaffected).
+    pub(super) fn script_line(&self) -> String {
+        let mut line = String::new();
+        if let Some(dir) = &self.current_dir {
+            line.push_str(&format!("(cd {} && ", quote(&dir.to_string_lossy())));
+        }
+        for (key, value) in &self.envs {
+            line.push_str(&format!("{}={} ", key, quote(value)));
+        }
+
+        line.push_str(&self.command_line());
+        if self.current_dir.is_some() {
+            line.push(')');
+        }
+
+        line
+    }
+
     /// Execute the given command and wait for its status, returning `Err` on failed
     /// execution.
+    ///
+    /// The program's output is only streamed at the most verbose log level (`-vv`).
+    /// Otherwise, it is captured and only shown if the program fails, to keep builds
+    /// quiet.
+    ///
+    /// Failures caused by the network (see [`set_retries`]) are retried.
+    ///
+    /// Outside of [`Mode::Run`], the command is printed to stdout instead, and reported
+    /// as successful.
     pub fn status(self) -> Result<ExitStatus> {
+        if is_dry_run() {
+            println!("{}", self.script_line());
+            return Ok(ExitStatus::from_raw(0));
+        }
+
+        debug!("Running {}", self.command_line());
+
+        let (retries, delay) = retries();
+        let mut attempt = 0;
+        loop {
+            match self.run() {
+                Err(CommandError::BadExit { network: true, .. }) if attempt < retries => {
+                    let wait = delay.saturating_mul(2u32.saturating_pow(attempt));
+                    attempt += 1;
+                    let terminal = progress::suspend();
+                    warn!(
+                        "{} failed because of the network; retrying in {}s ({} of {})",
+                        self.program,
+                        wait.as_secs(),
+                        attempt,
+                        retries
+                    );
+                    drop(terminal);
+                    thread::sleep(wait);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs the command once (see [`Command::status`]).
+    fn run(&self) -> Result<ExitStatus> {
+        let program = self.program;
+        let limit = self.limit();
+        if limit.is_some()
+            || log_enabled!(Level::Trace)
+            || is_logging()
+            || output_prefix().is_some()
+        {
+            return self.run_piped(limit);
+        }
+
+        let mut command: SystemCommand = self.into();
+        let output = command
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|source| io_error(source, program))?;
+
+        let mut combined = output.stdout;
+        combined.extend_from_slice(&output.stderr);
+        if !output.status.success() {
+            // Printed rather than written to stderr directly, so that tests capture it.
+            let _terminal = progress::suspend();
+            eprint!("{}", String::from_utf8_lossy(&combined));
+        }
+
+        status_result(output.status, program, &combined, self.downloads)
+    }
+
+    /// Like [`Command::run`], but reads the program's output line by line, to stream it
+    /// (at the most verbose log level), to copy it to the log file as it is produced, and
+    /// to add the output prefix. The program is killed if it runs longer than `limit`.
+    fn run_piped(&self, limit: Option<Duration>) -> Result<ExitStatus> {
+        let (status, captured) = self.capture(limit)?;
+        status_result(status, self.program, &captured, self.downloads)
+    }
+
+    /// Runs the command as in [`Command::run_piped`], returning its exit status and its
+    /// combined output.
+    fn capture(&self, limit: Option<Duration>) -> Result<(ExitStatus, Vec<u8>)> {
         let program = self.program;
+        write_log(format!("$ {}\n", self.script_line()).as_bytes());
+
+        if limit == Some(Duration::ZERO) {
+            return Err(CommandError::TimedOut {
+                command: String::from(program),
+                limit: Duration::ZERO,
+            });
+        }
+
         let mut command: SystemCommand = self.into();
-        let status = command.status().with_context(|| Io {
-            command: String::from(program),
-        })?;
+        if limit.is_some() {
+            command.process_group(0);
+        }
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|source| io_error(source, program))?;
+
+        let stream = log_enabled!(Level::Trace);
+        let prefix = output_prefix().unwrap_or_default();
+        let captured = Mutex::new(Vec::new());
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let status = thread::scope(|scope| {
+            if let Some(stdout) = stdout {
+                scope.spawn(|| {
+                    if REPORT_ON_STDOUT.load(Ordering::Relaxed) {
+                        copy_output(stdout, io::stderr(), &prefix, stream, &captured)
+                    } else {
+                        copy_output(stdout, io::stdout(), &prefix, stream, &captured)
+                    }
+                });
+            }
+            if let Some(stderr) = stderr {
+                scope.spawn(|| copy_output(stderr, io::stderr(), &prefix, stream, &captured));
+            }
 
-        let status = status_result(status, program)?;
-        Ok(status)
+            wait_with_limit(&mut child, limit)
+        });
+
+        let status = match status.map_err(|source| io_error(source, program))? {
+            Some(status) => status,
+            None => {
+                let limit = limit.unwrap_or_default();
+                write_log(format!("# timed out after {}s\n", limit.as_secs_f64()).as_bytes());
+                if !stream {
+                    if let Ok(captured) = captured.lock() {
+                        let _terminal = progress::suspend();
+                        eprint!("{}", String::from_utf8_lossy(&captured));
+                    }
+                }
+                return Err(CommandError::TimedOut {
+                    command: String::from(program),
+                    limit,
+                });
+            }
+        };
+
+        let captured = captured.into_inner().unwrap_or_default();
+        if !status.success() {
+            write_log(format!("# {}\n", exit_status_reason(status)).as_bytes());
+            if !stream {
+                let _terminal = progress::suspend();
+                eprint!("{}", String::from_utf8_lossy(&captured));
+            }
+        }
+
+        Ok((status, captured))
     }
 
     /// Execute the given command and wait for it to complete, discarding successful
@@ -135,6 +761,24 @@ impl<'a> Command<'a> {
         Ok(())
     }
 
+    /// Execute the given command as [`Command::status`] would (without retries), returning
+    /// its combined stdout and stderr.
+    ///
+    /// Outside of [`Mode::Run`], the command is printed to stdout instead, and its output
+    /// is empty.
+    pub fn output(self) -> Result<String> {
+        if is_dry_run() {
+            println!("{}", self.script_line());
+            return Ok(String::new());
+        }
+
+        debug!("Running {}", self.command_line());
+        let (status, captured) = self.capture(self.limit())?;
+        status_result(status, self.program, &captured, self.downloads)?;
+
+        Ok(String::from_utf8_lossy(&captured).into_owned())
+    }
+
     /// Execute the given command and capture its standard output.
     ///
     /// This method closes stdin, inherits stderr (allowing the user to see any error messages
@@ -143,17 +787,19 @@ impl<'a> Command<'a> {
     /// or encoding issues) are propagated as `Err` results.
     pub fn stdout(self) -> Result<String> {
         let program = self.program;
-        let mut command: SystemCommand = self.into();
+        debug!("Running {}", self.command_line());
+
+        let mut command: SystemCommand = (&self).into();
         command
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::inherit());
 
-        let output = command.output().with_context(|| Io {
-            command: String::from(program),
-        })?;
+        let output = command
+            .output()
+            .map_err(|source| io_error(source, program))?;
 
-        let _ = status_result(output.status, program)?;
+        let _ = status_result(output.status, program, &output.stdout, self.downloads)?;
 
         let buffer = String::from_utf8(output.stdout)?;
 
@@ -161,10 +807,169 @@ impl<'a> Command<'a> {
     }
 }
 
-impl<'a> Into<SystemCommand> for Command<'a> {
-    fn into(self) -> SystemCommand {
-        let mut command = SystemCommand::new(self.program);
-        command.args(self.args);
+impl<'a> From<&Command<'a>> for SystemCommand {
+    fn from(command: &Command<'a>) -> Self {
+        let mut system_command = SystemCommand::new(command.program);
+        system_command.args(&command.args);
+        if let Some(dir) = &command.current_dir {
+            system_command.current_dir(dir);
+        }
+        system_command.envs(command.envs.iter().map(|(key, value)| (key, value)));
+        system_command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Held by tests that run commands or change this module's settings, since the log
+    /// file and retry settings are shared by every test thread.
+    static SETTINGS: Mutex<()> = Mutex::new(());
+
+    /// Takes [`SETTINGS`], even if a test panicked while holding it.
+    fn lock_settings() -> std::sync::MutexGuard<'static, ()> {
+        SETTINGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut command = Command::new("pecl");
+        command.args([
+            "install",
+            "--configureoptions",
+            r#"enable-redis-igbinary="yes""#,
+        ]);
+        command.arg("redis");
+
+        assert_eq!(
+            command.command_line(),
+            r#"pecl install --configureoptions 'enable-redis-igbinary="yes"' redis"#
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/tmp/build/*.tgz"), "/tmp/build/*.tgz");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_log_file() {
+        let _settings = lock_settings();
+        let path = std::env::temp_dir().join(format!("f1-log-{}.log", std::process::id()));
+        set_log_file(&path).unwrap();
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo built; echo warned >&2"]);
+        command.wait().unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.starts_with("$ sh -c 'echo built; echo warned >&2'\n"));
+        assert!(log.contains("built\n"));
+        assert!(log.contains("warned\n"));
+
+        *LOG_FILE.lock().unwrap() = None;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_copy_output() {
+        let captured = Mutex::new(Vec::new());
+        copy_output(
+            &b"checking for gcc... yes\nok"[..],
+            io::sink(),
+            "grpc  | ",
+            false,
+            &captured,
+        );
+
+        assert_eq!(
+            captured.into_inner().unwrap(),
+            b"grpc  | checking for gcc... yes\ngrpc  | ok\n"
+        );
+    }
+
+    #[test]
+    fn test_retry_network_failure() {
+        let _settings = lock_settings();
+        let path = std::env::temp_dir().join(format!("f1-retry-{}", std::process::id()));
+        let (retries, delay) = retries();
+        set_retries(2, Duration::from_millis(0));
+
+        // Fails with a network error the first time, and succeeds the second.
+        let script = format!(
+            r#"echo >> {0}; [ "$(wc -l < {0})" -ge 2 ] || {{ echo "network error"; exit 1; }}"#,
+            path.display()
+        );
+        let mut command = Command::new("sh");
+        command.args(["-c", &script]).downloads();
+        command.wait().unwrap();
+
+        let mut command = Command::new("sh");
         command
+            .args(["-c", "echo 'error: expected expression'; exit 1"])
+            .downloads();
+        assert!(matches!(
+            command.wait(),
+            Err(CommandError::BadExit { network: false, .. })
+        ));
+
+        // A build log can mention network errors too, but only downloads are retried.
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo 'Connection refused'; exit 1"]);
+        assert!(matches!(
+            command.wait(),
+            Err(CommandError::BadExit { network: false, .. })
+        ));
+
+        set_retries(retries, delay);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("1e30").is_err());
+        assert!(parse_duration("inf").is_err());
+
+        assert_eq!(parse_seconds("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_seconds("0.5").unwrap(), Duration::from_millis(500));
+        assert!(parse_seconds("-1").is_err());
+        assert!(parse_seconds("1e30").is_err());
+        assert!(parse_seconds("NaN").is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let _settings = lock_settings();
+        let start = Instant::now();
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 10 & wait"]);
+        let result = command.run_piped(Some(Duration::from_millis(200)));
+
+        assert!(matches!(result, Err(CommandError::TimedOut { .. })));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_script_line() {
+        let mut command = Command::new("pecl");
+        command.args(["install", "grpc"]);
+        command.env("CFLAGS", "-O2 -g");
+        command.current_dir("/tmp/build");
+
+        assert_eq!(
+            command.script_line(),
+            "(cd /tmp/build && CFLAGS='-O2 -g' pecl install grpc)"
+        );
     }
 }
@@ -0,0 +1,92 @@
+//! Checks for free disk space, so that an installation fails before it starts rather than
+//! halfway through a layer with `ENOSPC`.
+
+use std::path::Path;
+
+use super::command::{self, Command};
+
+/// Where an installation writes: packages (`/`), PECL builds (`/tmp`), and extensions and
+/// PHP's source (`/usr/local`).
+pub const INSTALL_PATHS: &[&str] = &["/", "/tmp", "/usr/local"];
+
+/// The default free space required (1 GiB, in the form [`parse_size`] reads): enough for
+/// the build dependencies and compiling large extensions like grpc.
+pub const DEFAULT_MIN_FREE: &str = "1G";
+
+/// Free space on a filesystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FreeSpace {
+    /// Where the filesystem is mounted.
+    pub mount: String,
+    /// The space available to this process, in bytes.
+    pub available: u64,
+}
+
+/// Parses the output of `df -Pk <path>`.
+fn parse_df(output: &str) -> Option<FreeSpace> {
+    let fields: Vec<_> = output.lines().nth(1)?.split_whitespace().collect();
+    let available: u64 = fields.get(3)?.parse().ok()?;
+    let mount = fields.get(5..)?.join(" ");
+
+    Some(FreeSpace {
+        mount,
+        available: available * 1024,
+    })
+}
+
+/// Returns the free space on the filesystem holding `path`, or `None` if `df` doesn't
+/// report it.
+pub fn free_space(path: &Path) -> command::Result<Option<FreeSpace>> {
+    let mut command = Command::new("df");
+    command.arg("-Pk");
+    command.arg(path.to_string_lossy());
+
+    Ok(parse_df(&command.stdout()?))
+}
+
+/// Parses a size in bytes, or with a `K`, `M`, or `G` suffix (e.g., `512M` or `1.5G`).
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let (number, unit) = match input.char_indices().last() {
+        Some((index, 'k')) | Some((index, 'K')) => (&input[..index], 1u64 << 10),
+        Some((index, 'm')) | Some((index, 'M')) => (&input[..index], 1 << 20),
+        Some((index, 'g')) | Some((index, 'G')) => (&input[..index], 1 << 30),
+        _ => (input, 1),
+    };
+
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() && number >= 0.0 => Ok((number * unit as f64) as u64),
+        _ => Err(format!(
+            "{:?} isn't a size (e.g., 1073741824, 512M, or 1.5G)",
+            input
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_df() {
+        let output = "Filesystem           1024-blocks    Used Available Capacity Mounted on\n\
+                      overlay               61255492 48123104  10000000  83% /\n";
+
+        assert_eq!(
+            parse_df(output),
+            Some(FreeSpace {
+                mount: String::from("/"),
+                available: 10_240_000_000,
+            })
+        );
+        assert_eq!(parse_df("df: /nope: No such file or directory\n"), None);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size(DEFAULT_MIN_FREE).unwrap(), 1 << 30);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("1.5G").unwrap(), 3 << 29);
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert!(parse_size("lots").is_err());
+    }
+}
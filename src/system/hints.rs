@@ -0,0 +1,190 @@
+//! Hints for fixing well-known build failures, and recognition of network failures, from
+//! a failed command's output.
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Development packages that provide commonly missing headers.
+const HEADER_PACKAGES: &[(&str, &str)] = &[
+    ("zlib.h", "zlib-dev"),
+    ("zip.h", "libzip-dev"),
+    ("png.h", "libpng-dev"),
+    ("jpeglib.h", "libjpeg-turbo-dev"),
+    ("ft2build.h", "freetype-dev"),
+    ("webp/decode.h", "libwebp-dev"),
+    ("openssl/ssl.h", "openssl-dev"),
+    ("curl/curl.h", "curl-dev"),
+    ("libxml/parser.h", "libxml2-dev"),
+    ("sqlite3.h", "sqlite-dev"),
+    ("gmp.h", "gmp-dev"),
+    ("yaml.h", "yaml-dev"),
+    ("ldap.h", "openldap-dev"),
+    ("libpq-fe.h", "postgresql-dev"),
+    ("sasl/sasl.h", "cyrus-sasl-dev"),
+];
+
+/// Explains how to supply a missing build package.
+const PACKAGES_HINT: &str = "to the extension's build packages (in the registry, or with \
+                             F1_PECL_<NAME>_PACKAGES or F1_BUILTIN_<NAME>_PACKAGES)";
+
+/// Looks for a well-known failure in `output` (a failed command's stdout and stderr) and
+/// returns a hint for fixing it.
+pub fn diagnose(output: &str) -> Option<String> {
+    static MISSING_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"fatal error: ([\w./+-]+\.h): No such file or directory").unwrap()
+    });
+    static TOO_OLD: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"Package requirements \((\S+) >= ([\w.]+)\) were not met").unwrap()
+    });
+    static MISSING_PACKAGE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"Package requirements \(([\w.+-]+)[^)]*\) were not met").unwrap()
+    });
+
+    if let Some(caps) = MISSING_HEADER.captures(output) {
+        let header = &caps[1];
+        let hint = match HEADER_PACKAGES.iter().find(|(name, _)| *name == header) {
+            Some((_, package)) => {
+                format!("{} is missing; add {} {}", header, package, PACKAGES_HINT)
+            }
+            None => format!(
+                "{} is missing; add the -dev package that provides it {}",
+                header, PACKAGES_HINT
+            ),
+        };
+        return Some(hint);
+    }
+
+    if output.contains("Package requirements") && output.contains("Requested '") {
+        if let Some(caps) = TOO_OLD.captures(output) {
+            return Some(format!(
+                "{} is older than {} in this image's Alpine release; use an older version of \
+                 the extension (e.g., pecl:<name>@<version>) or a newer base image",
+                &caps[1], &caps[2]
+            ));
+        }
+    }
+
+    if let Some(caps) = MISSING_PACKAGE.captures(output) {
+        return Some(format!(
+            "pkg-config can't find {}; add the -dev package that provides {}.pc {}",
+            &caps[1], &caps[1], PACKAGES_HINT
+        ));
+    }
+
+    if output.contains("phpize: not found") || output.contains("phpize: No such file") {
+        return Some(String::from(
+            "phpize is missing; this tool needs an official PHP image (php:*-alpine)",
+        ));
+    }
+
+    if output.contains("Cannot find autoconf") {
+        return Some(String::from(
+            "autoconf is missing; $PHPIZE_DEPS (or --phpize-deps) should provide it, so check \
+             that it is set",
+        ));
+    }
+
+    if output.contains("No releases available for package")
+        || output.contains("No release available for package")
+    {
+        return Some(String::from(
+            "PECL has no matching release; check the name and version on https://pecl.php.net",
+        ));
+    }
+
+    None
+}
+
+/// Output from apk, pecl, and curl that indicates a network failure, which may succeed if
+/// retried.
+const NETWORK_SIGNATURES: &[&str] = &[
+    "temporary error (try again later)",
+    "network error",
+    "Could not resolve host",
+    "Name does not resolve",
+    "DNS lookup error",
+    "Failed to connect to",
+    "Connection refused",
+    "Connection reset by peer",
+    "Connection timed out",
+    "Operation timed out",
+    "SSL connect error",
+];
+
+/// Determines if `output` (a failed command's stdout and stderr) shows that it failed
+/// because of the network, rather than, e.g., a compile error.
+pub fn is_network_failure(output: &str) -> bool {
+    // pecl's messages, e.g. "Connection to `pecl.php.net:443' failed".
+    static PECL_NETWORK: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?m)(Connection to .* failed|Download of .* failed)").unwrap()
+    });
+
+    NETWORK_SIGNATURES
+        .iter()
+        .any(|signature| output.contains(signature))
+        || PECL_NETWORK.is_match(output)
+}
+
+/// Returns a hint for a program that couldn't be started because it doesn't exist.
+pub fn diagnose_missing_program(program: &str) -> Option<String> {
+    match program {
+        "pecl" => Some(String::from(
+            "this image doesn't include PEAR (pecl); use an official PHP image, which does",
+        )),
+        "phpize"
+        | "docker-php-source"
+        | "docker-php-ext-install"
+        | "docker-php-ext-configure"
+        | "docker-php-ext-enable" => Some(String::from(
+            "this tool needs an official PHP image (php:*-alpine), which provides it",
+        )),
+        "apk" | "scanelf" => Some(String::from("this tool only supports Alpine-based images")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnose() {
+        let output = "/tmp/pear/temp/zip/php_zip.c:32:10: fatal error: zlib.h: No such file or \
+                      directory\n";
+        assert_eq!(
+            diagnose(output).unwrap(),
+            format!("zlib.h is missing; add zlib-dev {}", PACKAGES_HINT)
+        );
+
+        let output = "checking for libzip >= 0.11 libzip != 1.3.1 libzip != 1.7.0... no\n\
+                      configure: error: Package requirements (libzip >= 1.7.0) were not met:\n\
+                      \n\
+                      Requested 'libzip >= 1.7.0' but version of libzip is 1.6.1\n";
+        assert!(diagnose(output)
+            .unwrap()
+            .starts_with("libzip is older than 1.7.0"));
+
+        let output = "configure: error: Package requirements (MagickWand) were not met:\n";
+        assert!(diagnose(output)
+            .unwrap()
+            .starts_with("pkg-config can't find MagickWand"));
+
+        assert!(diagnose("make: *** [Makefile:210: redis.lo] Error 1\n").is_none());
+    }
+
+    #[test]
+    fn test_is_network_failure() {
+        assert!(is_network_failure(
+            "fetch https://dl-cdn.alpinelinux.org/alpine/v3.19/main/x86_64/APKINDEX.tar.gz\n\
+             ERROR: https://dl-cdn.alpinelinux.org/alpine/v3.19/main: temporary error (try \
+             again later)\n"
+        ));
+        assert!(is_network_failure(
+            "Connection to `pecl.php.net:443' failed: php_network_getaddresses: getaddrinfo \
+             failed\n"
+        ));
+        assert!(!is_network_failure(
+            "redis.c:42:10: error: unknown type name 'zend_string'\n"
+        ));
+    }
+}
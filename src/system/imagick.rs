@@ -0,0 +1,328 @@
+//! Management of ImageMagick's security policy for the `imagick` extension.
+//!
+//! ImageMagick reads its limits and the list of permitted coders from a `policy.xml`
+//! file. Nearly every user of `pecl:imagick` needs to adjust it, most often to allow (or
+//! to make sure of denying) the Ghostscript-backed PDF and PostScript coders.
+
+use snafu::{ResultExt, Snafu};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use super::command;
+
+/// Directory containing ImageMagick's versioned configuration directories.
+const CONFIG_ROOT: &str = "/etc";
+
+/// The coders that delegate to Ghostscript.
+const GHOSTSCRIPT_CODERS: &[&str] = &["PDF", "PS", "PS2", "PS3", "EPS", "XPS"];
+
+/// Marks the policy line written by this module, so that later runs replace it.
+const MARKER: &str = "<!-- f1-ext-install: ghostscript coders -->";
+
+/// Errors encountered while managing the ImageMagick policy.
+#[derive(Debug, Snafu)]
+pub enum ImagickError {
+    /// A file or directory could not be accessed.
+    #[snafu(display("Failed to access {}: {}", path.display(), source))]
+    Io {
+        /// The underlying IO error
+        source: io::Error,
+        /// The path being accessed
+        path: PathBuf,
+    },
+
+    /// No `policy.xml` was found.
+    #[snafu(display("Could not find ImageMagick's policy.xml under {}", CONFIG_ROOT))]
+    MissingPolicy,
+
+    /// The policy file doesn't contain a `</policymap>` closing tag.
+    #[snafu(display("{} is not a valid ImageMagick policy", path.display()))]
+    InvalidPolicy {
+        /// The policy file
+        path: PathBuf,
+    },
+
+    /// An unknown value was given for `--imagick-pdf`.
+    #[snafu(display(r#"Unknown PDF policy "{}" (expected "allow" or "deny")"#, policy))]
+    UnknownPdfPolicy {
+        /// The value given
+        policy: String,
+    },
+}
+
+/// Helper type for the result of policy management.
+pub type Result<T> = std::result::Result<T, ImagickError>;
+
+/// Whether ImageMagick may use Ghostscript to read and write PDF and PostScript files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PdfPolicy {
+    /// Permit the Ghostscript coders (Ghostscript is installed too).
+    Allow,
+    /// Forbid the Ghostscript coders.
+    Deny,
+}
+
+impl PdfPolicy {
+    /// Returns the packages needed at run time for this policy.
+    pub fn packages(self) -> &'static [&'static str] {
+        match self {
+            Self::Allow => &["ghostscript"],
+            Self::Deny => &[],
+        }
+    }
+
+    /// Returns the `rights` attribute for the Ghostscript coders under this policy.
+    fn rights(self) -> &'static str {
+        match self {
+            Self::Allow => "read|write",
+            Self::Deny => "none",
+        }
+    }
+}
+
+impl FromStr for PdfPolicy {
+    type Err = ImagickError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            _ => Err(ImagickError::UnknownPdfPolicy {
+                policy: String::from(input),
+            }),
+        }
+    }
+}
+
+/// Finds the active `policy.xml` (e.g., `/etc/ImageMagick-7/policy.xml`).
+pub fn find_policy() -> Result<PathBuf> {
+    let root = Path::new(CONFIG_ROOT);
+    let entries = fs::read_dir(root).context(Io { path: root })?;
+
+    for entry in entries {
+        let entry = entry.context(Io { path: root })?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with("ImageMagick")
+        {
+            continue;
+        }
+
+        let policy = entry.path().join("policy.xml");
+        if policy.is_file() {
+            return Ok(policy);
+        }
+    }
+
+    Err(ImagickError::MissingPolicy)
+}
+
+/// Returns the `pattern` written for the Ghostscript coders (e.g., `{PDF,PS}`).
+fn ghostscript_pattern() -> String {
+    format!("{{{}}}", GHOSTSCRIPT_CODERS.join(","))
+}
+
+/// Removes the Ghostscript coders from a coder rule's `line`, keeping the rule for any
+/// other coders it names (e.g., `pattern="{MSL,MVG,PS}"` becomes `pattern="{MSL,MVG}"`).
+/// Returns `None` if the rule only names Ghostscript coders, so it should be dropped.
+fn strip_ghostscript_coders(line: &str) -> Option<String> {
+    let trimmed = line.trim();
+    if !trimmed.starts_with("<policy ") || !trimmed.contains(r#"domain="coder""#) {
+        return Some(String::from(line));
+    }
+
+    let start = match line.find(r#"pattern=""#) {
+        Some(start) => start + r#"pattern=""#.len(),
+        None => return Some(String::from(line)),
+    };
+    let end = match line[start..].find('"') {
+        Some(end) => start + end,
+        None => return Some(String::from(line)),
+    };
+
+    let pattern = &line[start..end];
+    let list = pattern
+        .strip_prefix('{')
+        .and_then(|list| list.strip_suffix('}'))
+        .unwrap_or(pattern);
+    let coders: Vec<_> = list.split(',').map(str::trim).collect();
+    let kept: Vec<_> = coders
+        .iter()
+        .filter(|coder| !GHOSTSCRIPT_CODERS.contains(coder))
+        .collect();
+
+    if kept.len() == coders.len() {
+        return Some(String::from(line));
+    }
+
+    let pattern = match kept.as_slice() {
+        [] => return None,
+        [coder] => String::from(**coder),
+        kept => format!(
+            "{{{}}}",
+            kept.iter()
+                .map(|coder| **coder)
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+    };
+
+    Some(format!("{}{}{}", &line[..start], pattern, &line[end..]))
+}
+
+/// Rewrites the policy in `contents` so that the Ghostscript coders follow `policy`.
+///
+/// Existing rules are stripped of those coders, since ImageMagick applies rules in order
+/// and an earlier, conflicting rule could otherwise win. Rules for other coders are kept.
+fn set_pdf_policy(contents: &str, policy: PdfPolicy) -> Option<String> {
+    let end = contents.rfind("</policymap>")?;
+
+    let mut output = String::with_capacity(contents.len() + 128);
+    for line in contents[..end].lines() {
+        if line.trim() == MARKER {
+            continue;
+        }
+
+        if let Some(line) = strip_ghostscript_coders(line) {
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+
+    output.push_str(&format!(
+        "  {}\n  <policy domain=\"coder\" rights=\"{}\" pattern=\"{}\" />\n",
+        MARKER,
+        policy.rights(),
+        ghostscript_pattern()
+    ));
+    output.push_str(&contents[end..]);
+
+    Some(output)
+}
+
+/// Updates the policy file at `path` so that the Ghostscript coders follow `policy`.
+pub fn apply_pdf_policy(path: &Path, policy: PdfPolicy) -> Result<()> {
+    let contents = fs::read_to_string(path).context(Io { path })?;
+    let updated = set_pdf_policy(&contents, policy).ok_or_else(|| ImagickError::InvalidPolicy {
+        path: path.to_path_buf(),
+    })?;
+
+    fs::write(path, updated).context(Io { path })
+}
+
+/// Returns the shell equivalent of [`find_policy`] followed by [`install_policy`] (if
+/// `source` is given) and [`apply_pdf_policy`] (if `pdf` is given), for scripts.
+pub fn policy_script(source: Option<&Path>, pdf: Option<PdfPolicy>) -> String {
+    let mut script = format!(
+        r#"policy="$(ls {}/ImageMagick*/policy.xml | head -n 1)""#,
+        CONFIG_ROOT
+    );
+
+    if let Some(source) = source {
+        script.push_str(&format!("\ncp {} \"$policy\"", command::quote_path(source)));
+    }
+
+    if let Some(pdf) = pdf {
+        // The same edits as set_pdf_policy: coder lists are padded with commas so that
+        // each Ghostscript coder can be removed as `,<coder>,`.
+        let coders = GHOSTSCRIPT_CODERS.join("|");
+        script.push_str(&format!(
+            concat!(
+                "\nsed -i -E",
+                r#" -e '/^[[:space:]]*{marker}$/d'"#,
+                r#" -e '/^[[:space:]]*<policy .*domain="coder"/{{'"#,
+                r#" -e '/pattern="({coders})"/d'"#,
+                r#" -e 's/pattern="\{{([^"]*)\}}"/pattern="{{,\1,}}"/'"#,
+                r#" -e ':strip'"#,
+                r#" -e 's/(pattern="\{{[^"]*),({coders}),/\1,/'"#,
+                r#" -e 't strip'"#,
+                r#" -e '/pattern="\{{,\}}"/d'"#,
+                r#" -e 's/pattern="\{{,([^,"]*),\}}"/pattern="\1"/'"#,
+                r#" -e 's/pattern="\{{,([^"]*),\}}"/pattern="{{\1}}"/'"#,
+                r#" -e '}}'"#,
+                r#" -e 's#</policymap>#  {marker}\n  <policy domain="coder" rights="{rights}" pattern="{pattern}" />\n</policymap>#'"#,
+                r#" "$policy""#,
+            ),
+            marker = MARKER,
+            coders = coders,
+            rights = pdf.rights(),
+            pattern = ghostscript_pattern(),
+        ));
+    }
+
+    script
+}
+
+/// Replaces the policy file at `path` with the contents of `source`.
+pub fn install_policy(path: &Path, source: &Path) -> Result<()> {
+    fs::copy(source, path).context(Io { path: source })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"<policymap>
+  <policy domain="resource" name="memory" value="256MiB"/>
+  <policy domain="coder" rights="none" pattern="PDF" />
+</policymap>
+"#;
+
+    #[test]
+    fn test_allow_pdf() {
+        let updated = set_pdf_policy(POLICY, PdfPolicy::Allow).unwrap();
+
+        assert!(updated.contains(r#"name="memory""#));
+        assert!(!updated.contains(r#"rights="none" pattern="PDF""#));
+        assert!(updated.contains(r#"rights="read|write" pattern="{PDF,PS,PS2,PS3,EPS,XPS}""#));
+        assert!(updated.trim_end().ends_with("</policymap>"));
+    }
+
+    #[test]
+    fn test_other_coders_kept() {
+        let policy = r#"<policymap>
+  <policy domain="coder" rights="none" pattern="PSD" />
+  <policy domain="coder" rights="none" pattern="{MSL,MVG,PS,PDF,XPS}" />
+  <policy domain="coder" rights="none" pattern="{EPS,MVG}" />
+</policymap>
+"#;
+        let updated = set_pdf_policy(policy, PdfPolicy::Allow).unwrap();
+
+        assert!(updated.contains(r#"pattern="PSD""#));
+        assert!(updated.contains(r#"rights="none" pattern="{MSL,MVG}""#));
+        assert!(updated.contains(r#"rights="none" pattern="MVG""#));
+        assert_eq!(updated.matches("<policy ").count(), 4);
+    }
+
+    #[test]
+    fn test_policy_replaced() {
+        let allowed = set_pdf_policy(POLICY, PdfPolicy::Allow).unwrap();
+        let denied = set_pdf_policy(&allowed, PdfPolicy::Deny).unwrap();
+
+        assert_eq!(denied.matches(MARKER).count(), 1);
+        assert!(!denied.contains("read|write"));
+        assert!(denied.contains(r#"rights="none" pattern="{PDF"#));
+    }
+
+    #[test]
+    fn test_policy_script() {
+        let script = policy_script(Some(Path::new("/build/policy.xml")), Some(PdfPolicy::Deny));
+        let lines: Vec<_> = script.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], r#"cp /build/policy.xml "$policy""#);
+        assert!(lines[2].contains(r#"rights="none" pattern="{PDF,PS,PS2,PS3,EPS,XPS}""#));
+
+        assert_eq!(policy_script(None, None).lines().count(), 1);
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        assert_eq!(set_pdf_policy("<xml/>", PdfPolicy::Deny), None);
+    }
+}
@@ -0,0 +1,817 @@
+//! Helpers for writing PHP ini configuration.
+
+use regex::{Captures, Regex};
+use snafu::{ResultExt, Snafu};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    env, fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{LazyLock, OnceLock},
+};
+
+use super::command;
+
+/// The value of `$PHP_INI_DIR` in the official Docker images.
+const DEFAULT_INI_DIR: &str = "/usr/local/etc/php";
+
+/// The symbol exported by Zend extensions, which PHP has to load with `zend_extension=`
+/// rather than `extension=`. `docker-php-ext-enable` looks for the same symbol.
+const ZEND_EXTENSION_SYMBOL: &[u8] = b"zend_extension_entry";
+
+/// The permissions given to ini files copied into the scan directory.
+const CONF_MODE: u32 = 0o644;
+
+/// Zend extensions that refuse to start unless they are loaded before any other.
+const FIRST_ZEND_EXTENSIONS: &[&str] = &["ioncube_loader"];
+
+/// PHP's configuration directory, if given on the command line.
+static INI_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// The directory scanned by PHP for additional ini files, if given on the command line.
+static SCAN_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Errors encountered while writing ini files.
+#[derive(Debug, Snafu)]
+pub enum IniError {
+    /// An ini file could not be read.
+    #[snafu(display("Failed to read {}: {}", path.display(), source))]
+    Read {
+        /// The underlying IO error
+        source: io::Error,
+        /// The file being read
+        path: PathBuf,
+    },
+
+    /// An ini value refers to an environment variable that isn't set and has no default.
+    #[snafu(display(
+        "{} refers to ${{{}}}, which isn't set (use ${{{}:-<default>}} to give a default)",
+        key,
+        variable,
+        variable
+    ))]
+    UndefinedVariable {
+        /// The ini directive whose value holds the placeholder
+        key: String,
+        /// The name of the environment variable
+        variable: String,
+    },
+
+    /// An unknown `php.ini` template was requested.
+    #[snafu(display(
+        r#"Unknown php.ini template {:?} (expected "production" or "development")"#,
+        template
+    ))]
+    UnknownTemplate {
+        /// The value given
+        template: String,
+    },
+
+    /// A `--conf` path or pattern didn't match any files.
+    #[snafu(display("No ini files match {}", pattern))]
+    NoMatches {
+        /// The path or pattern given
+        pattern: String,
+    },
+
+    /// An ini file could not be written.
+    #[snafu(display("Failed to write {}: {}", path.display(), source))]
+    Write {
+        /// The underlying IO error
+        source: io::Error,
+        /// The file being written
+        path: PathBuf,
+    },
+}
+
+/// Helper type for the result of ini operations.
+pub type Result<T> = std::result::Result<T, IniError>;
+
+/// The `php.ini` templates shipped with PHP (and in the official images'
+/// `$PHP_INI_DIR`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhpIni {
+    /// `php.ini-production`
+    Production,
+    /// `php.ini-development`
+    Development,
+}
+
+impl PhpIni {
+    /// Returns the file name of this template.
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Production => "php.ini-production",
+            Self::Development => "php.ini-development",
+        }
+    }
+}
+
+impl FromStr for PhpIni {
+    type Err = IniError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "production" => Ok(Self::Production),
+            "development" => Ok(Self::Development),
+            _ => Err(IniError::UnknownTemplate {
+                template: String::from(input),
+            }),
+        }
+    }
+}
+
+/// Copies the `template` in PHP's configuration directory to `php.ini`, replacing any
+/// `php.ini` already there. Returns the path of the new `php.ini`.
+pub fn install_php_ini(template: PhpIni) -> Result<PathBuf> {
+    let dir = ini_dir();
+    let from = dir.join(template.file_name());
+    let to = dir.join("php.ini");
+
+    let copied = command::skip_step(
+        format_args!("copy {} to {}", from.display(), to.display()),
+        format_args!(
+            "cp {} {}",
+            command::quote_path(&from),
+            command::quote_path(&to)
+        ),
+    );
+    if copied {
+        return Ok(to);
+    }
+
+    let contents = fs::read(&from).context(Read { path: &from })?;
+    fs::write(&to, contents).context(Write { path: &to })?;
+
+    Ok(to)
+}
+
+/// Determines if a file `name` matches a shell-style `pattern`, in which `*` matches any
+/// run of characters and `?` matches any one character.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let expression = regex::escape(pattern)
+        .replace(r"\*", ".*")
+        .replace(r"\?", ".");
+
+    Regex::new(&format!("^{}$", expression)).is_ok_and(|regex| regex.is_match(name))
+}
+
+/// Lists the ini files in `dir` whose names match `pattern`, in order.
+fn list_matching(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).context(Read { path: dir })?;
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| matches_pattern(pattern, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Finds the files named by a `--conf` argument: a file, a directory (meaning every
+/// `.ini` file in it), or a pattern with wildcards in its file name (e.g.,
+/// `/build/conf.d/*.ini`).
+pub fn find_conf_files(spec: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(spec);
+
+    let files = if path.is_dir() {
+        list_matching(path, "*.ini")?
+    } else if spec.contains(['*', '?']) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let pattern = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        list_matching(dir, &pattern)?
+    } else if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        Vec::new()
+    };
+
+    if files.is_empty() {
+        return NoMatches { pattern: spec }.fail();
+    }
+
+    Ok(files)
+}
+
+/// Copies the ini file at `from` into the scan directory, so that PHP reads it. The copy
+/// keeps the file's name (adding `.ini` if it lacks the extension, since PHP skips other
+/// files) and is readable by everyone. Returns the path of the copy.
+pub fn install_conf_file(from: &Path) -> Result<PathBuf> {
+    let mut name = from
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.ends_with(".ini") {
+        name.push_str(".ini");
+    }
+
+    let dir = scan_dir();
+    let to = dir.join(name);
+
+    let copied = command::skip_step(
+        format_args!("copy {} to {}", from.display(), to.display()),
+        format_args!(
+            "mkdir -p {dir} && cp {from} {to} && chmod {mode:o} {to}",
+            dir = command::quote_path(&dir),
+            from = command::quote_path(from),
+            to = command::quote_path(&to),
+            mode = CONF_MODE,
+        ),
+    );
+    if copied {
+        return Ok(to);
+    }
+
+    let contents = fs::read(from).context(Read { path: from })?;
+    fs::create_dir_all(&dir).context(Write { path: &dir })?;
+    fs::write(&to, contents).context(Write { path: &to })?;
+    fs::set_permissions(&to, fs::Permissions::from_mode(CONF_MODE)).context(Write { path: &to })?;
+
+    Ok(to)
+}
+
+/// Overrides the directories that ini files are written to, for PHP builds that don't
+/// follow the layout of the official images (or staging roots). Without a `scan_dir`,
+/// `conf.d` under the configuration directory is used. Only the first call has an effect.
+pub fn set_dirs(ini_dir: Option<PathBuf>, scan_dir: Option<PathBuf>) {
+    if let Some(dir) = ini_dir {
+        // The docker-php-ext-* scripts read $PHP_INI_DIR, so they have to agree.
+        env::set_var("PHP_INI_DIR", &dir);
+        let _ = INI_DIR.set(dir);
+    }
+    if let Some(dir) = scan_dir {
+        let _ = SCAN_DIR.set(dir);
+    }
+}
+
+/// Returns PHP's configuration directory: the one set with [`set_dirs`], `$PHP_INI_DIR`
+/// if set, or otherwise the location used by the official Docker images.
+pub fn ini_dir() -> PathBuf {
+    if let Some(dir) = INI_DIR.get() {
+        return dir.clone();
+    }
+
+    match env::var_os("PHP_INI_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(DEFAULT_INI_DIR),
+    }
+}
+
+/// Returns the directory scanned by PHP for additional ini files.
+pub fn scan_dir() -> PathBuf {
+    match SCAN_DIR.get() {
+        Some(dir) => dir.clone(),
+        None => ini_dir().join("conf.d"),
+    }
+}
+
+/// Returns the directory that the `docker-php-ext-*` scripts write ini files to.
+pub fn docker_scan_dir() -> PathBuf {
+    ini_dir().join("conf.d")
+}
+
+/// Determines if the configuration directory was set with [`set_dirs`].
+pub fn ini_dir_overridden() -> bool {
+    INI_DIR.get().is_some()
+}
+
+/// Determines if the scan directory was set to somewhere other than where the
+/// `docker-php-ext-*` scripts write, so that their files have to be moved.
+pub fn scan_dir_overridden() -> bool {
+    scan_dir() != docker_scan_dir()
+}
+
+/// Returns the directories that may hold files loading extensions: the scan directory,
+/// and any `conf.d-*` directories beside it (see the `sapi=` modifier).
+pub fn loader_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![scan_dir()];
+
+    if let Ok(entries) = fs::read_dir(ini_dir()) {
+        let mut extra: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("conf.d-"))
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        extra.sort();
+        dirs.extend(extra);
+    }
+
+    dirs
+}
+
+/// Splits an ini directive given as `key=value` (e.g., `apc.enable_cli=1`). Returns
+/// `None` if there is no `=` or the key is empty.
+pub fn parse_setting(input: &str) -> Option<(String, String)> {
+    let (key, value) = input.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() {
+        return None;
+    }
+
+    Some((String::from(key), String::from(value.trim())))
+}
+
+/// Replaces `${VAR}` and `${VAR:-default}` placeholders in the ini `value` for `key`
+/// with the result of `lookup`, so that one template can serve images built with
+/// different build arguments. An empty variable counts as unset when a default is given.
+///
+/// PHP also expands `${VAR}` when it reads ini files, so `$${VAR}` is written as a
+/// literal `${VAR}` for PHP to resolve at run time instead.
+fn substitute<F>(key: &str, value: &str, lookup: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    static PLACEHOLDER: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"\$(\$)?\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").unwrap());
+
+    let mut undefined = None;
+    let substituted = PLACEHOLDER.replace_all(value, |caps: &Captures<'_>| {
+        if caps.get(1).is_some() {
+            return caps[0][1..].to_string();
+        }
+
+        let variable = &caps[2];
+        match (lookup(variable), caps.get(3)) {
+            (Some(found), Some(default)) if found.is_empty() => default.as_str().to_string(),
+            (Some(found), _) => found,
+            (None, Some(default)) => default.as_str().to_string(),
+            (None, None) => {
+                undefined.get_or_insert_with(|| variable.to_string());
+                String::new()
+            }
+        }
+    });
+
+    match undefined {
+        Some(variable) => UndefinedVariable { key, variable }.fail(),
+        None => Ok(substituted.into_owned()),
+    }
+}
+
+/// Resolves environment variable placeholders (see [`substitute`]) in the values of
+/// `settings`.
+fn expand_env(settings: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+    settings
+        .iter()
+        .map(|(key, value)| {
+            let value = substitute(key, value, |name| env::var(name).ok())?;
+            Ok((key.clone(), value))
+        })
+        .collect()
+}
+
+/// Quotes an ini `value` unless PHP would read it back unchanged as written.
+///
+/// Values made only of words and the operators PHP allows in expressions (such as
+/// `E_ALL & ~E_DEPRECATED`) stay bare so that constants are still evaluated. Anything
+/// else, such as a value containing `;`, `=`, a quote, or surrounding whitespace, is
+/// wrapped in double quotes with embedded `"` and `\` escaped.
+fn quote_value(value: &str) -> Cow<'_, str> {
+    let is_bare = |c: char| c.is_ascii_alphanumeric() || "_-.,/:+*@%~${}".contains(c);
+    let is_expression = |c: char| c.is_ascii_alphanumeric() || " _&|~!^()".contains(c);
+
+    let simple = !value.is_empty()
+        && value.trim() == value
+        && (value.chars().all(is_bare) || value.chars().all(is_expression));
+    if simple {
+        return Cow::Borrowed(value);
+    }
+
+    let escaped = value.replace('\\', r"\\").replace('"', r#"\""#);
+    Cow::Owned(format!("\"{}\"", escaped))
+}
+
+/// Renders ini settings as the contents of an ini file.
+fn render(name: &str, settings: &BTreeMap<String, String>) -> String {
+    let mut contents = format!("; Settings for {} (generated by f1-ext-install)\n", name);
+    for (key, value) in settings {
+        contents.push_str(&format!("{}={}\n", key, quote_value(value)));
+    }
+
+    contents
+}
+
+/// Returns the path of the file in `dir` that holds the named extension's settings.
+///
+/// The file is named `f1-<name>.ini` so that it sorts after the
+/// `docker-php-ext-<name>.ini` file that loads the extension.
+pub fn extension_ini_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("f1-{}.ini", name))
+}
+
+/// Writes `settings` for the named extension into its own file in `dir` (see
+/// [`extension_ini_path`]), after substituting environment variables into their values.
+pub fn write_extension_ini(
+    dir: &Path,
+    name: &str,
+    settings: &BTreeMap<String, String>,
+) -> Result<PathBuf> {
+    let path = extension_ini_path(dir, name);
+    let settings = expand_env(settings)?;
+    let contents = render(name, &settings);
+    if command::skip_write(&path, &contents) {
+        return Ok(path);
+    }
+
+    fs::create_dir_all(dir).context(Write { path: dir })?;
+    fs::write(&path, contents).context(Write { path: &path })?;
+
+    Ok(path)
+}
+
+/// Returns the name of the file that `docker-php-ext-enable` would write to load the
+/// named extension.
+pub fn default_ini_name(name: &str) -> String {
+    format!("docker-php-ext-{}.ini", name)
+}
+
+/// Determines if a shared module's `contents` export the symbol of a Zend extension.
+fn exports_zend_extension(contents: &[u8]) -> bool {
+    contents
+        .windows(ZEND_EXTENSION_SYMBOL.len())
+        .any(|window| window == ZEND_EXTENSION_SYMBOL)
+}
+
+/// Determines if the shared module at `path` is a Zend extension (e.g., opcache or
+/// Xdebug).
+pub fn is_zend_extension(path: &Path) -> Result<bool> {
+    let contents = fs::read(path).context(Read { path })?;
+    Ok(exports_zend_extension(&contents))
+}
+
+/// Returns the name of the file that loads `module` when it is enabled without
+/// `docker-php-ext-enable`.
+///
+/// This matches the name `docker-php-ext-enable` would use, except for the Zend
+/// extensions that have to be loaded first (e.g., the ionCube Loader), whose files are
+/// named to sort before the rest of `conf.d`.
+pub fn native_ini_name(module: &str, zend: bool) -> String {
+    let first = FIRST_ZEND_EXTENSIONS
+        .iter()
+        .any(|prefix| module.starts_with(prefix));
+
+    if zend && first {
+        format!("00-{}.ini", module)
+    } else {
+        default_ini_name(module)
+    }
+}
+
+/// Renders the contents of the file that loads `module`.
+fn render_loader(module: &str, zend: bool) -> String {
+    let directive = if zend { "zend_extension" } else { "extension" };
+    format!("{}={}.so\n", directive, module)
+}
+
+/// Writes the file at `path` that loads `module`, for images without
+/// `docker-php-ext-enable`.
+pub fn write_loader_ini(path: &Path, module: &str, zend: bool) -> Result<()> {
+    let contents = render_loader(module, zend);
+    if command::skip_write(path, &contents) {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(Write { path: parent })?;
+    }
+
+    fs::write(path, contents).context(Write { path })
+}
+
+/// Determines if an ini `line` loads the shared module `name` (e.g.,
+/// `zend_extension=/path/to/xdebug.so` or `extension="redis"`).
+fn loads_module(line: &str, name: &str) -> bool {
+    let (key, value) = match line.split_once('=') {
+        Some(split) => split,
+        None => return false,
+    };
+
+    let key = key.trim();
+    if key != "extension" && key != "zend_extension" {
+        return false;
+    }
+
+    let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+    let file = value.rsplit('/').next().unwrap_or(value);
+    let module = file.strip_suffix(".so").unwrap_or(file);
+
+    module.eq_ignore_ascii_case(name)
+}
+
+/// Comments out the lines of an ini file's `contents` that load the module `name`.
+/// Returns `None` if no line loads it, or the new contents, which are empty if nothing
+/// but comments would be left.
+fn disable_in(contents: &str, name: &str) -> Option<String> {
+    if !contents.lines().any(|line| loads_module(line, name)) {
+        return None;
+    }
+
+    let mut disabled = String::new();
+    let mut active = false;
+    for line in contents.lines() {
+        if loads_module(line, name) {
+            disabled.push(';');
+        } else if !line.trim().is_empty() && !line.trim_start().starts_with(';') {
+            active = true;
+        }
+        disabled.push_str(line);
+        disabled.push('\n');
+    }
+
+    if active {
+        Some(disabled)
+    } else {
+        Some(String::new())
+    }
+}
+
+/// Stops PHP from loading the shared module `name` from the ini files in `dirs`.
+///
+/// Files that only load the module are deleted; in any others, the lines loading it are
+/// commented out. Returns the paths of the files that were changed.
+pub fn disable_extension(dirs: &[PathBuf], name: &str) -> Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+
+    for dir in dirs {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(source) => {
+                return Err(IniError::Read {
+                    source,
+                    path: dir.clone(),
+                })
+            }
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ini"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let contents = fs::read_to_string(&path).context(Read { path: &path })?;
+            match disable_in(&contents, name) {
+                None => continue,
+                Some(disabled) if disabled.is_empty() => {
+                    let removed = command::skip_step(
+                        format_args!("remove {}", path.display()),
+                        format_args!("rm {}", command::quote_path(&path)),
+                    );
+                    if !removed {
+                        fs::remove_file(&path).context(Write { path: &path })?;
+                    }
+                }
+                Some(disabled) => {
+                    if !command::skip_write(&path, &disabled) {
+                        fs::write(&path, disabled).context(Write { path: &path })?;
+                    }
+                }
+            }
+
+            changed.push(path);
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Removes the settings files (see [`write_extension_ini`]) for the named extension from
+/// `dirs`. Returns the paths of the files that were removed.
+pub fn remove_extension_ini(dirs: &[PathBuf], name: &str) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+
+    for dir in dirs {
+        let path = dir.join(format!("f1-{}.ini", name));
+        if !path.exists() {
+            continue;
+        }
+
+        let skipped = command::skip_step(
+            format_args!("remove {}", path.display()),
+            format_args!("rm {}", command::quote_path(&path)),
+        );
+        if !skipped {
+            fs::remove_file(&path).context(Write { path: &path })?;
+        }
+        removed.push(path);
+    }
+
+    Ok(removed)
+}
+
+/// Moves the file at `from` that loads an extension to each of the `targets`, changing
+/// where (or for which SAPIs) PHP loads the extension.
+pub fn relocate_loader_ini(from: &Path, targets: &[PathBuf]) -> Result<()> {
+    if command::is_dry_run() {
+        for target in targets.iter().filter(|target| *target != from) {
+            let parent = target.parent().unwrap_or(target);
+            command::skip_step(
+                format_args!("copy {} to {}", from.display(), target.display()),
+                format_args!(
+                    "mkdir -p {} && cp {} {}",
+                    command::quote_path(parent),
+                    command::quote_path(from),
+                    command::quote_path(target)
+                ),
+            );
+        }
+        if !targets.iter().any(|target| target == from) {
+            command::skip_step(
+                format_args!("remove {}", from.display()),
+                format_args!("rm {}", command::quote_path(from)),
+            );
+        }
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(from).context(Read { path: from })?;
+
+    for target in targets {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).context(Write { path: parent })?;
+        }
+        fs::write(target, &contents).context(Write { path: target })?;
+    }
+
+    if !targets.iter().any(|target| target == from) {
+        fs::remove_file(from).context(Write { path: from })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(
+            parse_setting("opcache.memory_consumption=256"),
+            Some((
+                String::from("opcache.memory_consumption"),
+                String::from("256")
+            ))
+        );
+        assert_eq!(parse_setting("=256"), None);
+        assert_eq!(parse_setting("apc.enable_cli"), None);
+    }
+
+    #[test]
+    fn test_substitute() {
+        let lookup = |name: &str| match name {
+            "APP_ENV" => Some(String::from("prod")),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+
+        assert_eq!(
+            substitute("k", "/var/log/${APP_ENV}.log", lookup).unwrap(),
+            "/var/log/prod.log"
+        );
+        assert_eq!(substitute("k", "${EMPTY:-256}", lookup).unwrap(), "256");
+        assert_eq!(substitute("k", "${MEMORY:-128M}", lookup).unwrap(), "128M");
+        assert_eq!(
+            substitute("k", "$${APP_ENV}", lookup).unwrap(),
+            "${APP_ENV}",
+            "escaped placeholders are left for PHP"
+        );
+
+        assert!(matches!(
+            substitute("opcache.jit", "${JIT}", lookup),
+            Err(IniError::UndefinedVariable { key, variable })
+                if key == "opcache.jit" && variable == "JIT"
+        ));
+    }
+
+    #[test]
+    fn test_zend_extension_loader() {
+        let xdebug = b"\x7fELF\0\0xdebug_init\0zend_extension_entry\0";
+        let redis = b"\x7fELF\0\0get_module\0zm_startup_redis\0";
+        assert!(exports_zend_extension(xdebug));
+        assert!(!exports_zend_extension(redis));
+
+        assert_eq!(render_loader("xdebug", true), "zend_extension=xdebug.so\n");
+        assert_eq!(render_loader("redis", false), "extension=redis.so\n");
+
+        assert_eq!(native_ini_name("xdebug", true), "docker-php-ext-xdebug.ini");
+        assert_eq!(
+            native_ini_name("ioncube_loader_lin_8.1", true),
+            "00-ioncube_loader_lin_8.1.ini"
+        );
+    }
+
+    #[test]
+    fn test_loads_module() {
+        assert!(loads_module("extension=redis", "redis"));
+        assert!(loads_module("extension = \"redis.so\"", "redis"));
+        assert!(loads_module(
+            "zend_extension=/usr/local/lib/php/extensions/no-debug-non-zts-20210902/opcache.so",
+            "opcache"
+        ));
+        assert!(!loads_module("extension=redis", "igbinary"));
+        assert!(!loads_module(";extension=redis", "redis"));
+        assert!(!loads_module("redis.session.locking_enabled=1", "redis"));
+    }
+
+    #[test]
+    fn test_disable_in() {
+        assert_eq!(disable_in("extension=redis\n", "igbinary"), None);
+        assert_eq!(
+            disable_in("; loads xdebug\nzend_extension=xdebug\n", "xdebug").as_deref(),
+            Some("")
+        );
+        assert_eq!(
+            disable_in("extension=igbinary\nextension=redis\n", "redis").as_deref(),
+            Some("extension=igbinary\n;extension=redis\n")
+        );
+    }
+
+    #[test]
+    fn test_remove_dry_run() {
+        let dir = env::temp_dir().join(format!("f1-ext-install-ini-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let loader = dir.join("docker-php-ext-redis.ini");
+        let settings = dir.join("f1-redis.ini");
+        fs::write(&loader, "extension=redis\n").unwrap();
+        fs::write(&settings, "redis.session.locking_enabled=1\n").unwrap();
+        let dirs = [dir.clone()];
+
+        command::set_mode(command::Mode::DryRun);
+        let disabled = disable_extension(&dirs, "redis").unwrap();
+        let removed = remove_extension_ini(&dirs, "redis").unwrap();
+        command::set_mode(command::Mode::Run);
+        let kept = loader.exists() && settings.exists();
+
+        let disabled_again = disable_extension(&dirs, "redis").unwrap();
+        let removed_again = remove_extension_ini(&dirs, "redis").unwrap();
+        let gone = !loader.exists() && !settings.exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(disabled, disabled_again);
+        assert_eq!(removed, removed_again);
+        assert_eq!(disabled, [loader]);
+        assert_eq!(removed, [settings]);
+        assert!(kept, "a dry run shouldn't change anything");
+        assert!(gone);
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("*.ini", "zz-app.ini"));
+        assert!(matches_pattern("app-?.ini", "app-1.ini"));
+        assert!(!matches_pattern("*.ini", "README.md"));
+        assert!(!matches_pattern("app.ini", "app1ini"), "dots are literal");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut settings = BTreeMap::new();
+        settings.insert(String::from("opcache.enable_cli"), String::from("1"));
+        settings.insert(
+            String::from("opcache.memory_consumption"),
+            String::from("128"),
+        );
+
+        assert_eq!(
+            render("opcache", &settings),
+            "; Settings for opcache (generated by f1-ext-install)\n\
+             opcache.enable_cli=1\n\
+             opcache.memory_consumption=128\n"
+        );
+    }
+
+    #[test]
+    fn test_quote_value() {
+        assert_eq!(quote_value("128M"), "128M");
+        assert_eq!(quote_value("/tmp/sessions"), "/tmp/sessions");
+        assert_eq!(quote_value("${HOME}/cache"), "${HOME}/cache");
+        assert_eq!(
+            quote_value("E_ALL & ~E_DEPRECATED"),
+            "E_ALL & ~E_DEPRECATED"
+        );
+        assert_eq!(quote_value(""), r#""""#);
+        assert_eq!(quote_value("a;b"), r#""a;b""#);
+        assert_eq!(
+            quote_value("tcp://redis:6379?auth=secret"),
+            r#""tcp://redis:6379?auth=secret""#
+        );
+        assert_eq!(quote_value(" padded"), r#"" padded""#);
+        assert_eq!(quote_value(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+    }
+}
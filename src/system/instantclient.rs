@@ -0,0 +1,232 @@
+//! Installation of the Oracle Instant Client needed to build `oci8`.
+//!
+//! Oracle's license terms don't allow us to fetch the Instant Client on the user's behalf,
+//! so it has to be provided either as files placed in [`INSTANT_CLIENT_DIR`] (e.g., with a
+//! `COPY` or a bind mount) or as download URLs in [`INSTANT_CLIENT_URLS_ENV`].
+
+use snafu::{ResultExt, Snafu};
+use std::{
+    env, fs, io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use super::command::{self, Command, CommandError};
+
+/// Where the Instant Client is expected to live. The registry's `oci8` configure flags
+/// point here.
+pub const INSTANT_CLIENT_DIR: &str = "/opt/oracle/instantclient";
+
+/// Environment variable holding a comma-separated list of Instant Client zip URLs (the
+/// "Basic" or "Basic Light" package plus the "SDK" package).
+pub const INSTANT_CLIENT_URLS_ENV: &str = "F1_OCI8_INSTANTCLIENT_URLS";
+
+/// Prefix of the Instant Client's main shared library.
+const CLIENT_LIBRARY: &str = "libclntsh.so";
+
+/// Errors encountered while installing the Instant Client.
+#[derive(Debug, Snafu)]
+pub enum InstantClientError {
+    /// A download or extraction command failed.
+    #[snafu(display("{}", source))]
+    CommandFailed {
+        /// The underlying command error
+        source: CommandError,
+    },
+
+    /// A file or directory could not be accessed.
+    #[snafu(display("Failed to access {}: {}", path.display(), source))]
+    Io {
+        /// The underlying IO error
+        source: io::Error,
+        /// The path being accessed
+        path: PathBuf,
+    },
+
+    /// No Instant Client was provided.
+    #[snafu(display(
+        "oci8 needs the Oracle Instant Client: copy it into {} or set {} to the URLs of \
+         the Basic and SDK zip files",
+        INSTANT_CLIENT_DIR,
+        INSTANT_CLIENT_URLS_ENV
+    ))]
+    Missing,
+
+    /// The downloaded archives didn't contain an `instantclient_*` directory.
+    #[snafu(display("No instantclient_* directory found in {}", dir.display()))]
+    MissingDirectory {
+        /// The directory the archives were extracted into
+        dir: PathBuf,
+    },
+}
+
+impl From<CommandError> for InstantClientError {
+    fn from(source: CommandError) -> Self {
+        Self::CommandFailed { source }
+    }
+}
+
+/// Helper type for the result of installing the Instant Client.
+pub type Result<T> = std::result::Result<T, InstantClientError>;
+
+/// Splits the value of [`INSTANT_CLIENT_URLS_ENV`] into individual URLs.
+fn parse_urls(input: &str) -> Vec<&str> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .collect()
+}
+
+/// Finds the file in `dir` whose name starts with `prefix`.
+fn find_entry(dir: &Path, prefix: &str) -> Result<Option<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => {
+            return Err(InstantClientError::Io {
+                source,
+                path: dir.to_path_buf(),
+            })
+        }
+    };
+
+    for entry in entries {
+        let entry = entry.context(Io { path: dir })?;
+        if entry.file_name().to_string_lossy().starts_with(prefix) {
+            return Ok(Some(entry.path()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Downloads and extracts each zip file into `staging`.
+fn download(urls: &[&str], staging: &Path) -> Result<()> {
+    for (index, url) in urls.iter().enumerate() {
+        let archive = staging.join(format!("instantclient-{}.zip", index));
+        let archive = archive.to_string_lossy();
+        let staging = staging.to_string_lossy();
+
+        let mut command = Command::new("curl");
+        command.args(["-fsSL", "-o"]);
+        command.arg(&*archive);
+        command.arg(*url);
+        command.downloads();
+        command.wait()?;
+
+        let mut command = Command::new("unzip");
+        command.args(["-q", "-o"]);
+        command.arg(&*archive);
+        command.arg("-d");
+        command.arg(&*staging);
+        command.wait()?;
+    }
+
+    Ok(())
+}
+
+/// Older Instant Client releases only ship a versioned `libclntsh.so.X.Y`, but the linker
+/// needs the unversioned name.
+fn link_client_library(dir: &Path) -> Result<()> {
+    let unversioned = dir.join(CLIENT_LIBRARY);
+    let linked = command::skip_step(
+        format_args!(
+            "link {} to the versioned library, if it is missing",
+            unversioned.display()
+        ),
+        format_args!(
+            r#"[ -e {link} ] || ln -s "$(ls {dir}/{library}.* | head -n 1)" {link}"#,
+            link = command::quote_path(&unversioned),
+            dir = command::quote_path(dir),
+            library = CLIENT_LIBRARY,
+        ),
+    );
+    if linked || unversioned.exists() {
+        return Ok(());
+    }
+
+    let versioned = find_entry(dir, &format!("{}.", CLIENT_LIBRARY))?;
+    if let Some(versioned) = versioned {
+        symlink(&versioned, &unversioned).context(Io { path: &unversioned })?;
+    }
+
+    Ok(())
+}
+
+/// Ensures that the Instant Client is present in [`INSTANT_CLIENT_DIR`], downloading it
+/// from the URLs in [`INSTANT_CLIENT_URLS_ENV`] if it hasn't already been provided.
+pub fn install_instant_client() -> Result<()> {
+    let dir = Path::new(INSTANT_CLIENT_DIR);
+    if find_entry(dir, CLIENT_LIBRARY)?.is_some() {
+        return link_client_library(dir);
+    }
+
+    let urls = env::var(INSTANT_CLIENT_URLS_ENV).unwrap_or_default();
+    let urls = parse_urls(&urls);
+    if urls.is_empty() {
+        return Err(InstantClientError::Missing);
+    }
+
+    // Stage next to the final location so that the rename below doesn't cross devices.
+    let parent = dir.parent().unwrap_or(dir);
+    let staging = parent.join(".f1-instantclient");
+
+    // When commands are only printed, nothing is downloaded, so the extracted directory
+    // is named with a glob that the shell would expand.
+    if command::is_dry_run() {
+        command::skip_step(
+            format_args!("create {}", staging.display()),
+            format_args!("mkdir -p {}", command::quote_path(&staging)),
+        );
+        download(&urls, &staging)?;
+        command::skip_step(
+            format_args!(
+                "replace {} with the extracted instantclient_* directory",
+                dir.display()
+            ),
+            format_args!(
+                "rm -rf {dir} && mv {staging}/instantclient_* {dir} && rm -rf {staging}",
+                dir = command::quote_path(dir),
+                staging = command::quote_path(&staging),
+            ),
+        );
+        return link_client_library(dir);
+    }
+
+    fs::create_dir_all(&staging).context(Io { path: &staging })?;
+
+    let result = download(&urls, &staging).and_then(|()| {
+        let extracted = find_entry(&staging, "instantclient_")?.ok_or_else(|| {
+            InstantClientError::MissingDirectory {
+                dir: staging.clone(),
+            }
+        })?;
+
+        if dir.exists() {
+            fs::remove_dir_all(dir).context(Io { path: dir })?;
+        }
+        fs::rename(&extracted, dir).context(Io { path: dir })
+    });
+
+    let _ = fs::remove_dir_all(&staging);
+    result?;
+
+    link_client_library(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_urls() {
+        assert_eq!(
+            parse_urls("https://example.com/basic.zip, https://example.com/sdk.zip,"),
+            vec![
+                "https://example.com/basic.zip",
+                "https://example.com/sdk.zip"
+            ]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! How many compile jobs to run at once (`make -j`), from the CPUs and memory available.
+
+use log::info;
+use num_cpus;
+use std::{
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The memory limit of a cgroup v2 container (`max` if there is none).
+const CGROUP_V2_LIMIT: &str = "/sys/fs/cgroup/memory.max";
+
+/// The memory limit of a cgroup v1 container.
+const CGROUP_V1_LIMIT: &str = "/sys/fs/cgroup/memory/memory.limit_in_bytes";
+
+/// cgroup v1 reports "no limit" as a very large number (just under 2^63) rather than
+/// `max`; anything over this is treated as unlimited.
+const UNLIMITED: u64 = 1 << 60;
+
+/// The number of jobs set with [`set_jobs`], or 0 to use one per CPU.
+static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the number of compile jobs to run at once.
+pub fn set_jobs(jobs: usize) {
+    JOBS.store(jobs, Ordering::Relaxed);
+}
+
+/// Returns the number of compile jobs to run at once: the number set with [`set_jobs`],
+/// or one per CPU.
+pub fn jobs() -> usize {
+    match JOBS.load(Ordering::Relaxed) {
+        0 => num_cpus::get(),
+        jobs => jobs,
+    }
+}
+
+/// Parses a number of jobs, which must be at least one.
+pub fn parse_jobs(input: &str) -> Result<usize, String> {
+    match input.parse() {
+        Ok(0) | Err(_) => Err(format!("{:?} isn't a number of jobs (1 or more)", input)),
+        Ok(jobs) => Ok(jobs),
+    }
+}
+
+/// Parses a cgroup memory limit file's contents, returning `None` if there is no limit.
+fn parse_cgroup_limit(contents: &str) -> Option<u64> {
+    let limit: u64 = contents.trim().parse().ok()?;
+    if limit >= UNLIMITED {
+        return None;
+    }
+
+    Some(limit)
+}
+
+/// Parses `MemAvailable` (in bytes) out of `/proc/meminfo`.
+fn parse_meminfo(contents: &str) -> Option<u64> {
+    let line = contents
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some(kib * 1024)
+}
+
+/// Returns the memory available to this process, in bytes: the lower of the container's
+/// memory limit and the system's available memory, whichever are known.
+pub fn available_memory() -> Option<u64> {
+    let read = |path| fs::read_to_string(path).ok();
+
+    let limit = read(CGROUP_V2_LIMIT)
+        .or_else(|| read(CGROUP_V1_LIMIT))
+        .and_then(|contents| parse_cgroup_limit(&contents));
+    let available = read("/proc/meminfo").and_then(|contents| parse_meminfo(&contents));
+
+    match (limit, available) {
+        (Some(limit), Some(available)) => Some(limit.min(available)),
+        (limit, available) => limit.or(available),
+    }
+}
+
+/// Determines how many jobs `cpus` CPUs and `memory` bytes of memory can run, if each job
+/// needs `job_memory` bytes. This is always at least one.
+fn jobs_for(cpus: usize, memory: Option<u64>, job_memory: u64) -> usize {
+    let by_memory = match (memory, job_memory) {
+        (Some(memory), job_memory) if job_memory > 0 => (memory / job_memory) as usize,
+        _ => cpus,
+    };
+
+    cpus.min(by_memory).max(1)
+}
+
+/// Determines how many compile jobs this system can run at once, if each job needs
+/// `job_memory` bytes (0 to only consider the CPUs).
+pub fn plan_jobs(job_memory: u64) -> usize {
+    let cpus = num_cpus::get();
+    let memory = available_memory();
+    let jobs = jobs_for(cpus, memory, job_memory);
+    if let (true, Some(memory)) = (jobs < cpus, memory) {
+        info!(
+            "Running {} compile job(s) instead of {} (one per CPU), since only {} MiB of \
+             memory is available",
+            jobs,
+            cpus,
+            memory >> 20
+        );
+    }
+
+    jobs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        assert_eq!(parse_cgroup_limit("2147483648\n"), Some(2 << 30));
+        assert_eq!(parse_cgroup_limit("max\n"), None);
+        assert_eq!(parse_cgroup_limit("9223372036854771712\n"), None);
+
+        let meminfo = "MemTotal:       16314172 kB\n\
+                       MemFree:         1201640 kB\n\
+                       MemAvailable:    8388608 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(8 << 30));
+    }
+
+    #[test]
+    fn test_jobs_for() {
+        let gib = 1 << 30;
+
+        assert_eq!(jobs_for(8, Some(2 * gib), gib), 2);
+        assert_eq!(jobs_for(2, Some(64 * gib), gib), 2);
+        assert_eq!(jobs_for(8, Some(gib / 2), gib), 1);
+        assert_eq!(jobs_for(8, None, gib), 8);
+        assert_eq!(jobs_for(8, Some(gib), 0), 8);
+    }
+
+    #[test]
+    fn test_parse_jobs() {
+        assert_eq!(parse_jobs("4"), Ok(4));
+        assert!(parse_jobs("0").is_err());
+        assert!(parse_jobs("all").is_err());
+    }
+}
@@ -1,32 +1,83 @@
 //! System interaction helpers.
 
-use lazy_static::lazy_static;
-use num_cpus;
-use std::env;
+use snafu::Snafu;
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
 
 mod alpine;
 pub mod command;
+pub mod disk;
+mod hints;
+pub mod imagick;
+pub mod ini;
+pub mod instantclient;
+pub mod jobs;
+mod platform;
+pub mod source;
+pub mod staging;
+pub mod vendor;
 
-use super::extension::{Extension, Pecl};
-use command::Command;
+use super::extension::{Extension, Pecl, Version};
+use command::{Command, CommandError};
 
-pub use alpine::Apk;
+pub use alpine::{Apk, PreflightRepositories};
+pub use platform::{Platform, DOCKER_HOST};
 
-lazy_static! {
-    static ref NUM_CPUS: String = format!("{}", num_cpus::get());
+/// The release states `pecl list` prints in its last column.
+const PECL_STATES: &[&str] = &["stable", "beta", "alpha", "devel", "snapshot"];
+
+/// PECL's REST interface, which describes each package and its releases.
+const PECL_REST: &str = "https://pecl.php.net/rest/r";
+
+/// The build tools the official Alpine php images list in `$PHPIZE_DEPS`, suggested when
+/// it isn't set.
+pub const DEFAULT_PHPIZE_DEPS: &str =
+    "autoconf dpkg-dev dpkg file g++ gcc libc-dev make pkgconf re2c";
+
+/// The build tools given with [`set_phpize_deps`], used instead of `$PHPIZE_DEPS`.
+static PHPIZE_DEPS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Uses `packages` (separated by whitespace) as the C compiler and other build tools,
+/// instead of the ones named by `$PHPIZE_DEPS`.
+pub fn set_phpize_deps(packages: &str) {
+    let packages = packages
+        .split_ascii_whitespace()
+        .map(String::from)
+        .collect();
+    let _ = PHPIZE_DEPS.set(packages);
+}
+
+/// Returns the packages providing the C compiler and other build tools: those given with
+/// [`set_phpize_deps`], or else those named by `$PHPIZE_DEPS`. Returns `None` if there
+/// are none, e.g., because the image isn't based on an official php image.
+pub fn phpize_deps() -> Option<Vec<String>> {
+    let packages = match PHPIZE_DEPS.get() {
+        Some(packages) => packages.clone(),
+        None => env::var("PHPIZE_DEPS")
+            .unwrap_or_default()
+            .split_ascii_whitespace()
+            .map(String::from)
+            .collect(),
+    };
+
+    if packages.is_empty() {
+        return None;
+    }
+
+    Some(packages)
 }
 
 /// Collect the system packages needed the provided lest of dependencies.
 ///
-/// This function also collects the values in `$PHPIZE_DEPS`, which names the system
-/// C compiler and other utilities needed to build extensions.
+/// This function also collects the build tools from [`phpize_deps`], which names the
+/// system C compiler and other utilities needed to build extensions.
 pub fn collect_packages(dependencies: &[Extension]) -> Vec<String> {
-    let mut all_packages = Vec::new();
-
-    let phpize_deps = env::var("PHPIZE_DEPS").unwrap_or_default();
-    let phpize_deps = phpize_deps.split_ascii_whitespace().map(String::from);
-
-    all_packages.extend(phpize_deps);
+    let mut all_packages = phpize_deps().unwrap_or_default();
 
     for dependency in dependencies {
         if let Some(packages) = dependency.packages() {
@@ -37,6 +88,144 @@ pub fn collect_packages(dependencies: &[Extension]) -> Vec<String> {
     all_packages
 }
 
+/// Collect the extra package repositories needed by the provided list of dependencies,
+/// without duplicates.
+pub fn collect_repositories(dependencies: &[Extension]) -> Vec<String> {
+    let mut all_repositories: Vec<String> = Vec::new();
+
+    for repository in dependencies
+        .iter()
+        .filter_map(Extension::repositories)
+        .flatten()
+    {
+        if !all_repositories.contains(repository) {
+            all_repositories.push(repository.clone());
+        }
+    }
+
+    all_repositories
+}
+
+/// Collect the runtime packages declared by the provided list of dependencies.
+///
+/// Returns `None` if any dependency that needs external packages doesn't declare its
+/// runtime packages, in which case they have to be discovered by scanning binaries.
+pub fn collect_runtime_packages(dependencies: &[Extension]) -> Option<Vec<String>> {
+    let mut all_packages = Vec::new();
+
+    for dependency in dependencies.iter().filter(|dep| dep.has_packages()) {
+        let packages = dependency.runtime_packages()?;
+        all_packages.extend(packages.iter().map(String::from));
+    }
+
+    Some(all_packages)
+}
+
+/// Parses the output of `php -m` into lowercase extension names.
+fn parse_modules(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('['))
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+/// Lists the extensions already loaded by PHP (as reported by `php -m`), in lowercase.
+pub fn loaded_extensions() -> command::Result<Vec<String>> {
+    let mut command = Command::new("php");
+    command.arg("-m");
+
+    let output = command.stdout()?;
+    Ok(parse_modules(&output))
+}
+
+/// Determines if `module` is among the `loaded` extensions (as listed by
+/// [`loaded_extensions`]), whether compiled into PHP or loaded as a shared module.
+pub fn is_loaded(loaded: &[String], module: &str) -> bool {
+    let name = registered_name(module).to_ascii_lowercase();
+    loaded.contains(&name)
+}
+
+/// Parses `<module> <version>` lines into a map of versions by module.
+fn parse_versions(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(module, version)| (String::from(module), String::from(version.trim())))
+        .collect()
+}
+
+/// Returns the versions PHP reports (with `phpversion()`) for whichever of `modules` it
+/// loads, keyed by module.
+pub fn loaded_versions(modules: &[&str]) -> command::Result<HashMap<String, String>> {
+    let mut command = Command::new("php");
+    command.arg("-r");
+    command.arg(
+        r#"foreach (array_slice($argv, 1) as $m) { $v = phpversion($m); if ($v !== false) echo "$m $v\n"; }"#,
+    );
+    command.args(modules);
+
+    let output = command.stdout()?;
+    Ok(parse_versions(&output))
+}
+
+/// Returns the name PHP registers the module `module` under, for `extension_loaded()`.
+fn registered_name(module: &str) -> &str {
+    match module {
+        "opcache" => "Zend OPcache",
+        module => module,
+    }
+}
+
+/// Checks that PHP's CLI loads the shared `module`, by running
+/// `php -r 'exit(extension_loaded("<name>") ? 0 : 1);'`. PHP's output (e.g., a warning that
+/// a library couldn't be loaded) is shown if it doesn't.
+///
+/// `extra_dirs` are scanned for ini files after `conf.d`, as a SAPI configured to load
+/// them would. If `directive` is given (`extension` or `zend_extension`), the module is
+/// loaded with it, for modules that aren't enabled.
+pub fn check_loaded(
+    module: &str,
+    extra_dirs: &[PathBuf],
+    directive: Option<&str>,
+) -> command::Result<()> {
+    let ini_dir = ini::ini_dir();
+    let mut command = Command::new("php");
+    // The CLI only reads the configuration from its usual places.
+    if ini::ini_dir_overridden() {
+        command.arg("-c");
+        command.arg(ini_dir.to_string_lossy());
+    }
+
+    // A leading separator in PHP_INI_SCAN_DIR keeps PHP's own scan directory.
+    let mut scan_dirs = Vec::new();
+    if ini::ini_dir_overridden() || ini::scan_dir_overridden() {
+        scan_dirs.push(ini::scan_dir().to_string_lossy().into_owned());
+    } else if !extra_dirs.is_empty() {
+        scan_dirs.push(String::new());
+    }
+    scan_dirs.extend(
+        extra_dirs
+            .iter()
+            .map(|dir| dir.to_string_lossy().into_owned()),
+    );
+    if !scan_dirs.is_empty() {
+        command.env("PHP_INI_SCAN_DIR", scan_dirs.join(":"));
+    }
+
+    if let Some(directive) = directive {
+        command.arg("-d");
+        command.arg(format!("{}={}", directive, module));
+    }
+    command.arg("-r");
+    command.arg(format!(
+        r#"exit(extension_loaded("{}") ? 0 : 1);"#,
+        registered_name(module)
+    ));
+    command.wait()
+}
+
 /// Invokes `docker-php-ext-configure` for the given builtin name and configure arguments.
 pub fn configure_builtin<I, S>(name: &str, configure_args: I) -> command::Result<()>
 where
@@ -66,28 +255,520 @@ where
 
     let mut command = Command::new("docker-php-ext-install");
     command.arg("-j");
-    command.arg(&*NUM_CPUS);
+    command.arg(jobs::jobs().to_string());
     command.arg(builtin);
     command.args(builtins);
 
     command.wait()
 }
 
-/// Installs the given PECL extension, and enables it if specified.
-pub fn install_pecl_extension(pecl: &Pecl) -> command::Result<()> {
-    let name = pecl.name();
-    let enabled = pecl.is_enabled();
+/// Installs the given PECL extension from `target` (see
+/// [`source::download_pecl_source`]). The extension is not enabled; see
+/// [`enable_extension`].
+pub fn install_pecl_extension(pecl: &Pecl, target: &str) -> command::Result<()> {
+    pecl_install_command(pecl, target).wait()
+}
 
+/// Builds the `pecl install` command for [`install_pecl_extension`].
+fn pecl_install_command<'a>(pecl: &Pecl, target: &'a str) -> Command<'a> {
     let mut command = Command::new("pecl");
     command.arg("install");
-    command.arg(pecl.specifier());
-    command.wait()?;
+    set_build_env(&mut command, pecl);
+    if let Some(options) = pecl.configure_options_arg() {
+        command.arg("--configureoptions");
+        command.arg(options);
+    }
+    command.arg(target);
+
+    command
+}
+
+/// Builds and installs an extension from the source extracted to `dir` (see
+/// [`source::extract_source`]) with `phpize`, `./configure`, and `make install`, for
+/// extensions that aren't published on PECL. The extension is not enabled; see
+/// [`enable_extension`].
+pub fn build_extension(pecl: &Pecl, dir: &Path) -> command::Result<()> {
+    let mut phpize = Command::new("phpize");
+    phpize.current_dir(dir);
+    phpize.wait()?;
 
-    if enabled {
-        let mut command = Command::new("docker-php-ext-enable");
-        command.arg(name);
-        command.wait()?;
+    let mut configure = Command::new("./configure");
+    configure.current_dir(dir);
+    set_build_env(&mut configure, pecl);
+    configure.args(configure_args(pecl));
+    configure.wait()?;
+
+    for target in [None, Some("install")] {
+        let mut make = Command::new("make");
+        make.current_dir(dir);
+        set_build_env(&mut make, pecl);
+        make.args(target);
+        make.wait()?;
     }
 
     Ok(())
 }
+
+/// Formats this extension's configure answers as `./configure` flags (`--name=value`, or
+/// `--name` for an answer without a value).
+fn configure_args(pecl: &Pecl) -> Vec<String> {
+    let options = pecl.configure_options().map_or(&[][..], Vec::as_slice);
+    options
+        .iter()
+        .map(|option| match option.split_once('=') {
+            Some((name, value)) => format!("--{}={}", name.trim(), value.trim()),
+            None => format!("--{}", option.trim()),
+        })
+        .collect()
+}
+
+/// Sets the environment for a command that compiles `pecl`.
+///
+/// These are the only commands given `MAKEFLAGS`: pecl runs a plain `make`, which is
+/// painfully slow for large extensions (e.g., mongodb or grpc), so it builds in parallel
+/// unless the user has opinions of their own. Nothing else that runs (apk, curl, etc.)
+/// is affected.
+fn set_build_env(command: &mut Command<'_>, pecl: &Pecl) {
+    if env::var_os("MAKEFLAGS").is_none() {
+        command.env("MAKEFLAGS", format!("-j{}", jobs::jobs()));
+    }
+    if let Some(cflags) = pecl.cflags() {
+        // Keep any flags the user set; theirs come last so that they can override ours.
+        for variable in ["CFLAGS", "CXXFLAGS"] {
+            let flags = match env::var(variable) {
+                Ok(existing) if !existing.is_empty() => format!("{} {}", cflags, existing),
+                _ => String::from(cflags),
+            };
+            command.env(variable, flags);
+        }
+    }
+}
+
+/// The outcome of an extension's test suite.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestSummary {
+    /// The number of tests that passed.
+    pub passed: usize,
+    /// The number of tests that were skipped (e.g., for lacking a server to talk to).
+    pub skipped: usize,
+    /// The number of tests that failed.
+    pub failed: usize,
+    /// The tests that failed, as listed after the totals.
+    pub failures: Vec<String>,
+}
+
+/// Parses the totals `pecl run-tests` prints (e.g., `12 PASSED TESTS`).
+fn parse_test_summary(output: &str) -> TestSummary {
+    let mut summary = TestSummary::default();
+
+    let mut lines = output.lines();
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        let count = match words.next().and_then(|count| count.parse().ok()) {
+            Some(count) => count,
+            None => continue,
+        };
+        match (words.next(), words.next()) {
+            (Some("PASSED"), Some("TESTS")) => summary.passed = count,
+            (Some("SKIPPED"), Some("TESTS")) => summary.skipped = count,
+            (Some("FAILED"), Some(tests)) if tests.starts_with("TESTS") => {
+                summary.failed = count;
+                summary.failures = lines
+                    .by_ref()
+                    .take(count)
+                    .map(|test| String::from(test.trim()))
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+
+    summary
+}
+
+/// Runs the test suite that a PECL extension installed (with `pecl run-tests`), killing it
+/// if it runs longer than `limit`.
+pub fn run_pecl_tests(pecl: &Pecl, limit: Duration) -> command::Result<TestSummary> {
+    let mut command = Command::new("pecl");
+    command.args(["run-tests", "-p", pecl.name()]);
+    command.timeout(limit);
+    // run-tests writes its log to the working directory.
+    command.current_dir(env::temp_dir());
+
+    Ok(parse_test_summary(&command.output()?))
+}
+
+/// Returns the URL describing the release of `pecl` requested: the specific version, or
+/// for channels like `stable`, the list of all of the package's releases.
+fn pecl_release_url(pecl: &Pecl) -> String {
+    let name = pecl.name().to_ascii_lowercase();
+    match pecl.version() {
+        Version::Custom(version) => format!("{}/{}/{}.xml", PECL_REST, name, version),
+        _ => format!("{}/{}/allreleases.xml", PECL_REST, name),
+    }
+}
+
+/// Errors encountered while asking PECL about a release.
+#[derive(Debug, Snafu)]
+pub enum ReleaseCheckError {
+    /// PECL couldn't be reached.
+    #[snafu(display("{}", source))]
+    CommandFailed {
+        /// The underlying command error
+        source: CommandError,
+    },
+
+    /// PECL answered with something other than the release or a 404 (e.g., a server error
+    /// or a rate limit), so whether the release exists isn't known.
+    #[snafu(display("{} responded with HTTP status {}", url, status))]
+    UnexpectedStatus {
+        /// The URL asked
+        url: String,
+        /// The HTTP status code returned
+        status: String,
+    },
+}
+
+impl From<CommandError> for ReleaseCheckError {
+    fn from(source: CommandError) -> Self {
+        Self::CommandFailed { source }
+    }
+}
+
+/// Interprets the HTTP `status` of a PECL REST request: `Some(true)` if the resource
+/// exists, `Some(false)` if PECL says it doesn't, and `None` for any other answer.
+fn parse_release_status(status: &str) -> Option<bool> {
+    match status.trim() {
+        "200" => Some(true),
+        "404" => Some(false),
+        _ => None,
+    }
+}
+
+/// Checks PECL's REST interface for the release requested of `pecl` (or, for channels,
+/// for the package). Returns `Ok(false)` if PECL doesn't have it, and an error if PECL
+/// couldn't be asked or didn't give a clear answer.
+pub fn pecl_release_exists(pecl: &Pecl) -> Result<bool, ReleaseCheckError> {
+    let url = pecl_release_url(pecl);
+    let mut command = Command::new("curl");
+    command.args(["-sS", "-o", "/dev/null", "-w", "%{http_code}"]);
+    command.arg(&url);
+
+    let status = command.stdout()?;
+    parse_release_status(&status).ok_or_else(|| ReleaseCheckError::UnexpectedStatus {
+        url,
+        status: String::from(status.trim()),
+    })
+}
+
+/// Determines if `program` can be found in `$PATH`.
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Parses the effective user ID out of `/proc/<pid>/status` (the second field of its
+/// `Uid:` line).
+fn parse_effective_uid(status: &str) -> Option<u32> {
+    let line = status.lines().find(|line| line.starts_with("Uid:"))?;
+    line.split_whitespace().nth(2)?.parse().ok()
+}
+
+/// Lists every prerequisite for an installation that isn't met, for reporting all at once:
+/// running as root (which `apk` needs), each of `programs` being in `$PATH`, and `php`
+/// being runnable.
+pub fn missing_prerequisites(programs: &[&str]) -> Vec<String> {
+    let mut missing = Vec::new();
+
+    let uid = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| parse_effective_uid(&status));
+    if let Some(uid) = uid.filter(|&uid| uid != 0) {
+        missing.push(format!(
+            "this isn't running as root (its user ID is {}), which installing packages \
+             needs; switch to root (e.g., USER root) before installing",
+            uid
+        ));
+    }
+
+    for program in programs.iter().filter(|program| !on_path(program)) {
+        let mut problem = format!("{} isn't in $PATH", program);
+        if let Some(hint) = hints::diagnose_missing_program(program) {
+            problem.push_str(&format!("; {}", hint));
+        }
+        missing.push(problem);
+    }
+
+    if on_path("php") {
+        let mut command = Command::new("php");
+        command.arg("-v");
+        if let Err(error) = command.output() {
+            missing.push(format!("php can't be run: {}", error));
+        }
+    }
+
+    missing
+}
+
+/// Determines if this image has the `docker-php-ext-*` scripts of the official images.
+pub fn has_docker_php_ext_enable() -> bool {
+    on_path("docker-php-ext-enable")
+}
+
+/// Removes a PECL extension from PECL's records (and the shared module with it), if
+/// `pecl` is available and installed the extension. Returns whether it did.
+pub fn uninstall_pecl_extension(pecl: &Pecl) -> command::Result<bool> {
+    if !on_path("pecl") {
+        return Ok(false);
+    }
+
+    if !pecl_versions()?.contains_key(&pecl.name().to_ascii_lowercase()) {
+        return Ok(false);
+    }
+
+    let mut command = Command::new("pecl");
+    command.args(["uninstall", pecl.name()]);
+    command.wait()?;
+    Ok(true)
+}
+
+/// Parses the table printed by `pecl list` into a map of (lowercased) package names to
+/// their installed versions.
+fn parse_pecl_list(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let package = columns.next()?;
+            let version = columns.next()?;
+            let state = columns.next()?;
+            if columns.next().is_some() || !PECL_STATES.contains(&state) {
+                return None;
+            }
+
+            Some((package.to_ascii_lowercase(), String::from(version)))
+        })
+        .collect()
+}
+
+/// Returns the versions of the installed PECL packages, keyed by lowercased name.
+pub fn pecl_versions() -> command::Result<HashMap<String, String>> {
+    let mut command = Command::new("pecl");
+    command.arg("list");
+    Ok(parse_pecl_list(&command.stdout()?))
+}
+
+/// Enables the shared `module` with `docker-php-ext-enable`, writing the file named
+/// `ini_name` if given.
+pub fn enable_extension(module: &str, ini_name: Option<&str>) -> command::Result<()> {
+    let mut command = Command::new("docker-php-ext-enable");
+    if let Some(ini_name) = ini_name {
+        command.arg("--ini-name");
+        command.arg(ini_name);
+    }
+    command.arg(module);
+    command.wait()
+}
+
+/// Finds the directory PHP loads shared extensions from.
+pub fn extension_dir() -> command::Result<PathBuf> {
+    let mut command = Command::new("php-config");
+    command.arg("--extension-dir");
+
+    let output = command.stdout()?;
+    Ok(PathBuf::from(output.trim()))
+}
+
+/// Returns the directory that `make install` copies PHP's and extensions' headers into.
+pub fn include_dir() -> command::Result<PathBuf> {
+    let mut command = Command::new("php-config");
+    command.arg("--include-dir");
+
+    let output = command.stdout()?;
+    Ok(PathBuf::from(output.trim()))
+}
+
+/// Removes a file or directory that installing an extension left behind (e.g., its shared
+/// module or headers), if it exists. Returns whether there was anything to remove.
+pub fn remove_installed(path: &Path) -> io::Result<bool> {
+    if fs::symlink_metadata(path).is_err() {
+        return Ok(false);
+    }
+
+    let skipped = command::skip_step(
+        format_args!("remove {}", path.display()),
+        format_args!("rm -rf {}", command::quote_path(path)),
+    );
+    if skipped {
+        return Ok(true);
+    }
+
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pecl_list() {
+        let output = "Installed packages, channel pecl.php.net:\n\
+                      =========================================\n\
+                      Package  Version State\n\
+                      igbinary 3.2.14  stable\n\
+                      Xdebug   3.3.0alpha3 alpha\n";
+
+        let versions = parse_pecl_list(output);
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["igbinary"], "3.2.14");
+        assert_eq!(versions["xdebug"], "3.3.0alpha3");
+    }
+
+    #[test]
+    fn test_pecl_install_command() {
+        let pecl: Pecl = "mongodb".parse().unwrap();
+        let line = pecl_install_command(&pecl, "mongodb").script_line();
+        if env::var_os("MAKEFLAGS").is_none() {
+            assert!(line.starts_with(&format!("MAKEFLAGS=-j{} pecl install", jobs::jobs())));
+        }
+
+        let line = Command::new("apk").arg("info").script_line();
+        assert!(!line.contains("MAKEFLAGS"));
+    }
+
+    #[test]
+    fn test_remove_installed() {
+        let dir = env::temp_dir().join(format!("f1-ext-install-so-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let module = dir.join("redis.so");
+        fs::write(&module, "").unwrap();
+
+        command::set_mode(command::Mode::DryRun);
+        let dry_run = remove_installed(&module).unwrap();
+        command::set_mode(command::Mode::Run);
+        let kept = module.exists();
+
+        let removed = remove_installed(&module).unwrap();
+        let missing = remove_installed(&module).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(dry_run && kept, "a dry run shouldn't remove the module");
+        assert!(removed && !missing);
+    }
+
+    #[test]
+    fn test_parse_release_status() {
+        assert_eq!(parse_release_status("200"), Some(true));
+        assert_eq!(parse_release_status("404\n"), Some(false));
+        assert_eq!(parse_release_status("429"), None);
+        assert_eq!(parse_release_status("503"), None);
+    }
+
+    #[test]
+    fn test_configure_args() {
+        let mut pecl: Pecl = "tideways_xhprof".parse().unwrap();
+        pecl.set_configure_options(vec![
+            String::from("enable-tideways-xhprof"),
+            String::from("with-foo = /usr"),
+        ]);
+        assert_eq!(
+            configure_args(&pecl),
+            ["--enable-tideways-xhprof", "--with-foo=/usr"]
+        );
+    }
+
+    #[test]
+    fn test_parse_effective_uid() {
+        let status = "Name:\tf1-ext-install\nUid:\t1000\t0\t0\t0\nGid:\t0\t0\t0\t0\n";
+        assert_eq!(parse_effective_uid(status), Some(0));
+        assert_eq!(parse_effective_uid("Name:\tsh\n"), None);
+    }
+
+    #[test]
+    fn test_declared_runtime_packages() {
+        let extensions: Vec<Extension> = vec![
+            "builtin:zip".parse().unwrap(),
+            "pecl:memcached".parse().unwrap(),
+            "builtin:opcache".parse().unwrap(),
+        ];
+
+        let packages = collect_runtime_packages(&extensions).unwrap();
+        assert!(packages.contains(&String::from("libzip")));
+        assert!(packages.contains(&String::from("libmemcached-libs")));
+    }
+
+    #[test]
+    fn test_undeclared_runtime_packages() {
+        let extensions: Vec<Extension> = vec![
+            "builtin:zip".parse().unwrap(),
+            "pecl:imagick".parse().unwrap(),
+        ];
+
+        assert_eq!(collect_runtime_packages(&extensions), None);
+    }
+
+    #[test]
+    fn test_parse_test_summary() {
+        let output = "Running 3 tests\n\
+                      PASS Redis::ping [tests/ping.phpt]\n\
+                      TOTAL TIME: 00:02\n\
+                      1 PASSED TESTS\n\
+                      1 SKIPPED TESTS\n\
+                      1 FAILED TESTS:\n\
+                      tests/serializer.phpt\n";
+
+        assert_eq!(
+            parse_test_summary(output),
+            TestSummary {
+                passed: 1,
+                skipped: 1,
+                failed: 1,
+                failures: vec![String::from("tests/serializer.phpt")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_pecl_release_url() {
+        let redis: Pecl = "redis@6.0.2".parse().unwrap();
+        assert_eq!(
+            pecl_release_url(&redis),
+            "https://pecl.php.net/rest/r/redis/6.0.2.xml"
+        );
+
+        let http: Pecl = "pecl_http".parse().unwrap();
+        assert_eq!(
+            pecl_release_url(&http),
+            "https://pecl.php.net/rest/r/pecl_http/allreleases.xml"
+        );
+    }
+
+    #[test]
+    fn test_parse_versions() {
+        let versions = parse_versions("redis 6.0.2\nhttp 4.2.4\n\n");
+
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["redis"], "6.0.2");
+        assert_eq!(versions["http"], "4.2.4");
+    }
+
+    #[test]
+    fn test_parse_modules() {
+        let output = "[PHP Modules]\nCore\nsodium\nZend OPcache\n\n[Zend Modules]\nZend OPcache\n";
+
+        assert_eq!(
+            parse_modules(output),
+            vec!["core", "sodium", "zend opcache", "zend opcache"]
+        );
+
+        let loaded = parse_modules(output);
+        assert!(is_loaded(&loaded, "sodium"));
+        assert!(is_loaded(&loaded, "opcache"));
+        assert!(!is_loaded(&loaded, "pdo_mysql"));
+    }
+}
@@ -0,0 +1,232 @@
+//! Detection of the platform (PHP version, architecture, etc.) that extensions are being built for.
+
+use regex::Regex;
+use semver::Version;
+use std::{env, fs, net::ToSocketAddrs, sync::LazyLock};
+
+use super::command::Command;
+
+/// Path to the file identifying the Alpine release of this system.
+const ALPINE_RELEASE_PATH: &str = "/etc/alpine-release";
+
+/// Path to the kernel's IPv4 routing table.
+const ROUTE_PATH: &str = "/proc/net/route";
+
+/// The host that Docker Desktop (and Docker Engine with a `host-gateway` mapping) resolves
+/// to the machine running the container.
+pub const DOCKER_HOST: &str = "host.docker.internal";
+
+/// Parses a loosely-formatted version string (as found in `$PHP_VERSION` or
+/// `/etc/alpine-release`).
+///
+/// These versions are mostly semver-compatible, but pre-release builds use suffixes like
+/// `8.4.0RC1` or `3.19_alpha20230901` that semver rejects, so we only take the leading
+/// MAJOR.MINOR[.PATCH] numbers. A missing patch number is treated as zero.
+fn parse_version(input: &str) -> Option<Version> {
+    static VERSION: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^\s*(\d+)\.(\d+)(?:\.(\d+))?").unwrap());
+
+    let caps = VERSION.captures(input)?;
+    let major = caps[1].parse().ok()?;
+    let minor = caps[2].parse().ok()?;
+    let patch = match caps.get(3) {
+        Some(patch) => patch.as_str().parse().ok()?,
+        None => 0,
+    };
+
+    Some(Version::new(major, minor, patch))
+}
+
+/// Finds the gateway of the default route in the contents of `/proc/net/route`, where
+/// addresses are written as little-endian hex.
+fn parse_default_gateway(routes: &str) -> Option<String> {
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        let [a, b, c, d] = gateway.to_le_bytes();
+        Some(format!("{}.{}.{}.{}", a, b, c, d))
+    })
+}
+
+/// Returns the address of the Docker host if [`DOCKER_HOST`] doesn't resolve to it (as on
+/// Docker Engine without a `host-gateway` mapping): this container's default gateway, if
+/// it can be determined.
+fn detect_host_gateway() -> Option<String> {
+    let resolves = (DOCKER_HOST, 0)
+        .to_socket_addrs()
+        .is_ok_and(|mut addresses| addresses.next().is_some());
+    if resolves {
+        return None;
+    }
+
+    let routes = fs::read_to_string(ROUTE_PATH).ok()?;
+    parse_default_gateway(&routes)
+}
+
+/// Describes the system that extensions are being installed on.
+///
+/// Registry entries use this information to select between variants of their packages
+/// and configure flags.
+#[derive(Clone, Debug, Default)]
+pub struct Platform {
+    /// The version of PHP installed in this image, if it could be determined.
+    php_version: Option<Version>,
+
+    /// The Alpine release of this image, if this is an Alpine system.
+    alpine_version: Option<Version>,
+
+    /// The CPU architecture of this image (e.g., `x86_64` or `aarch64`).
+    arch: Option<String>,
+
+    /// The address of the Docker host, if [`DOCKER_HOST`] doesn't resolve to it.
+    host_gateway: Option<String>,
+}
+
+impl Platform {
+    /// Creates a platform description for the given PHP version.
+    pub fn new(php_version: Option<Version>) -> Self {
+        Platform {
+            php_version,
+            ..Platform::default()
+        }
+    }
+
+    /// Sets the Alpine release of this platform.
+    pub fn with_alpine_version(mut self, alpine_version: Option<Version>) -> Self {
+        self.alpine_version = alpine_version;
+        self
+    }
+
+    /// Sets the CPU architecture of this platform.
+    pub fn with_arch(mut self, arch: Option<&str>) -> Self {
+        self.arch = arch.map(String::from);
+        self
+    }
+
+    /// Sets the address of the Docker host, for when [`DOCKER_HOST`] doesn't resolve.
+    pub fn with_host_gateway(mut self, host_gateway: Option<&str>) -> Self {
+        self.host_gateway = host_gateway.map(String::from);
+        self
+    }
+
+    /// Detects the current platform.
+    ///
+    /// The PHP version is read from `$PHP_VERSION` (set by the official Docker images),
+    /// falling back to asking the `php` binary directly. The Alpine release is read from
+    /// `/etc/alpine-release`. The architecture is the one this binary was built for, which
+    /// matches the image since the binary is copied into (or built inside) it. If
+    /// `host.docker.internal` doesn't resolve, the Docker host is taken to be the default
+    /// gateway in `/proc/net/route`.
+    pub fn detect() -> Self {
+        let from_env = env::var("PHP_VERSION")
+            .ok()
+            .and_then(|version| parse_version(&version));
+
+        let php_version = from_env.or_else(|| {
+            let mut command = Command::new("php");
+            command.args(["-r", "echo PHP_VERSION;"]);
+
+            let output = command.stdout().ok()?;
+            parse_version(&output)
+        });
+
+        let alpine_version = fs::read_to_string(ALPINE_RELEASE_PATH)
+            .ok()
+            .and_then(|release| parse_version(&release));
+
+        Platform {
+            php_version,
+            alpine_version,
+            arch: Some(String::from(env::consts::ARCH)),
+            host_gateway: detect_host_gateway(),
+        }
+    }
+
+    /// Returns the detected PHP version, if any.
+    pub fn php_version(&self) -> Option<&Version> {
+        self.php_version.as_ref()
+    }
+
+    /// Returns the detected Alpine release, if any.
+    pub fn alpine_version(&self) -> Option<&Version> {
+        self.alpine_version.as_ref()
+    }
+
+    /// Returns the detected CPU architecture, if any.
+    pub fn arch(&self) -> Option<&str> {
+        self.arch.as_deref()
+    }
+
+    /// Returns the address that reaches the Docker host from this container:
+    /// `host.docker.internal`, or the default gateway if that name doesn't resolve.
+    pub fn docker_host(&self) -> &str {
+        self.host_gateway.as_deref().unwrap_or(DOCKER_HOST)
+    }
+
+    /// Replaces `{alpine}` in `template` with this platform's Alpine branch (e.g.,
+    /// `v3.18`), so that registry entries can name repositories of the running release.
+    ///
+    /// The template is returned unchanged if the Alpine release isn't known.
+    pub fn expand(&self, template: &str) -> String {
+        match &self.alpine_version {
+            Some(version) => {
+                let branch = format!("v{}.{}", version.major, version.minor);
+                template.replace("{alpine}", &branch)
+            }
+            None => String::from(template),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_release() {
+        assert_eq!(parse_version("7.4.33"), Some(Version::new(7, 4, 33)));
+    }
+
+    #[test]
+    fn test_expand() {
+        let platform = Platform::default().with_alpine_version(Some(Version::new(3, 18, 4)));
+        assert_eq!(
+            platform.expand("https://dl-cdn.alpinelinux.org/alpine/{alpine}/community"),
+            "https://dl-cdn.alpinelinux.org/alpine/v3.18/community"
+        );
+    }
+
+    #[test]
+    fn test_parse_default_gateway() {
+        let routes = "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                      eth0\t00000000\t010011AC\t0003\t0\t0\t0\t00000000\n\
+                      eth0\t000011AC\t00000000\t0001\t0\t0\t0\t0000FFFF\n";
+
+        assert_eq!(
+            parse_default_gateway(routes),
+            Some(String::from("172.17.0.1"))
+        );
+    }
+
+    #[test]
+    fn test_parse_prerelease() {
+        assert_eq!(parse_version("8.4.0RC1"), Some(Version::new(8, 4, 0)));
+    }
+
+    #[test]
+    fn test_parse_alpine_edge() {
+        assert_eq!(
+            parse_version("3.19_alpha20230901\n"),
+            Some(Version::new(3, 19, 0))
+        );
+    }
+
+    #[test]
+    fn test_parse_garbage() {
+        assert_eq!(parse_version("not a version"), None);
+    }
+}
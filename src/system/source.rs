@@ -0,0 +1,692 @@
+//! Helpers for preparing extension source code before it is built.
+//!
+//! Most extensions are built straight from the PHP source tree or a PECL download, but
+//! some need small patches first (e.g., to build against musl or a newer compiler).
+//!
+//! PECL archives are also checked against the SHA-256 digests pinned in the registry
+//! before they are built, since PECL itself doesn't publish any. Archives without a pinned
+//! digest are refused with `--require-checksums`, and otherwise built with a warning that
+//! names their digest. Likewise, PECL doesn't
+//! sign its releases, so environments that need signatures can supply their own minisign
+//! signatures of the archives along with a keyring to check them against. (Builtins come
+//! from the PHP source tarball in the image, which the official images already verify.)
+//! Extensions that aren't published on PECL are downloaded from the registry's
+//! `source_url` instead, and checked the same way.
+
+use log::{info, warn};
+use snafu::{ResultExt, Snafu};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use super::command::{self, Command, CommandError};
+use crate::{
+    extension::{Pecl, Version},
+    signature::{self, SignatureError},
+};
+
+/// Directory that `docker-php-source extract` unpacks the PHP source tree into.
+const PHP_SOURCE_DIR: &str = "/usr/src/php";
+
+/// Directory that `pecl install` downloads and builds extensions in.
+const PECL_TEMP_DIR: &str = "/tmp/pear";
+
+/// Whether every PECL archive must have a pinned digest (see [`set_require_checksums`]).
+static REQUIRE_CHECKSUMS: AtomicBool = AtomicBool::new(false);
+
+/// The keys and signatures PECL archives are verified with, if signatures are required.
+static SIGNATURES: OnceLock<Signatures> = OnceLock::new();
+
+/// Where to find the signatures of PECL archives, and whose signatures to trust.
+#[derive(Debug)]
+struct Signatures {
+    /// The trusted minisign public keys.
+    keys: Vec<String>,
+    /// The directory holding each archive's signature, named `<archive>.minisig`.
+    dir: PathBuf,
+}
+
+/// Requires every PECL archive to carry a minisign signature, read from
+/// `<dir>/<name>-<version>.tgz.minisig`, by one of the keys in `keyring` (see
+/// [`signature::parse_keyring`]).
+pub fn require_signatures(keyring: &Path, dir: &Path) -> Result<()> {
+    let contents = fs::read_to_string(keyring).context(Io { path: keyring })?;
+    let keys = signature::parse_keyring(&contents).context(Keyring { path: keyring })?;
+    let _ = SIGNATURES.set(Signatures {
+        keys,
+        dir: dir.to_path_buf(),
+    });
+
+    Ok(())
+}
+
+/// Sets whether PECL extensions without a pinned digest for the version being installed
+/// are refused, rather than installed unverified.
+pub fn set_require_checksums(require: bool) {
+    REQUIRE_CHECKSUMS.store(require, Ordering::Relaxed);
+}
+
+/// Errors encountered while preparing extension sources.
+#[derive(Debug, Snafu)]
+pub enum SourceError {
+    /// A command (e.g., `patch` or `tar`) failed.
+    #[snafu(display("{}", source))]
+    CommandFailed {
+        /// The underlying command error
+        source: CommandError,
+    },
+
+    /// A file or directory could not be accessed.
+    #[snafu(display("Failed to access {}: {}", path.display(), source))]
+    Io {
+        /// The underlying IO error
+        source: io::Error,
+        /// The path being accessed
+        path: PathBuf,
+    },
+
+    /// A PECL download didn't produce the expected archive or source directory.
+    #[snafu(display("Could not find the downloaded PECL source in {}", dir.display()))]
+    MissingSource {
+        /// The directory the source was downloaded into
+        dir: PathBuf,
+    },
+
+    /// A PECL archive's SHA-256 digest didn't match the one pinned in the registry.
+    #[snafu(display(
+        "The SHA-256 digest of {} is {}, but the registry expects {}",
+        archive.display(),
+        actual,
+        expected
+    ))]
+    ChecksumMismatch {
+        /// The downloaded archive
+        archive: PathBuf,
+        /// The digest pinned in the registry
+        expected: String,
+        /// The archive's digest
+        actual: String,
+    },
+
+    /// The keyring given for PECL signatures couldn't be used.
+    #[snafu(display("Invalid keyring {}: {}", path.display(), source))]
+    Keyring {
+        /// The underlying signature error
+        source: SignatureError,
+        /// The keyring file
+        path: PathBuf,
+    },
+
+    /// Signatures are required, but a PECL archive has none.
+    #[snafu(display(
+        "{} has no signature (expected {})",
+        archive.display(),
+        expected.display()
+    ))]
+    MissingSignature {
+        /// The downloaded archive
+        archive: PathBuf,
+        /// Where its signature should be
+        expected: PathBuf,
+    },
+
+    /// A PECL archive's signature isn't valid for any key in the keyring.
+    #[snafu(display("Failed to verify {}: {}", archive.display(), source))]
+    BadSignature {
+        /// The underlying signature error
+        source: SignatureError,
+        /// The downloaded archive
+        archive: PathBuf,
+    },
+
+    /// Checksums are required, but none is pinned for the version downloaded.
+    #[snafu(display(
+        "No SHA-256 digest is pinned for {}-{}; add it to the registry's checksums or \
+         drop --require-checksums",
+        name,
+        version
+    ))]
+    MissingChecksum {
+        /// The extension's name
+        name: String,
+        /// The version downloaded
+        version: String,
+    },
+
+    /// An extension built from a source URL was requested by channel (e.g., `@stable`)
+    /// rather than by version, which only PECL can resolve.
+    #[snafu(display(
+        "{} isn't published on PECL, so it needs a version (e.g., pecl:{}@<version>)",
+        name,
+        name
+    ))]
+    UnversionedSource {
+        /// The extension's name
+        name: String,
+    },
+}
+
+impl From<CommandError> for SourceError {
+    fn from(source: CommandError) -> Self {
+        Self::CommandFailed { source }
+    }
+}
+
+/// Helper type for the result of source preparation.
+pub type Result<T> = std::result::Result<T, SourceError>;
+
+/// Determines if a patch entry is an inline diff rather than a path to a patch file.
+fn is_inline_patch(patch: &str) -> bool {
+    patch.contains('\n')
+}
+
+/// Applies each patch to the source tree in `dir`, in order.
+///
+/// Patches are applied with `patch -p1`, so they should be relative to the root of the
+/// extension's source (as produced by `git diff` in the extension's repository). Each
+/// entry is either a path to a patch file or the text of a diff.
+pub fn apply_patches(dir: &Path, patches: &[String]) -> Result<()> {
+    for (index, patch) in patches.iter().enumerate() {
+        let patch_file = if is_inline_patch(patch) {
+            let path = dir.join(format!(".f1-patch-{}.diff", index));
+            if !command::skip_write(&path, patch) {
+                fs::write(&path, patch).context(Io { path: &path })?;
+            }
+            path
+        } else {
+            PathBuf::from(patch)
+        };
+
+        let mut command = Command::new("patch");
+        command.args(["-p1", "--forward", "-d"]);
+        command.arg(dir.to_string_lossy());
+        command.arg("-i");
+        command.arg(patch_file.to_string_lossy());
+        command.wait()?;
+    }
+
+    Ok(())
+}
+
+/// Returns the source directory of a builtin extension within the PHP source tree.
+pub fn builtin_source_dir(name: &str) -> PathBuf {
+    Path::new(PHP_SOURCE_DIR).join("ext").join(name)
+}
+
+/// Extracts the PHP source tree with `docker-php-source`, so that builtins can be patched
+/// before they are configured.
+pub fn extract_php_source() -> Result<()> {
+    let mut command = Command::new("docker-php-source");
+    command.arg("extract");
+    command.wait()?;
+
+    Ok(())
+}
+
+/// Removes the PHP source tree extracted by [`extract_php_source`].
+pub fn delete_php_source() -> Result<()> {
+    let mut command = Command::new("docker-php-source");
+    command.arg("delete");
+    command.wait()?;
+
+    Ok(())
+}
+
+/// Removes the downloads and build trees that `pecl install` leaves behind.
+pub fn delete_pecl_temp() -> Result<()> {
+    let dir = Path::new(PECL_TEMP_DIR);
+    let removed = command::skip_step(
+        format_args!("remove {}", dir.display()),
+        format_args!("rm -rf {}", command::quote_path(dir)),
+    );
+    if removed {
+        return Ok(());
+    }
+
+    match fs::remove_dir_all(dir) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            Err(error).context(Io { path: dir })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the directory that `pecl install` builds the extension `name` in.
+fn pecl_build_dir(name: &str) -> PathBuf {
+    Path::new(PECL_TEMP_DIR).join("temp").join(name)
+}
+
+/// Removes the build tree that an earlier `pecl install` of the extension `name` left
+/// behind (e.g., when its build failed), which would otherwise make this build fail with
+/// "directory already exists". Other extensions' build trees are left alone.
+pub fn delete_stale_pecl_build(name: &str) -> Result<()> {
+    let dir = pecl_build_dir(name);
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    info!(
+        "Removing {}, left behind by an earlier build of {}",
+        dir.display(),
+        name
+    );
+    let removed = command::skip_step(
+        format_args!("remove {}", dir.display()),
+        format_args!("rm -rf {}", command::quote_path(&dir)),
+    );
+    if removed {
+        return Ok(());
+    }
+
+    fs::remove_dir_all(&dir).context(Io { path: &dir })
+}
+
+/// The PECL install target for an extension, along with any temporary files that
+/// need to be cleaned up once it has been installed.
+#[derive(Debug)]
+pub struct PeclSource {
+    /// The argument to pass to `pecl install`.
+    target: String,
+    /// The temporary directory holding the downloaded (and perhaps patched) archive.
+    work_dir: PathBuf,
+}
+
+impl PeclSource {
+    /// Returns the argument to pass to `pecl install`.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+}
+
+impl Drop for PeclSource {
+    fn drop(&mut self) {
+        let work_dir = &self.work_dir;
+        let removed = command::skip_step(
+            format_args!("remove {}", work_dir.display()),
+            format_args!("rm -rf {}", command::quote_path(work_dir)),
+        );
+        if !removed {
+            let _ = fs::remove_dir_all(work_dir);
+        }
+    }
+}
+
+/// Finds the archive that `pecl download` saved into `dir`.
+fn find_archive(dir: &Path) -> Result<PathBuf> {
+    let entries = fs::read_dir(dir).context(Io { path: dir })?;
+
+    for entry in entries {
+        let path = entry.context(Io { path: dir })?.path();
+        if path.extension().is_some_and(|ext| ext == "tgz") {
+            return Ok(path);
+        }
+    }
+
+    Err(SourceError::MissingSource {
+        dir: dir.to_path_buf(),
+    })
+}
+
+/// Finds the directory extracted from a PECL archive (named `<name>-<version>`).
+fn find_extracted_source(dir: &Path, name: &str) -> Result<PathBuf> {
+    let prefix = format!("{}-", name);
+    let entries = fs::read_dir(dir).context(Io { path: dir })?;
+
+    for entry in entries {
+        let entry = entry.context(Io { path: dir })?;
+        let file_name = entry.file_name();
+        let is_source = file_name.to_string_lossy().starts_with(&prefix);
+        if is_source && entry.path().is_dir() {
+            return Ok(entry.path());
+        }
+    }
+
+    Err(SourceError::MissingSource {
+        dir: dir.to_path_buf(),
+    })
+}
+
+/// Determines the version of a PECL archive from its name (`<name>-<version>.tgz`).
+fn archive_version<'a>(archive: &'a Path, name: &str) -> Option<&'a str> {
+    archive
+        .file_name()?
+        .to_str()?
+        .strip_prefix(name)?
+        .strip_prefix('-')?
+        .strip_suffix(".tgz")
+}
+
+/// Parses the digest out of `sha256sum`'s output (`<digest>  <file>`).
+fn parse_sha256sum(output: &str) -> Option<&str> {
+    output
+        .split_whitespace()
+        .next()
+        .filter(|digest| digest.len() == 64)
+}
+
+/// Computes the SHA-256 digest (in hex) of `archive` with `sha256sum`.
+fn sha256_digest(archive: &Path) -> Result<String> {
+    let mut command = Command::new("sha256sum");
+    command.arg(archive.to_string_lossy());
+    let output = command.stdout()?;
+    Ok(String::from(parse_sha256sum(&output).unwrap_or_default()))
+}
+
+/// Checks a downloaded archive against the digest pinned for its version. With
+/// `--require-checksums`, an archive without one is refused; otherwise, it's built with a
+/// warning naming its digest, so that it can be pinned.
+fn verify_archive(pecl: &Pecl, archive: &Path) -> Result<()> {
+    // When commands are only printed, the archive's name isn't known, so only a version
+    // named in the spec can be checked.
+    let version = if command::is_dry_run() {
+        match pecl.version() {
+            Version::Custom(version) => Some(version.as_str()),
+            _ => None,
+        }
+    } else {
+        archive_version(archive, pecl.name())
+    };
+
+    let expected = match version.and_then(|version| pecl.checksum(version)) {
+        Some(expected) => expected,
+        None if command::is_dry_run() => return Ok(()),
+        None if REQUIRE_CHECKSUMS.load(Ordering::Relaxed) => {
+            return Err(SourceError::MissingChecksum {
+                name: String::from(pecl.name()),
+                version: String::from(version.unwrap_or("unknown")),
+            })
+        }
+        None => {
+            warn!(
+                "{} has no pinned SHA-256 digest, so it wasn't verified (its digest is {}; \
+                 pin it under checksums in an imported registry, or refuse unpinned \
+                 downloads with --require-checksums)",
+                archive.display(),
+                sha256_digest(archive)?
+            );
+            return Ok(());
+        }
+    };
+
+    let verified = command::skip_step(
+        format_args!("verify the SHA-256 digest of {}", archive.display()),
+        format_args!(
+            "printf '%s  %s\\n' {} {} | sha256sum -c -",
+            command::quote(expected),
+            command::quote_path(archive)
+        ),
+    );
+    if verified {
+        return Ok(());
+    }
+
+    let actual = sha256_digest(archive)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(SourceError::ChecksumMismatch {
+            archive: archive.to_path_buf(),
+            expected: String::from(expected),
+            actual,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks a downloaded archive's signature, if signatures are required.
+fn verify_signature(archive: &Path) -> Result<()> {
+    let signatures = match SIGNATURES.get() {
+        Some(signatures) => signatures,
+        None => return Ok(()),
+    };
+
+    let mut name = archive.file_name().unwrap_or_default().to_os_string();
+    name.push(".minisig");
+    let signature_path = signatures.dir.join(name);
+
+    let checks: Vec<_> = signatures
+        .keys
+        .iter()
+        .map(|key| {
+            format!(
+                "minisign -Vq -P {} -m {} -x {}",
+                command::quote(key),
+                command::quote_path(archive),
+                command::quote_path(&signature_path)
+            )
+        })
+        .collect();
+    let verified = command::skip_step(
+        format_args!("verify the signature of {}", archive.display()),
+        checks.join(" || "),
+    );
+    if verified {
+        return Ok(());
+    }
+
+    if !signature_path.exists() {
+        return Err(SourceError::MissingSignature {
+            archive: archive.to_path_buf(),
+            expected: signature_path,
+        });
+    }
+
+    let data = fs::read(archive).context(Io { path: archive })?;
+    let signature_text = fs::read_to_string(&signature_path).context(Io {
+        path: &signature_path,
+    })?;
+    signature::verify_any(&data, &signature_text, &signatures.keys)
+        .context(BadSignature { archive })
+}
+
+/// Downloads the source archive of an extension that isn't published on PECL from `url`
+/// into `work_dir`, named like a PECL archive (`<name>-<version>.tgz`) so that it is
+/// checked the same way.
+fn download_source_url(pecl: &Pecl, url: &str, work_dir: &Path) -> Result<PathBuf> {
+    let version = match pecl.version() {
+        Version::Custom(version) => version,
+        _ => {
+            return Err(SourceError::UnversionedSource {
+                name: String::from(pecl.name()),
+            })
+        }
+    };
+
+    let archive = work_dir.join(format!("{}-{}.tgz", pecl.name(), version));
+    let mut command = Command::new("curl");
+    command.args(["-fsSL", "-o"]);
+    command.arg(archive.to_string_lossy());
+    command.arg(url);
+    command.downloads();
+    command.wait()?;
+
+    Ok(archive)
+}
+
+/// Downloads the source that `pecl install` should build for this extension into a
+/// temporary directory with `pecl download` (or from its `source_url`, if it isn't
+/// published on PECL), and checks its digest and signature.
+///
+/// Downloading first means that `pecl install` only has to build the extension, so that
+/// only the download is retried on network failures. See [`patch_pecl_source`] for
+/// extensions with patches.
+pub fn download_pecl_source(pecl: &Pecl) -> Result<PeclSource> {
+    let work_dir = env::temp_dir().join(format!("f1-ext-install-{}", pecl.name()));
+    let recreated = command::skip_step(
+        format_args!("create an empty {}", work_dir.display()),
+        format_args!(
+            "rm -rf {dir} && mkdir -p {dir}",
+            dir = command::quote_path(&work_dir)
+        ),
+    );
+    if !recreated {
+        if work_dir.exists() {
+            fs::remove_dir_all(&work_dir).context(Io { path: &work_dir })?;
+        }
+        fs::create_dir_all(&work_dir).context(Io { path: &work_dir })?;
+    }
+
+    // Construct the result now so that the work directory is cleaned up on failure.
+    let mut source = PeclSource {
+        target: String::new(),
+        work_dir: work_dir.clone(),
+    };
+
+    let archive = match pecl.source_url() {
+        Some(url) => download_source_url(pecl, &url, &work_dir)?,
+        None => {
+            let mut command = Command::new("pecl");
+            command.arg("download");
+            command.arg(pecl.specifier());
+            command.downloads();
+            command.current_dir(&work_dir);
+            command.wait()?;
+
+            // When commands are only printed, nothing was downloaded, so the archive and
+            // source directory are named with globs that the shell would expand.
+            if command::is_dry_run() {
+                work_dir.join("*.tgz")
+            } else {
+                find_archive(&work_dir)?
+            }
+        }
+    };
+    verify_archive(pecl, &archive)?;
+    verify_signature(&archive)?;
+
+    source.target = archive.to_string_lossy().into_owned();
+    Ok(source)
+}
+
+/// Applies the extension's patches, if it has any, to the `source` downloaded by
+/// [`download_pecl_source`]: the archive is extracted, patched, and repacked in place.
+pub fn patch_pecl_source(pecl: &Pecl, source: &PeclSource) -> Result<()> {
+    let patches = match pecl.patches().filter(|patches| !patches.is_empty()) {
+        Some(patches) => patches,
+        None => return Ok(()),
+    };
+
+    let work_dir = &source.work_dir;
+    let archive = Path::new(&source.target);
+    let mut command = Command::new("tar");
+    command.arg("-xzf");
+    command.arg(archive.to_string_lossy());
+    command.current_dir(work_dir);
+    command.wait()?;
+
+    let source_dir = if command::is_dry_run() {
+        work_dir.join(format!("{}-*", pecl.name()))
+    } else {
+        find_extracted_source(work_dir, pecl.name())?
+    };
+    apply_patches(&source_dir, patches)?;
+
+    // Repack the patched source in place of the original download, since `pecl install`
+    // happily accepts a local archive.
+    let source_name = source_dir.file_name().unwrap_or_default().to_string_lossy();
+    let mut command = Command::new("tar");
+    command.arg("-czf");
+    command.arg(archive.to_string_lossy());
+    command.arg("package.xml");
+    command.arg(source_name);
+    command.current_dir(work_dir);
+    command.wait()?;
+
+    Ok(())
+}
+
+/// Extracts the `source` of an extension built from its `source_url` (see
+/// [`download_pecl_source`]) and applies its patches, returning the directory to build in.
+pub fn extract_source(pecl: &Pecl, source: &PeclSource) -> Result<PathBuf> {
+    let dir = source.work_dir.join("src");
+    let created = command::skip_step(
+        format_args!("create {}", dir.display()),
+        format_args!("mkdir -p {}", command::quote_path(&dir)),
+    );
+    if !created {
+        fs::create_dir_all(&dir).context(Io { path: &dir })?;
+    }
+
+    // Release archives (e.g., GitHub's) hold a single top-level directory.
+    let mut command = Command::new("tar");
+    command.args(["-xzf", &source.target, "--strip-components=1", "-C"]);
+    command.arg(dir.to_string_lossy());
+    command.wait()?;
+
+    if let Some(patches) = pecl.patches() {
+        apply_patches(&dir, patches)?;
+    }
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn test_inline_patch_detection() {
+        assert!(is_inline_patch("--- a/foo.c\n+++ b/foo.c\n"));
+        assert!(!is_inline_patch("/build/patches/fix-musl.patch"));
+    }
+
+    #[test]
+    fn test_archive_checks() {
+        let archive = Path::new("/tmp/f1-ext-install-redis/redis-6.0.2.tgz");
+        assert_eq!(archive_version(archive, "redis"), Some("6.0.2"));
+        assert_eq!(archive_version(archive, "apcu"), None);
+
+        let digest = "01531a2be1e6ea5ef9d4d0e4e4a4ae0ff1c2fb5fb3d3a1e0e5b37b3ddf6d1f07";
+        let output = format!("{}  {}\n", digest, archive.display());
+        assert_eq!(parse_sha256sum(&output), Some(digest));
+        assert_eq!(parse_sha256sum("sha256sum: nope: No such file\n"), None);
+    }
+
+    #[test]
+    fn test_verify_archive() {
+        let dir = env::temp_dir().join(format!("f1-ext-install-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("redis-6.0.2.tgz");
+        fs::write(&archive, "hello").unwrap();
+
+        // The SHA-256 digest of "hello"
+        let digest = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let mut redis: Pecl = "redis".parse().unwrap();
+        redis.set_checksum("6.0.2", digest);
+        let verified = verify_archive(&redis, &archive);
+
+        redis.set_checksum("6.0.2", &digest.replace('2', "3"));
+        let mismatched = verify_archive(&redis, &archive);
+        fs::remove_dir_all(&dir).unwrap();
+
+        verified.unwrap();
+        assert_matches!(
+            mismatched,
+            Err(SourceError::ChecksumMismatch { actual, .. }) => {
+                assert_eq!(actual, digest);
+            }
+        );
+    }
+
+    #[test]
+    fn test_builtin_source_dir() {
+        assert_eq!(
+            builtin_source_dir("gd"),
+            PathBuf::from("/usr/src/php/ext/gd")
+        );
+    }
+
+    #[test]
+    fn test_pecl_build_dir() {
+        assert_eq!(
+            pecl_build_dir("redis"),
+            PathBuf::from("/tmp/pear/temp/redis")
+        );
+    }
+}
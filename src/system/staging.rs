@@ -0,0 +1,299 @@
+//! Staging of built extensions, so that an installation puts either all of them in place
+//! or none of them.
+//!
+//! Each module (and, for builtins, the ini file `docker-php-ext-install` writes) is moved
+//! into a `.f1-staging` directory next to it as soon as it's built, and any file it
+//! replaced is put back. The staged files are only moved into place once every build has
+//! succeeded; if the installation fails first, they are discarded. Until the installation
+//! [finishes](Staging::finish), the files they replaced are kept, so that a failure after
+//! the commit (e.g., while enabling the extensions) can still put everything back. PHP
+//! doesn't look in subdirectories of its extension or scan directories, so staged files
+//! are never loaded.
+
+use snafu::{ResultExt, Snafu};
+use std::{
+    fs, io, mem,
+    path::{Path, PathBuf},
+};
+
+/// The name of the directory files are staged in, next to where they belong.
+const STAGING_DIR: &str = ".f1-staging";
+
+/// Errors encountered while staging files.
+#[derive(Debug, Snafu)]
+pub enum StagingError {
+    /// A file couldn't be copied, moved, or removed.
+    #[snafu(display("Failed to stage {}: {}", path.display(), source))]
+    Io {
+        /// The underlying IO error
+        source: io::Error,
+        /// The file being staged
+        path: PathBuf,
+    },
+}
+
+/// Helper type for the result of staging.
+pub type Result<T> = std::result::Result<T, StagingError>;
+
+/// Returns where `target` is staged (with `suffix` appended to its name).
+fn staging_path(target: &Path, suffix: &str) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+
+    target
+        .parent()
+        .unwrap_or_else(|| Path::new("/"))
+        .join(STAGING_DIR)
+        .join(name)
+}
+
+/// Creates the staging directory for `path`.
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+}
+
+/// Files built by an installation and held back until it commits.
+#[derive(Debug, Default)]
+pub struct Staging {
+    /// Files being rebuilt, paired with copies of their original contents.
+    backups: Vec<(PathBuf, PathBuf)>,
+    /// Built files, paired with where they were staged.
+    staged: Vec<(PathBuf, PathBuf)>,
+    /// Committed files, paired with where the files they replaced (if any) were kept.
+    committed: Vec<(PathBuf, Option<PathBuf>)>,
+    /// The staging directories created.
+    dirs: Vec<PathBuf>,
+}
+
+impl Staging {
+    /// Records a staging directory for cleanup.
+    fn track(&mut self, path: &Path) {
+        if let Some(dir) = path.parent() {
+            if !self.dirs.iter().any(|tracked| tracked == dir) {
+                self.dirs.push(dir.to_path_buf());
+            }
+        }
+    }
+
+    /// Prepares for `target` to be rebuilt: if it exists, it's copied aside, so that it can
+    /// be put back when the new file is staged (or the build fails).
+    pub fn protect(&mut self, target: &Path) -> Result<()> {
+        if !target.exists() || self.backups.iter().any(|(path, _)| path == target) {
+            return Ok(());
+        }
+
+        let backup = staging_path(target, ".orig");
+        create_parent(&backup)
+            .and_then(|()| fs::copy(target, &backup))
+            .context(Io { path: target })?;
+        self.track(&backup);
+        self.backups.push((target.to_path_buf(), backup));
+
+        Ok(())
+    }
+
+    /// Moves the newly built `target` aside until [`commit`](Self::commit), putting back
+    /// the file it replaced, if any. A missing `target` is ignored.
+    pub fn stage(&mut self, target: &Path) -> Result<()> {
+        if !target.exists() {
+            return Ok(());
+        }
+
+        let staged = staging_path(target, "");
+        create_parent(&staged)
+            .and_then(|()| fs::rename(target, &staged))
+            .context(Io { path: target })?;
+        self.track(&staged);
+        self.staged.push((target.to_path_buf(), staged));
+
+        self.restore(target)
+    }
+
+    /// Puts back the original of `target`, if it was protected.
+    fn restore(&mut self, target: &Path) -> Result<()> {
+        if let Some(index) = self.backups.iter().position(|(path, _)| path == target) {
+            let (target, backup) = self.backups.remove(index);
+            fs::rename(&backup, &target).context(Io { path: &target })?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts back every protected file whose replacement wasn't staged (e.g., because its
+    /// build failed).
+    fn restore_all(&mut self) -> Result<()> {
+        let targets: Vec<_> = self.backups.iter().map(|(path, _)| path.clone()).collect();
+        for target in targets {
+            self.restore(&target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the staging directories and anything left in them.
+    fn clean(&mut self) {
+        for dir in self.dirs.drain(..) {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+
+    /// Moves every staged file into place, returning their paths. The files they replace
+    /// are kept until [`finish`](Self::finish), so that the commit can be undone.
+    pub fn commit(&mut self) -> Result<Vec<PathBuf>> {
+        self.restore_all()?;
+
+        let mut committed = Vec::new();
+        for (target, staged) in mem::take(&mut self.staged) {
+            let original = if target.exists() {
+                let original = staging_path(&target, ".orig");
+                fs::rename(&target, &original).context(Io { path: &target })?;
+                Some(original)
+            } else {
+                None
+            };
+            self.committed.push((target.clone(), original));
+
+            fs::rename(&staged, &target).context(Io { path: &target })?;
+            committed.push(target);
+        }
+
+        Ok(committed)
+    }
+
+    /// Takes back the built `target`: if it was committed, it's removed and the file it
+    /// replaced (if any) is put back; if it's still staged, it's discarded.
+    pub fn revert(&mut self, target: &Path) -> Result<()> {
+        if let Some(index) = self.committed.iter().position(|(path, _)| path == target) {
+            let (target, original) = self.committed.remove(index);
+            match fs::remove_file(&target) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    return Err(error).context(Io { path: &target })
+                }
+                _ => {}
+            }
+            if let Some(original) = original {
+                fs::rename(&original, &target).context(Io { path: &target })?;
+            }
+        } else if let Some(index) = self.staged.iter().position(|(path, _)| path == target) {
+            let (_, staged) = self.staged.remove(index);
+            let _ = fs::remove_file(staged);
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the committed files for good, removing the files they replaced.
+    pub fn finish(&mut self) {
+        self.committed.clear();
+        self.clean();
+    }
+}
+
+impl Drop for Staging {
+    /// Discards anything not finished, leaving the original files in place.
+    fn drop(&mut self) {
+        let committed: Vec<_> = self
+            .committed
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect();
+        for target in committed.iter().rev() {
+            let _ = self.revert(target);
+        }
+        let _ = self.restore_all();
+        self.clean();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit() {
+        let dir = std::env::temp_dir().join(format!("f1-staging-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let replaced = dir.join("redis.so");
+        let added = dir.join("apcu.so");
+        fs::write(&replaced, "old").unwrap();
+
+        let mut staging = Staging::default();
+        staging.protect(&replaced).unwrap();
+        fs::write(&replaced, "new").unwrap();
+        staging.stage(&replaced).unwrap();
+        fs::write(&added, "new").unwrap();
+        staging.stage(&added).unwrap();
+
+        // Nothing changes until the commit.
+        assert_eq!(fs::read_to_string(&replaced).unwrap(), "old");
+        assert!(!added.exists());
+
+        assert_eq!(
+            staging.commit().unwrap(),
+            vec![replaced.clone(), added.clone()]
+        );
+        assert_eq!(fs::read_to_string(&replaced).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&added).unwrap(), "new");
+
+        staging.finish();
+        assert!(!dir.join(STAGING_DIR).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_undo_commit() {
+        let dir = std::env::temp_dir().join(format!("f1-undo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let replaced = dir.join("redis.so");
+        let added = dir.join("apcu.so");
+        fs::write(&replaced, "old").unwrap();
+
+        {
+            let mut staging = Staging::default();
+            staging.protect(&replaced).unwrap();
+            fs::write(&replaced, "new").unwrap();
+            staging.stage(&replaced).unwrap();
+            fs::write(&added, "new").unwrap();
+            staging.stage(&added).unwrap();
+            staging.commit().unwrap();
+
+            // Enabling apcu failed, so it's taken back on its own.
+            staging.revert(&added).unwrap();
+            assert!(!added.exists());
+            assert_eq!(fs::read_to_string(&replaced).unwrap(), "new");
+        }
+
+        // The installation never finished, so the rest of the commit is undone too.
+        assert_eq!(fs::read_to_string(&replaced).unwrap(), "old");
+        assert!(!dir.join(STAGING_DIR).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_discard() {
+        let dir = std::env::temp_dir().join(format!("f1-discard-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let built = dir.join("redis.so");
+        let failed = dir.join("grpc.so");
+        fs::write(&failed, "old").unwrap();
+
+        {
+            let mut staging = Staging::default();
+            fs::write(&built, "new").unwrap();
+            staging.stage(&built).unwrap();
+            staging.protect(&failed).unwrap();
+            fs::write(&failed, "partial").unwrap();
+        }
+
+        assert!(!built.exists());
+        assert_eq!(fs::read_to_string(&failed).unwrap(), "old");
+        assert!(!dir.join(STAGING_DIR).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+//! Download and verification of vendor `.apk` files (e.g., Microsoft's ODBC driver).
+//!
+//! These packages aren't published in an Alpine repository, so `apk` can't check them
+//! against a key it trusts and they are installed with `--allow-untrusted`. Instead, each
+//! file must come with a detached OpenPGP signature (at its URL with `.apk` replaced by
+//! `.sig`, as Microsoft publishes them) made by the key the registry pins by fingerprint.
+
+use snafu::{ResultExt, Snafu};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::command::{self, Command, CommandError};
+use crate::extension::Extension;
+
+/// Directory holding the downloaded files, keys, and the `gpg` home directory.
+const WORK_DIR: &str = "/tmp/f1-package-files";
+
+/// Errors encountered while fetching vendor package files.
+#[derive(Debug, Snafu)]
+pub enum VendorError {
+    /// A download or `gpg` command failed.
+    #[snafu(display("{}", source))]
+    CommandFailed {
+        /// The underlying command error
+        source: CommandError,
+    },
+
+    /// A file or directory could not be accessed.
+    #[snafu(display("Failed to access {}: {}", path.display(), source))]
+    Io {
+        /// The underlying IO error
+        source: io::Error,
+        /// The path being accessed
+        path: PathBuf,
+    },
+
+    /// The registry lists package files for an extension without a key to check them.
+    #[snafu(display(
+        "{} lists package files but no package_key to verify them with",
+        extension
+    ))]
+    MissingKey {
+        /// The extension's key
+        extension: String,
+    },
+
+    /// The registry names a signing key for an extension, but no package files for this
+    /// platform.
+    #[snafu(display(
+        "{} needs vendor packages that aren't published for this architecture",
+        extension
+    ))]
+    Unavailable {
+        /// The extension's key
+        extension: String,
+    },
+
+    /// A package file URL doesn't end in `.apk`, so its signature can't be found.
+    #[snafu(display("{} isn't an .apk file", url))]
+    NotApk {
+        /// The package file's URL
+        url: String,
+    },
+
+    /// A package file's signature was made by a key other than the pinned one.
+    #[snafu(display("{} isn't signed by the key with fingerprint {}", url, fingerprint))]
+    WrongKey {
+        /// The package file's URL
+        url: String,
+        /// The fingerprint pinned in the registry
+        fingerprint: String,
+    },
+}
+
+impl From<CommandError> for VendorError {
+    fn from(source: CommandError) -> Self {
+        Self::CommandFailed { source }
+    }
+}
+
+/// Helper type for the result of fetching vendor package files.
+pub type Result<T> = std::result::Result<T, VendorError>;
+
+/// A vendor package file, and the key that must have signed it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageFile {
+    /// Where the `.apk` file is downloaded from.
+    pub url: String,
+    /// Where the signing key is downloaded from.
+    pub key_url: String,
+    /// The signing key's fingerprint.
+    pub fingerprint: String,
+}
+
+/// Collects the vendor package files needed by the provided list of dependencies, without
+/// duplicates. Fails if a dependency lists files without a key to verify them, or has a
+/// key but no files for this platform (e.g., an architecture the vendor doesn't support).
+pub fn collect_package_files(dependencies: &[Extension]) -> Result<Vec<PackageFile>> {
+    let mut files = Vec::new();
+
+    for dependency in dependencies {
+        let urls = dependency.package_urls().map_or(&[][..], Vec::as_slice);
+        let key = match dependency.package_key() {
+            Some(key) => key,
+            None if urls.is_empty() => continue,
+            None => {
+                return Err(VendorError::MissingKey {
+                    extension: dependency.key(),
+                })
+            }
+        };
+        if urls.is_empty() {
+            return Err(VendorError::Unavailable {
+                extension: dependency.key(),
+            });
+        }
+
+        for url in urls {
+            let file = PackageFile {
+                url: url.clone(),
+                key_url: key.url.clone(),
+                fingerprint: key.fingerprint.clone(),
+            };
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Returns the URL of the detached signature of the package file at `url`.
+fn signature_url(url: &str) -> Option<String> {
+    url.strip_suffix(".apk").map(|base| format!("{}.sig", base))
+}
+
+/// Determines if `gpg --status-fd 1 --verify` reported a good signature by the key with
+/// `fingerprint` (the primary key's fingerprint is the last field of `VALIDSIG`).
+fn is_signed_by(status: &str, fingerprint: &str) -> bool {
+    status.lines().any(|line| {
+        line.starts_with("[GNUPG:] VALIDSIG ")
+            && line
+                .split_whitespace()
+                .last()
+                .is_some_and(|primary| primary.eq_ignore_ascii_case(fingerprint))
+    })
+}
+
+/// Downloads `url` to `path`.
+fn download(url: &str, path: &Path) -> command::Result<()> {
+    let mut command = Command::new("curl");
+    command.args(["-fsSL", "-o"]);
+    command.arg(path.to_string_lossy());
+    command.arg(url);
+    command.downloads();
+    command.wait()
+}
+
+/// Downloads the given package files and checks their signatures, returning the paths of
+/// the verified `.apk` files. Needs `gpg`, which the registry lists as a build package.
+pub fn download_package_files(files: &[PackageFile]) -> Result<Vec<PathBuf>> {
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let work_dir = Path::new(WORK_DIR);
+    let home = work_dir.join("gnupg");
+    let created = command::skip_step(
+        format_args!("create an empty {}", work_dir.display()),
+        format_args!(
+            "rm -rf {dir} && mkdir -p -m 700 {home}",
+            dir = command::quote_path(work_dir),
+            home = command::quote_path(&home)
+        ),
+    );
+    if !created {
+        if work_dir.exists() {
+            fs::remove_dir_all(work_dir).context(Io { path: work_dir })?;
+        }
+        fs::create_dir_all(&home).context(Io { path: &home })?;
+    }
+
+    let home = home.to_string_lossy();
+    let mut key_urls: Vec<&str> = Vec::new();
+    let mut paths = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        if !key_urls.contains(&file.key_url.as_str()) {
+            let key = work_dir.join(format!("key-{}.asc", key_urls.len()));
+            download(&file.key_url, &key)?;
+
+            let mut command = Command::new("gpg");
+            command.args(["--homedir", &home, "--batch", "--import"]);
+            command.arg(key.to_string_lossy());
+            command.wait()?;
+
+            key_urls.push(&file.key_url);
+        }
+
+        let signature_url = signature_url(&file.url).ok_or_else(|| VendorError::NotApk {
+            url: file.url.clone(),
+        })?;
+        let path = work_dir.join(format!("package-{}.apk", index));
+        let signature = work_dir.join(format!("package-{}.sig", index));
+        download(&file.url, &path)?;
+        download(&signature_url, &signature)?;
+
+        let signature = signature.to_string_lossy();
+        let package = path.to_string_lossy();
+        let verify_args = [
+            "--homedir",
+            &home,
+            "--batch",
+            "--status-fd",
+            "1",
+            "--verify",
+            &signature,
+            &package,
+        ];
+        let verified = command::skip_step(
+            format_args!("verify {} was signed by {}", file.url, file.fingerprint),
+            format_args!(
+                r"gpg {} | grep -q '^\[GNUPG:\] VALIDSIG .* {}$'",
+                verify_args.map(command::quote).join(" "),
+                file.fingerprint
+            ),
+        );
+        if !verified {
+            let mut command = Command::new("gpg");
+            command.args(verify_args);
+            let status = command.stdout()?;
+            if !is_signed_by(&status, &file.fingerprint) {
+                return Err(VendorError::WrongKey {
+                    url: file.url.clone(),
+                    fingerprint: file.fingerprint.clone(),
+                });
+            }
+        }
+
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Removes the files left by [`download_package_files`].
+pub fn delete_package_files() -> Result<()> {
+    let work_dir = Path::new(WORK_DIR);
+    let removed = command::skip_step(
+        format_args!("remove {}", work_dir.display()),
+        format_args!("rm -rf {}", command::quote_path(work_dir)),
+    );
+    if removed {
+        return Ok(());
+    }
+
+    match fs::remove_dir_all(work_dir) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            Err(error).context(Io { path: work_dir })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+    use crate::system::Platform;
+
+    #[test]
+    fn test_signature_url() {
+        assert_eq!(
+            signature_url("https://example.com/msodbcsql18_18.3.2.1-1_amd64.apk").as_deref(),
+            Some("https://example.com/msodbcsql18_18.3.2.1-1_amd64.sig")
+        );
+        assert_eq!(signature_url("https://example.com/driver.tgz"), None);
+    }
+
+    #[test]
+    fn test_is_signed_by() {
+        let status = "[GNUPG:] GOODSIG EB3E94ADBE1229CF Microsoft (Release signing)\n\
+                      [GNUPG:] VALIDSIG 1234 2023-06-01 1685577600 0 4 0 1 8 00 \
+                      BC528686B50D79E339D3721CEB3E94ADBE1229CF\n";
+
+        assert!(is_signed_by(
+            status,
+            "bc528686b50d79e339d3721ceb3e94adbe1229cf"
+        ));
+        assert!(!is_signed_by(status, "1234"));
+        assert!(!is_signed_by(
+            "[GNUPG:] BADSIG EB3E94ADBE1229CF\n",
+            "EB3E94ADBE1229CF"
+        ));
+    }
+
+    #[test]
+    fn test_shared_package_files() {
+        let arm64 = Platform::default().with_arch(Some("aarch64"));
+        let mut extensions: Vec<Extension> = vec![
+            "pecl:pdo_sqlsrv".parse().unwrap(),
+            "pecl:sqlsrv".parse().unwrap(),
+        ];
+        for extension in &mut extensions {
+            extension.resolve(&arm64).unwrap();
+        }
+
+        let files = collect_package_files(&extensions).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].url.ends_with("_arm64.apk"));
+    }
+
+    #[test]
+    fn test_unsupported_architecture() {
+        let mut sqlsrv: Extension = "pecl:sqlsrv".parse().unwrap();
+        sqlsrv
+            .resolve(&Platform::default().with_arch(Some("s390x")))
+            .unwrap();
+
+        assert_matches!(
+            collect_package_files(&[sqlsrv]),
+            Err(VendorError::Unavailable { extension }) => {
+                assert_eq!(extension, "pecl:sqlsrv");
+            }
+        );
+    }
+}
@@ -33,6 +33,31 @@ fn test_gd_features() {
     }
 }
 
+#[test]
+fn test_gd_webp() {
+    // Test to assert that the optional WebP feature is enabled when requested
+
+    let dockerfile = indoc!(
+        r#"ARG PHP_VERSION
+           FROM php:${PHP_VERSION}-cli-alpine
+
+           COPY f1-ext-install /usr/bin/
+           RUN chmod +x /usr/bin/f1-ext-install
+
+           RUN f1-ext-install 'builtin:gd?with=webp' >/dev/null
+           RUN php -d assert.exception=1 -r 'assert(gd_info()["WebP Support"]);'
+        "#
+    );
+
+    let client = connect();
+
+    for &version in PHP_VERSIONS {
+        let tag = tag_for_test("gd-webp", "gd", version);
+
+        build_image(&client, dockerfile, &[("PHP_VERSION", version)], &tag);
+    }
+}
+
 const REGISTRY_DOCKERFILE: &str = indoc!(
     r#"ARG PHP_VERSION
        FROM php:${PHP_VERSION}-cli-alpine
@@ -48,11 +73,11 @@ const REGISTRY_DOCKERFILE: &str = indoc!(
 );
 
 /// Shorthand macro to define a test for a builtin from the registry. Takes an extension
-/// name (as a Rust identifier) as its only argument.
+/// name (as a Rust identifier) and, optionally, the PHP versions to test against.
 ///
 /// This macro exists for two reasons:
 /// 1. It abstracts away the boilerplate of setting up a test for a new builtin added to
-/// the internal registry and
+///    the internal registry and
 /// 2. It enables us to separate tests for each extension.
 ///
 /// The cargo test infrastructure gets somewhat cranky when we run a test for too long,
@@ -65,16 +90,22 @@ const REGISTRY_DOCKERFILE: &str = indoc!(
 /// ```
 ///
 /// The macro expands to a test function named `bz2` that installs `builtin:bz2` in a
-/// Docker build test.
+/// Docker build test. Use `define_registry_test!(ffi, versions = &["7.4"])` to skip PHP
+/// versions that lack the extension.
 macro_rules! define_registry_test {
     ($builtin:ident) => {
+        define_registry_test!($builtin, versions = PHP_VERSIONS);
+    };
+
+    // Limits the test to the given PHP versions (e.g., for extensions added in 7.4)
+    ($builtin:ident, versions = $versions:expr $(,)?) => {
         #[test]
         fn $builtin() {
             let client = connect();
 
             let builtin = stringify!($builtin);
 
-            for &version in PHP_VERSIONS {
+            for &version in $versions {
                 let tag = tag_for_test("builtin", builtin, version);
 
                 build_image(
@@ -90,13 +121,22 @@ macro_rules! define_registry_test {
 
 define_registry_test!(bz2);
 define_registry_test!(enchant);
+define_registry_test!(ffi, versions = &["7.4"]);
 define_registry_test!(gd);
 define_registry_test!(gettext);
 define_registry_test!(gmp);
 define_registry_test!(imap);
 define_registry_test!(intl);
 define_registry_test!(ldap);
+define_registry_test!(pdo_dblib);
+define_registry_test!(pdo_odbc);
+define_registry_test!(pdo_pgsql);
+define_registry_test!(pgsql);
 define_registry_test!(soap);
+define_registry_test!(sodium);
+define_registry_test!(tidy);
+define_registry_test!(xmlrpc);
+define_registry_test!(xsl);
 define_registry_test!(zip);
 
 const EXTERNAL_DOCKERFILE: &str = indoc!(
@@ -204,8 +244,7 @@ macro_rules! define_external_test {
 }
 
 define_external_test!(
-    ffi,
-    F1_BUILTIN_FFI_PACKAGES = "libffi-dev",
-    F1_BUILTIN_FFI_CONFIGURE_ARGS = "--with-ffi",
-    versions = &["7.4"]
+    pspell,
+    F1_BUILTIN_PSPELL_PACKAGES = "aspell-dev",
+    F1_BUILTIN_PSPELL_CONFIGURE_CMD = "--with-pspell",
 );
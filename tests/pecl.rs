@@ -21,7 +21,14 @@ const REGISTRY_DOCKERFILE: &str = indoc!(
 fn test_pecl_from_registry() {
     let client = connect();
 
-    let packages = &["imagick", "memcached"];
+    let packages = &[
+        "ds",
+        "excimer",
+        "igbinary",
+        "imagick",
+        "mailparse",
+        "memcached",
+    ];
 
     for &package in packages {
         for &version in PHP_VERSIONS {
@@ -79,3 +86,72 @@ fn test_external_pecl_config() {
         }
     }
 }
+
+#[test]
+fn test_redis_igbinary() {
+    // Test to assert that requesting a feature installs its requirements first and passes
+    // its configure options along
+
+    let dockerfile = indoc!(
+        r#"ARG PHP_VERSION
+           FROM php:${PHP_VERSION}-cli-alpine
+
+           COPY f1-ext-install /usr/bin/
+           RUN chmod +x /usr/bin/f1-ext-install
+
+           RUN f1-ext-install 'pecl:redis?with=igbinary' >/dev/null
+           RUN php -r "if (!defined('Redis::SERIALIZER_IGBINARY')) exit(1);"
+        "#
+    );
+
+    let client = connect();
+
+    // Current releases of redis require PHP 7.4
+    let tag = tag_for_test("pecl-features", "redis", "7.4");
+    build_image(&client, dockerfile, &[("PHP_VERSION", "7.4")], &tag);
+}
+
+#[test]
+fn test_event_after_sockets() {
+    // Test to assert that event's ini file is loaded after the sockets extension it
+    // links against
+
+    let dockerfile = indoc!(
+        r#"ARG PHP_VERSION
+           FROM php:${PHP_VERSION}-cli-alpine
+
+           COPY f1-ext-install /usr/bin/
+           RUN chmod +x /usr/bin/f1-ext-install
+
+           RUN f1-ext-install pecl:event >/dev/null
+           RUN php -r "exit(extension_loaded('event') ? 0 : 1);"
+        "#
+    );
+
+    let client = connect();
+
+    let tag = tag_for_test("pecl-ordering", "event", "8.1");
+    build_image(&client, dockerfile, &[("PHP_VERSION", "8.1")], &tag);
+}
+
+#[test]
+fn test_ds_pinned() {
+    // Test to assert that a registry entry still applies when the version is pinned
+
+    let dockerfile = indoc!(
+        r#"ARG PHP_VERSION
+           FROM php:${PHP_VERSION}-cli-alpine
+
+           COPY f1-ext-install /usr/bin/
+           RUN chmod +x /usr/bin/f1-ext-install
+
+           RUN f1-ext-install pecl:ds@1.4.0 >/dev/null
+           RUN php -r "exit(phpversion('ds') === '1.4.0' ? 0 : 1);"
+        "#
+    );
+
+    let client = connect();
+
+    let tag = tag_for_test("pecl-pinned", "ds", "7.4");
+    build_image(&client, dockerfile, &[("PHP_VERSION", "7.4")], &tag);
+}